@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
 use criterion::criterion_group;
@@ -11,8 +12,12 @@ use raxiom::ics::InitialConditionsPlugin;
 use raxiom::ics::MonteCarloSampler;
 use raxiom::parameters::DomainParameters;
 use raxiom::parameters::GravityParameters;
+use raxiom::parameters::MultipoleOrder;
+use raxiom::parameters::OpeningCriterion;
 use raxiom::parameters::PerformanceParameters;
+use raxiom::parameters::Periodicity;
 use raxiom::parameters::SimulationParameters;
+use raxiom::parameters::Softening;
 use raxiom::parameters::TimestepParameters;
 use raxiom::prelude::GravityPlugin;
 use raxiom::prelude::Simulation;
@@ -55,14 +60,23 @@ fn setup_gravity_sim(num_particles: usize, opening_angle: Dimensionless) -> Simu
         .add_parameters_explicitly(SimulationBox::cube_from_side_length(Length::meters(100.0)))
         .add_parameters_explicitly(GravityParameters {
             softening_length: Length::zero(),
+            softening: Softening::Plummer,
+            species_softening_lengths: HashMap::default(),
             opening_angle,
+            opening_criterion: OpeningCriterion::Geometric,
+            relative_tolerance: Dimensionless::dimensionless(0.0025),
+            multipole_order: MultipoleOrder::Quadrupole,
+            periodicity: Periodicity::NearestImage,
         })
         .add_parameters_explicitly(SimulationParameters {
             final_time: Some(Time::seconds(10e-3)),
+            max_wall_clock_time: None,
         })
         .add_parameters_explicitly(TimestepParameters {
             max_timestep: Time::seconds(1e-3),
             num_levels: 1,
+            courant_factor: 0.15,
+            acceleration_factor: 0.025,
         });
     SimulationBuilder::bench()
         .build_with_sim(&mut sim)
@@ -9,6 +9,7 @@ use criterion::Throughput;
 use raxiom::ics::ConstantDensity;
 use raxiom::ics::InitialConditionsPlugin;
 use raxiom::ics::MonteCarloSampler;
+use raxiom::parameters::ArtificialViscosity;
 use raxiom::parameters::DomainParameters;
 use raxiom::parameters::HydrodynamicsParameters;
 use raxiom::parameters::InitialGasEnergy;
@@ -16,6 +17,8 @@ use raxiom::parameters::PerformanceParameters;
 use raxiom::parameters::QuadTreeConfig;
 use raxiom::parameters::SimulationBox;
 use raxiom::parameters::SimulationParameters;
+use raxiom::parameters::SmoothingLengthIteration;
+use raxiom::parameters::SphKernel;
 use raxiom::parameters::TimestepParameters;
 use raxiom::prelude::HydrodynamicsPlugin;
 use raxiom::prelude::Simulation;
@@ -64,13 +67,19 @@ fn setup_hydro_sim(num_particles: usize) -> Simulation {
                 molecular_weight: Dimensionless::dimensionless(1.0),
             },
             tree: QuadTreeConfig::default(),
+            viscosity: ArtificialViscosity::default(),
+            smoothing_length_iteration: SmoothingLengthIteration::default(),
+            kernel: SphKernel::default(),
         })
         .add_parameters_explicitly(SimulationParameters {
             final_time: Some(Time::seconds(10e-3)),
+            max_wall_clock_time: None,
         })
         .add_parameters_explicitly(TimestepParameters {
             max_timestep: Time::seconds(1e-3),
             num_levels: 1,
+            courant_factor: 0.15,
+            acceleration_factor: 0.025,
         });
     SimulationBuilder::bench()
         .build_with_sim(&mut sim)
@@ -10,6 +10,7 @@ use raxiom::prelude::SimulationBox;
 use raxiom::quadtree::QuadTreeConfig;
 use raxiom::units::Length;
 use raxiom::units::VecLength;
+use raxiom::units::VecVelocity;
 
 fn quadtree_radius_search(quadtree: &QuadTree, box_size: &SimulationBox) {
     quadtree.get_particles_in_radius(
@@ -39,6 +40,7 @@ fn get_quadtree_and_box_size(min_depth: usize) -> (QuadTree, SimulationBox) {
                     entity: Entity::from_raw(0),
                     pos,
                     smoothing_length: Length::meters(0.0),
+                    velocity: VecVelocity::zero(),
                 })
                 .collect(),
             &extent,
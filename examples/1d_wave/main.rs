@@ -11,9 +11,12 @@ use raxiom::ics::DensityProfile;
 use raxiom::ics::InitialConditionsPlugin;
 use raxiom::ics::IntegerTuple;
 use raxiom::ics::RegularSampler;
+use raxiom::parameters::ArtificialViscosity;
 use raxiom::parameters::HydrodynamicsParameters;
 use raxiom::parameters::InitialGasEnergy;
 use raxiom::parameters::SimulationParameters;
+use raxiom::parameters::SmoothingLengthIteration;
+use raxiom::parameters::SphKernel;
 use raxiom::parameters::TimestepParameters;
 use raxiom::prelude::*;
 use raxiom::quadtree::QuadTreeConfig;
@@ -69,14 +72,20 @@ fn build_sim(num_particles: usize) -> Simulation {
         num_smoothing_neighbours: 20,
         initial_gas_energy: InitialGasEnergy::Explicit,
         tree: QuadTreeConfig::default(),
+        viscosity: ArtificialViscosity::default(),
+        smoothing_length_iteration: SmoothingLengthIteration::default(),
+        kernel: SphKernel::default(),
     })
     .add_parameters_explicitly(SimulationBox::from(Extent::cube_from_side_length(BOX_SIZE)))
     .add_parameters_explicitly(SimulationParameters {
         final_time: Some(crossing_time),
+        max_wall_clock_time: None,
     })
     .add_parameters_explicitly(TimestepParameters {
         num_levels: 1,
         max_timestep,
+        courant_factor: 0.15,
+        acceleration_factor: 0.025,
     });
     SimulationBuilder::new()
         .read_initial_conditions(false)
@@ -115,6 +124,7 @@ fn initialize_energy_system(
             components::Density::default(),
             components::SmoothingLength::default(),
             components::InternalEnergy(energy * **mass),
+            components::BalsaraFactor::default(),
         ));
     }
 }
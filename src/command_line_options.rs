@@ -56,4 +56,7 @@ pub struct CommandLineOptions {
     pub verbosity: usize,
     #[clap(long)]
     pub num_worker_threads: Option<usize>,
+    /// Resume the simulation from the restart files in the output directory.
+    #[clap(long)]
+    pub restart: bool,
 }
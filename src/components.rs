@@ -4,12 +4,19 @@ use bevy::prelude::DerefMut;
 use derive_more::From;
 use hdf5::H5Type;
 use mpi::traits::Equivalence;
+use serde::Deserialize;
+use serde::Serialize;
 
 pub use crate::hydrodynamics::hydro_components::*;
+use crate::io::to_dataset::ToDataset;
 use crate::named::Named;
+use crate::prelude::Float;
+use crate::units::Dimension;
 use crate::units::Time;
+use crate::units::VecAcceleration;
 use crate::units::VecLength;
 use crate::units::VecVelocity;
+use crate::units::NONE;
 
 #[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Named)]
 #[name = "position"]
@@ -26,7 +33,173 @@ pub struct Mass(pub crate::units::Mass);
 #[repr(transparent)]
 pub struct Velocity(pub VecVelocity);
 
+#[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Named)]
+#[name = "acceleration"]
+#[repr(transparent)]
+pub struct Acceleration(pub VecAcceleration);
+
 #[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Named)]
 #[name = "timestep"]
 #[repr(transparent)]
 pub struct Timestep(pub Time);
+
+/// The acceleration of a particle due to gravity alone, as of
+/// the last force calculation of the particle.
+#[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Named)]
+#[name = "gravitational_acceleration"]
+#[repr(transparent)]
+pub struct GravitationalAcceleration(pub VecAcceleration);
+
+/// An estimate of the computational cost of the last force
+/// calculation of a particle, given by its number of interactions
+/// with other particles and tree nodes. Used for load balancing.
+#[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Named)]
+#[name = "work"]
+#[repr(transparent)]
+pub struct Work(pub Float);
+
+/// The gravitational softening length of a particle.
+#[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Named)]
+#[name = "softening_length"]
+#[repr(transparent)]
+pub struct SofteningLength(pub crate::units::Length);
+
+/// A globally unique identifier of a particle which, unlike its
+/// [Entity](bevy::prelude::Entity), stays the same when the particle
+/// moves to another rank and across snapshots and restarts.
+#[derive(
+    H5Type,
+    Component,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Equivalence,
+    Deref,
+    DerefMut,
+    From,
+    Named,
+)]
+#[name = "particle_id"]
+#[repr(transparent)]
+pub struct ParticleId(pub u64);
+
+impl ToDataset for ParticleId {
+    fn dimension() -> Dimension {
+        NONE
+    }
+
+    fn convert_base_units(self, _factor: f64) -> Self {
+        self
+    }
+}
+
+/// The number of different particle species.
+pub const NUM_SPECIES: usize = 4;
+
+/// The species of a particle. Every particle additionally carries
+/// the corresponding marker component ([Gas], [DarkMatter], [Star]
+/// or [Sink]) which systems can filter for. Particles which are
+/// spawned without a species are gas particles.
+#[derive(
+    H5Type,
+    Component,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Named,
+    Serialize,
+    Deserialize,
+)]
+#[name = "species"]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Species {
+    /// Collisional particles which take part in the hydrodynamics.
+    #[default]
+    Gas = 0,
+    /// Collisionless particles which only interact gravitationally.
+    DarkMatter = 1,
+    /// Collisionless star particles.
+    Star = 2,
+    /// Sink particles which accrete the surrounding gas.
+    Sink = 3,
+}
+
+// Safety: Species is represented by a u8
+unsafe impl Equivalence for Species {
+    type Out = <u8 as Equivalence>::Out;
+
+    fn equivalent_datatype() -> Self::Out {
+        u8::equivalent_datatype()
+    }
+}
+
+impl Species {
+    pub const ALL: [Species; NUM_SPECIES] = [
+        Species::Gas,
+        Species::DarkMatter,
+        Species::Star,
+        Species::Sink,
+    ];
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// The name of the group containing the particles of this
+    /// species in snapshot files.
+    pub fn group_name(&self) -> &'static str {
+        match self {
+            Species::Gas => "gas",
+            Species::DarkMatter => "dark_matter",
+            Species::Star => "star",
+            Species::Sink => "sink",
+        }
+    }
+}
+
+/// Marks gas particles. Only gas particles take part in
+/// the hydrodynamics.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct Gas;
+
+/// Marks dark matter particles.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct DarkMatter;
+
+/// Marks star particles.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct Star;
+
+/// Marks sink particles.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct Sink;
+
+/// The components which only gas particles carry. They are removed
+/// when a gas particle is turned into a particle of another species.
+pub type GasComponents = (
+    Gas,
+    SmoothingLength,
+    Density,
+    Pressure,
+    InternalEnergy,
+    BalsaraFactor,
+    SignalVelocity,
+    SofteningCorrection,
+    Abundances,
+);
+
+/// The time at which a star particle formed from a gas particle.
+#[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Named)]
+#[name = "stellar_formation_time"]
+#[repr(transparent)]
+pub struct StellarFormationTime(pub Time);
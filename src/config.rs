@@ -4,3 +4,12 @@ pub const NUM_DIMENSIONS: usize = 2;
 pub const NUM_DIMENSIONS: usize = 3;
 
 pub const TWO_TO_NUM_DIMENSIONS: usize = 2i32.pow(NUM_DIMENSIONS as u32) as usize;
+
+/// Selects the value which corresponds to the number of dimensions.
+pub const fn per_dimension<T: Copy>(value_2d: T, value_3d: T) -> T {
+    if NUM_DIMENSIONS == 2 {
+        value_2d
+    } else {
+        value_3d
+    }
+}
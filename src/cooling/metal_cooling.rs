@@ -0,0 +1,83 @@
+use hdf5::File;
+
+use super::parameters::MetalCoolingParameters;
+use crate::prelude::Float;
+
+/// The tabulated metal-line cooling rate, interpolated linearly
+/// in log T - log Lambda.
+#[derive(Clone)]
+pub(super) struct MetalCoolingTable {
+    log_temperatures: Vec<Float>,
+    log_cooling_rates: Vec<Float>,
+    metallicity: Float,
+}
+
+impl MetalCoolingTable {
+    pub fn read(parameters: &MetalCoolingParameters) -> Self {
+        let path = &parameters.path;
+        let file = File::open(path)
+            .unwrap_or_else(|e| panic!("Failed to open metal cooling table: {path:?}, {e:?}"));
+        let read = |name: &str| -> Vec<Float> {
+            file.dataset(name)
+                .and_then(|dataset| dataset.read_raw())
+                .unwrap_or_else(|e| panic!("Failed to read dataset {name} from {path:?}: {e:?}"))
+        };
+        let temperatures = read("temperature");
+        let cooling_rates = read("cooling_rate");
+        Self::new(&temperatures, &cooling_rates, parameters.metallicity)
+    }
+
+    fn new(temperatures: &[Float], cooling_rates: &[Float], metallicity: Float) -> Self {
+        assert_eq!(
+            temperatures.len(),
+            cooling_rates.len(),
+            "Different lengths of temperatures and cooling rates in metal cooling table."
+        );
+        assert!(
+            temperatures.windows(2).all(|w| w[0] < w[1]),
+            "Temperatures in metal cooling table need to be in ascending order."
+        );
+        Self {
+            log_temperatures: temperatures.iter().map(|t| t.log10()).collect(),
+            log_cooling_rates: cooling_rates.iter().map(|rate| rate.log10()).collect(),
+            metallicity,
+        }
+    }
+
+    /// The cooling rate Lambda / n_H^2 in erg cm^3 / s. Gas below the
+    /// lowest temperature in the table does not cool via metal lines,
+    /// above the highest temperature the rate is kept constant.
+    pub fn cooling_rate(&self, temperature: Float) -> Float {
+        let log_t = temperature.log10();
+        let index = self.log_temperatures.partition_point(|t| *t <= log_t);
+        if index == 0 {
+            return 0.0;
+        }
+        if index == self.log_temperatures.len() {
+            return self.metallicity * Float::powf(10.0, *self.log_cooling_rates.last().unwrap());
+        }
+        let (t0, t1) = (
+            self.log_temperatures[index - 1],
+            self.log_temperatures[index],
+        );
+        let (r0, r1) = (
+            self.log_cooling_rates[index - 1],
+            self.log_cooling_rates[index],
+        );
+        self.metallicity * Float::powf(10.0, r0 + (r1 - r0) * (log_t - t0) / (t1 - t0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetalCoolingTable;
+
+    #[test]
+    fn interpolate_metal_cooling_table() {
+        let table = MetalCoolingTable::new(&[1e4, 1e6], &[1e-22, 1e-24], 0.5);
+        assert_eq!(table.cooling_rate(1e3), 0.0);
+        assert!((table.cooling_rate(1e4) / 0.5e-22 - 1.0).abs() < 1e-10);
+        assert!((table.cooling_rate(1e5) / 0.5e-23 - 1.0).abs() < 1e-10);
+        assert!((table.cooling_rate(1e8) / 0.5e-24 - 1.0).abs() < 1e-10);
+    }
+}
@@ -0,0 +1,401 @@
+mod metal_cooling;
+mod parameters;
+mod primordial;
+
+use bevy::prelude::*;
+
+use self::metal_cooling::MetalCoolingTable;
+pub use self::parameters::Chemistry;
+pub use self::parameters::CoolingParameters;
+pub use self::parameters::MetalCoolingParameters;
+use self::primordial::cooling_rate;
+use self::primordial::RateCoefficients;
+use crate::components;
+use crate::components::Abundances;
+use crate::components::InternalEnergy;
+use crate::components::Mass;
+use crate::components::Species;
+use crate::components::Timestep;
+use crate::hydrodynamics::compute_energy_change_system;
+use crate::hydrodynamics::HydrodynamicsParameters;
+use crate::hydrodynamics::InitialGasEnergy;
+use crate::io::input::ComponentInput;
+use crate::named::Named;
+use crate::performance_parameters::PerformanceParameters;
+use crate::prelude::ActiveParticles;
+use crate::prelude::Float;
+use crate::prelude::Particles;
+use crate::prelude::SimulationStartupStages;
+use crate::simulation::RaxiomPlugin;
+use crate::simulation::Simulation;
+use crate::simulation_plugin::SimulationStages;
+use crate::units;
+use crate::units::Dimensionless;
+use crate::units::EnergyPerMass;
+use crate::units::Temperature;
+use crate::units::PROTON_MASS;
+
+/// The relative tolerance of the iterations which determine the
+/// temperature and the cooled internal energy of a particle.
+const TOLERANCE: Float = 1e-6;
+const MAX_ITERATIONS: usize = 100;
+
+/// The non-equilibrium chemistry is subcycled such that the electron
+/// abundance changes by at most this fraction of its value (or of
+/// [ELECTRON_ABUNDANCE_SCALE] in almost neutral gas) per substep.
+const MAX_ELECTRON_CHANGE: Float = 0.1;
+const ELECTRON_ABUNDANCE_SCALE: Float = 1e-3;
+const MAX_SUBSTEPS: usize = 1000;
+
+/// Radiative cooling of the gas. The cooling rate is given by
+/// the cooling function of a primordial hydrogen and helium gas
+/// (Katz, Weinberg & Hernquist 1996) plus optional tabulated
+/// metal-line cooling. The ionization state of every gas particle
+/// is stored in its [Abundances](crate::components::Abundances),
+/// which are either kept in collisional ionization equilibrium or
+/// evolved with a non-equilibrium chemistry network, depending on
+/// the [Chemistry](crate::parameters::Chemistry) parameter.
+/// Gas particles without abundances in the initial conditions start
+/// out in equilibrium with their initial temperature.
+/// The internal energy of every active gas particle is integrated
+/// implicitly over its timestep, after the adiabatic energy change
+/// of the [HydrodynamicsPlugin](crate::prelude::HydrodynamicsPlugin),
+/// which needs to be added as well.
+#[derive(Named)]
+pub struct CoolingPlugin;
+
+impl RaxiomPlugin for CoolingPlugin {
+    fn build_everywhere(&self, sim: &mut Simulation) {
+        let parameters = sim
+            .add_parameter_type_and_get_result::<CoolingParameters>()
+            .clone();
+        sim.insert_resource(CoolingFunction::new(&parameters))
+            .add_component::<Abundances>(ComponentInput::Optional)
+            .add_system_to_stage(
+                SimulationStages::ForceCalculation,
+                cooling_system.after(compute_energy_change_system),
+            );
+        // When restarting, the abundances are read from the
+        // restart files during InsertComponents.
+        if !sim.restart {
+            sim.add_startup_system_to_stage(
+                SimulationStartupStages::InsertComponents,
+                insert_abundances_system,
+            );
+        }
+    }
+}
+
+#[derive(Resource)]
+struct CoolingFunction {
+    hydrogen_mass_fraction: Float,
+    energy_floor: EnergyPerMass,
+    metal_cooling: Option<MetalCoolingTable>,
+    chemistry: Chemistry,
+}
+
+impl CoolingFunction {
+    fn new(parameters: &CoolingParameters) -> Self {
+        let hydrogen_mass_fraction = parameters.hydrogen_mass_fraction;
+        let abundances = Abundances::equilibrium(
+            parameters.temperature_floor.in_kelvins(),
+            hydrogen_mass_fraction,
+        );
+        Self {
+            hydrogen_mass_fraction,
+            energy_floor: parameters
+                .temperature_floor
+                .to_internal_energy(abundances.molecular_weight()),
+            metal_cooling: parameters
+                .metal_cooling
+                .as_ref()
+                .map(MetalCoolingTable::read),
+            chemistry: parameters.chemistry.clone(),
+        }
+    }
+
+    fn equilibrium(&self, temperature: Temperature) -> Abundances {
+        Abundances::equilibrium(temperature.in_kelvins(), self.hydrogen_mass_fraction)
+    }
+
+    /// The temperature (in Kelvin) and the equilibrium abundances
+    /// of gas with the given internal energy. Since the molecular
+    /// weight depends on the ionization state, which in turn depends
+    /// on the temperature, both are determined iteratively.
+    fn equilibrium_state(&self, energy: EnergyPerMass) -> (Float, Abundances) {
+        let x = self.hydrogen_mass_fraction;
+        let temperature = |molecular_weight: Float| {
+            energy
+                .to_temperature(Dimensionless::dimensionless(molecular_weight))
+                .in_kelvins()
+        };
+        let mut molecular_weight = 1.0;
+        for _ in 0..MAX_ITERATIONS {
+            let abundances = Abundances::equilibrium(temperature(molecular_weight), x);
+            let new_molecular_weight = abundances.molecular_weight().value();
+            if (new_molecular_weight - molecular_weight).abs() < TOLERANCE * molecular_weight {
+                return (temperature(molecular_weight), abundances);
+            }
+            // Damp the iteration to prevent oscillations close
+            // to the ionization temperatures.
+            molecular_weight = 0.5 * (molecular_weight + new_molecular_weight);
+        }
+        let temperature = temperature(molecular_weight);
+        (temperature, Abundances::equilibrium(temperature, x))
+    }
+
+    /// The cooling rate Lambda / n_H^2 in erg cm^3 / s.
+    fn cooling_rate(&self, temperature: Float, abundances: &Abundances) -> Float {
+        let metal_cooling_rate = self
+            .metal_cooling
+            .as_ref()
+            .map(|table| table.cooling_rate(temperature))
+            .unwrap_or(0.0);
+        cooling_rate(temperature, abundances) + metal_cooling_rate
+    }
+
+    /// Integrates the internal energy (per unit mass) and the
+    /// abundances of gas at the given density over the timestep.
+    /// With non-equilibrium chemistry, the abundances and the energy
+    /// are evolved alternately in substeps.
+    fn cool(
+        &self,
+        energy: EnergyPerMass,
+        density: units::Density,
+        timestep: units::Time,
+        abundances: &mut Abundances,
+    ) -> EnergyPerMass {
+        // In cgs units
+        let num_density_hydrogen =
+            (density * self.hydrogen_mass_fraction / PROTON_MASS).value_unchecked() * 1e-6;
+        let density_cgs = density.value_unchecked() * 1e-3;
+        // The energy loss rate in erg / (g s) is Lambda n_H^2 / rho,
+        // 1 erg / g = 1e-4 J / kg
+        let loss_factor = num_density_hydrogen.powi(2) / density_cgs * 1e-4;
+        match self.chemistry {
+            Chemistry::Equilibrium => {
+                let energy =
+                    self.integrate(energy, loss_factor * timestep.in_seconds(), |energy| {
+                        let (temperature, abundances) = self.equilibrium_state(energy);
+                        self.cooling_rate(temperature, &abundances)
+                    });
+                *abundances = self.equilibrium_state(energy).1;
+                energy
+            }
+            Chemistry::NonEquilibrium => {
+                let mut energy = energy;
+                let mut remaining = timestep.in_seconds();
+                let mut num_substeps = 0;
+                while remaining > 0.0 {
+                    num_substeps += 1;
+                    let rates = RateCoefficients::new(temperature(energy, abundances));
+                    let electron_rate = num_density_hydrogen * abundances.electron_rate(&rates);
+                    let mut dt = remaining;
+                    if num_substeps < MAX_SUBSTEPS && electron_rate != 0.0 {
+                        let electron_change =
+                            MAX_ELECTRON_CHANGE * abundances.e.max(ELECTRON_ABUNDANCE_SCALE);
+                        dt = dt.min(electron_change / electron_rate.abs());
+                    }
+                    abundances.evolve(&rates, num_density_hydrogen, dt);
+                    energy = self.integrate(energy, loss_factor * dt, |energy| {
+                        self.cooling_rate(temperature(energy, abundances), abundances)
+                    });
+                    remaining -= dt;
+                }
+                energy
+            }
+        }
+    }
+
+    /// Solves the implicit Euler step
+    /// u = u_0 - factor * rate(u)
+    /// for the internal energy u by bisection between the energy at
+    /// the temperature floor and the initial energy u_0, where rate
+    /// is the cooling rate Lambda / n_H^2.
+    fn integrate(
+        &self,
+        energy: EnergyPerMass,
+        factor: Float,
+        rate: impl Fn(EnergyPerMass) -> Float,
+    ) -> EnergyPerMass {
+        let floor = self.energy_floor.in_joules_per_kilogram();
+        let initial = energy.in_joules_per_kilogram();
+        if initial <= floor {
+            return self.energy_floor;
+        }
+        let residual = |energy: Float| {
+            energy - initial + factor * rate(EnergyPerMass::joules_per_kilogram(energy))
+        };
+        if residual(floor) >= 0.0 {
+            return self.energy_floor;
+        }
+        let mut lower = floor;
+        let mut upper = initial;
+        for _ in 0..MAX_ITERATIONS {
+            let mid = 0.5 * (lower + upper);
+            if residual(mid) < 0.0 {
+                lower = mid;
+            } else {
+                upper = mid;
+            }
+            if upper - lower < TOLERANCE * upper {
+                break;
+            }
+        }
+        EnergyPerMass::joules_per_kilogram(0.5 * (lower + upper))
+    }
+}
+
+/// The temperature in Kelvin of gas with the given internal
+/// energy per unit mass and ionization state.
+fn temperature(energy: EnergyPerMass, abundances: &Abundances) -> Float {
+    energy
+        .to_temperature(abundances.molecular_weight())
+        .in_kelvins()
+}
+
+/// Gives every gas particle without abundances the abundances in
+/// collisional ionization equilibrium at its initial temperature.
+fn insert_abundances_system(
+    mut commands: Commands,
+    particles: Particles<
+        (Entity, &Mass, Option<&InternalEnergy>, Option<&Species>),
+        Without<Abundances>,
+    >,
+    cooling_function: Res<CoolingFunction>,
+    hydro_parameters: Res<HydrodynamicsParameters>,
+) {
+    for (entity, mass, internal_energy, species) in particles.iter() {
+        // The species markers are not inserted yet at this point.
+        if species.copied().unwrap_or_default() != Species::Gas {
+            continue;
+        }
+        let abundances = match internal_energy {
+            Some(internal_energy) => {
+                cooling_function
+                    .equilibrium_state(**internal_energy / **mass)
+                    .1
+            }
+            None => match hydro_parameters.initial_gas_energy {
+                InitialGasEnergy::TemperatureAndMolecularWeight { temperature, .. }
+                | InitialGasEnergy::Temperature { temperature } => {
+                    cooling_function.equilibrium(temperature)
+                }
+                InitialGasEnergy::Energy(energy) => cooling_function.equilibrium_state(energy).1,
+                InitialGasEnergy::Explicit => {
+                    panic!("Cannot determine the initial abundances of gas particles without an internal energy!")
+                }
+            },
+        };
+        commands.entity(entity).insert(abundances);
+    }
+}
+
+fn cooling_system(
+    mut particles: ActiveParticles<(
+        &mut InternalEnergy,
+        &mut Abundances,
+        &Mass,
+        &components::Density,
+        &Timestep,
+    )>,
+    cooling_function: Res<CoolingFunction>,
+    performance_parameters: Res<PerformanceParameters>,
+) {
+    particles.par_for_each_mut(
+        performance_parameters.batch_size(),
+        |(mut energy, mut abundances, mass, density, timestep)| {
+            let specific_energy = **energy / **mass;
+            **energy =
+                cooling_function.cool(specific_energy, **density, **timestep, &mut abundances)
+                    * **mass;
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::temperature;
+    use super::Chemistry;
+    use super::CoolingFunction;
+    use super::CoolingParameters;
+    use crate::units::Dimensionless;
+    use crate::units::Temperature;
+    use crate::units::Time;
+    use crate::units::Volume;
+    use crate::units::PROTON_MASS;
+
+    fn cooling_function(chemistry: Chemistry) -> CoolingFunction {
+        CoolingFunction::new(&CoolingParameters {
+            hydrogen_mass_fraction: 0.76,
+            temperature_floor: Temperature::kelvins(100.0),
+            metal_cooling: None,
+            chemistry,
+        })
+    }
+
+    #[test]
+    fn temperature_of_ionized_gas() {
+        let cooling_function = cooling_function(Chemistry::Equilibrium);
+        let energy =
+            Temperature::kelvins(1e7).to_internal_energy(Dimensionless::dimensionless(0.588));
+        let (temperature, _) = cooling_function.equilibrium_state(energy);
+        assert!((temperature / 1e7 - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn hot_gas_cools() {
+        let cooling_function = cooling_function(Chemistry::Equilibrium);
+        // One proton mass per cubic centimeter
+        let density = PROTON_MASS / Volume::cubic_meters(1e-6);
+        let initial =
+            Temperature::kelvins(1e6).to_internal_energy(Dimensionless::dimensionless(0.588));
+        let mut abundances = cooling_function.equilibrium(Temperature::kelvins(1e6));
+        let mut energy = initial;
+        for _ in 0..100 {
+            let new_energy =
+                cooling_function.cool(energy, density, Time::years(1e5), &mut abundances);
+            assert!(new_energy <= energy);
+            energy = new_energy;
+        }
+        assert!(energy < initial * 0.5);
+        // The implicit integration remains stable for very long
+        // timesteps. Without metals, the gas stops cooling once
+        // the hydrogen recombines.
+        let energy = cooling_function.cool(initial, density, Time::years(1e12), &mut abundances);
+        let temperature = temperature(energy, &abundances);
+        assert!(5e3 < temperature && temperature < 1.5e4);
+        assert_eq!(abundances, cooling_function.equilibrium_state(energy).1);
+    }
+
+    #[test]
+    fn gas_does_not_cool_below_floor() {
+        let cooling_function = cooling_function(Chemistry::Equilibrium);
+        let density = PROTON_MASS / Volume::cubic_meters(1e-6);
+        let energy =
+            Temperature::kelvins(50.0).to_internal_energy(Dimensionless::dimensionless(1.22));
+        let mut abundances = cooling_function.equilibrium(Temperature::kelvins(50.0));
+        let energy = cooling_function.cool(energy, density, Time::years(1e5), &mut abundances);
+        assert!(energy == cooling_function.energy_floor);
+    }
+
+    #[test]
+    fn rapidly_cooling_gas_stays_ionized() {
+        let cooling_function = cooling_function(Chemistry::NonEquilibrium);
+        // Dense gas cools faster than it recombines.
+        let density = PROTON_MASS / Volume::cubic_meters(1e-10);
+        let mut abundances = cooling_function.equilibrium(Temperature::kelvins(1e6));
+        let mut energy =
+            Temperature::kelvins(1e6).to_internal_energy(abundances.molecular_weight());
+        for _ in 0..1000 {
+            energy = cooling_function.cool(energy, density, Time::years(1.0), &mut abundances);
+            let hydrogen = abundances.hi + abundances.hii;
+            assert!((hydrogen - 1.0).abs() < 1e-10);
+            assert!(abundances.hi >= 0.0 && abundances.hii >= 0.0);
+        }
+        let temperature = temperature(energy, &abundances);
+        assert!(temperature < 1e4);
+        let equilibrium = cooling_function.equilibrium(Temperature::kelvins(temperature));
+        assert!(abundances.e > 10.0 * equilibrium.e);
+    }
+}
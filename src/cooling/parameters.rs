@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use derive_custom::raxiom_parameters;
+
+use crate::prelude::Float;
+use crate::units::Temperature;
+
+/// Parameters for radiative cooling. Only needed if the
+/// [CoolingPlugin](crate::prelude::CoolingPlugin) is added
+/// to the simulation.
+#[raxiom_parameters("cooling")]
+pub struct CoolingParameters {
+    /// The mass fraction of hydrogen in the gas. The rest of the
+    /// gas is assumed to be helium.
+    #[serde(default = "default_hydrogen_mass_fraction")]
+    pub hydrogen_mass_fraction: Float,
+    /// The gas does not cool below this temperature.
+    #[serde(default = "default_temperature_floor")]
+    pub temperature_floor: Temperature,
+    /// Tabulated metal-line cooling which is added to the
+    /// primordial cooling. See
+    /// [MetalCoolingParameters](crate::parameters::MetalCoolingParameters)
+    #[serde(default)]
+    pub metal_cooling: Option<MetalCoolingParameters>,
+    /// How the ionization state of the gas is determined. See
+    /// [Chemistry](crate::parameters::Chemistry)
+    #[serde(default)]
+    pub chemistry: Chemistry,
+}
+
+/// How the ionization state of the gas, which determines its
+/// cooling rate and molecular weight, is determined.
+#[derive(Default)]
+#[raxiom_parameters]
+pub enum Chemistry {
+    /// The gas is in collisional ionization equilibrium at its
+    /// current temperature (default).
+    #[default]
+    Equilibrium,
+    /// The abundances of every gas particle are evolved with the
+    /// rate equations of the hydrogen and helium network, which
+    /// captures delayed recombination in rapidly cooling gas.
+    NonEquilibrium,
+}
+
+/// Parameters of the tabulated metal-line cooling.
+#[raxiom_parameters]
+pub struct MetalCoolingParameters {
+    /// An HDF5 file containing two one-dimensional datasets of equal
+    /// length: `temperature` (in Kelvin, in ascending order) and
+    /// `cooling_rate`, the cooling rate Lambda / n_H^2 of gas with
+    /// solar metallicity in erg cm^3 / s.
+    pub path: PathBuf,
+    /// The metallicity of the gas in units of the solar
+    /// metallicity. The tabulated cooling rate is scaled linearly
+    /// with the metallicity.
+    #[serde(default = "default_metallicity")]
+    pub metallicity: Float,
+}
+
+fn default_hydrogen_mass_fraction() -> Float {
+    0.76
+}
+
+fn default_temperature_floor() -> Temperature {
+    Temperature::kelvins(10.0)
+}
+
+fn default_metallicity() -> Float {
+    1.0
+}
@@ -0,0 +1,222 @@
+//! Rates of the primordial hydrogen and helium network of Katz,
+//! Weinberg & Hernquist (1996), without a photo-ionizing
+//! background. All rates are in cgs units and all temperatures
+//! in Kelvin.
+
+use crate::components::Abundances;
+use crate::prelude::Float;
+
+/// The electron abundance assumed in the rate equations when the gas
+/// is fully neutral, so that it can be ionized collisionally once it
+/// is heated.
+const MIN_ELECTRON_ABUNDANCE: Float = 1e-10;
+
+/// The recombination, collisional ionization and dielectronic
+/// recombination rate coefficients in cm^3 / s at a given
+/// temperature.
+pub(super) struct RateCoefficients {
+    pub alpha_hp: Float,
+    pub alpha_hep: Float,
+    pub alpha_d: Float,
+    pub alpha_hepp: Float,
+    pub gamma_h0: Float,
+    pub gamma_he0: Float,
+    pub gamma_hep: Float,
+}
+
+impl RateCoefficients {
+    pub fn new(temperature: Float) -> Self {
+        let t = temperature;
+        let recombination_factor =
+            t.sqrt().recip() * (t / 1e3).powf(-0.2) / (1.0 + (t / 1e6).powf(0.7));
+        let ionization_factor = t.sqrt() / (1.0 + (t / 1e5).sqrt());
+        Self {
+            alpha_hp: 8.40e-11 * recombination_factor,
+            alpha_hep: 1.50e-10 * t.powf(-0.6353),
+            alpha_d: 1.9e-3
+                * t.powf(-1.5)
+                * (-470000.0 / t).exp()
+                * (1.0 + 0.3 * (-94000.0 / t).exp()),
+            alpha_hepp: 3.36e-10 * recombination_factor,
+            gamma_h0: 5.85e-11 * ionization_factor * (-157809.1 / t).exp(),
+            gamma_he0: 2.38e-11 * ionization_factor * (-285335.4 / t).exp(),
+            gamma_hep: 5.68e-12 * ionization_factor * (-631515.0 / t).exp(),
+        }
+    }
+}
+
+impl Abundances {
+    /// The abundances in collisional ionization equilibrium. Without
+    /// a photo-ionizing background, all rates are proportional to
+    /// the electron density, so the equilibrium only depends on the
+    /// temperature.
+    pub(super) fn equilibrium(temperature: Float, hydrogen_mass_fraction: Float) -> Self {
+        let rates = RateCoefficients::new(temperature);
+        let y_he = helium_abundance(hydrogen_mass_fraction);
+        let hi = rates.alpha_hp / (rates.alpha_hp + rates.gamma_h0);
+        let hii = 1.0 - hi;
+        // Written such that the ionization rates (which vanish at low
+        // temperatures) only appear in the numerators.
+        let hei_ionization = rates.gamma_he0 / (rates.alpha_hep + rates.alpha_d);
+        let heii_ionization = rates.gamma_hep / rates.alpha_hepp;
+        let hei = y_he / (1.0 + hei_ionization * (1.0 + heii_ionization));
+        let heii = hei * hei_ionization;
+        let heiii = heii * heii_ionization;
+        Self {
+            hi,
+            hii,
+            hei,
+            heii,
+            heiii,
+            e: hii + heii + 2.0 * heiii,
+        }
+    }
+
+    /// The rate of change of the electron abundance per unit n_H in
+    /// cm^3 / s.
+    pub(super) fn electron_rate(&self, rates: &RateCoefficients) -> Float {
+        let hii = rates.gamma_h0 * self.hi - rates.alpha_hp * self.hii;
+        let heii = rates.gamma_he0 * self.hei
+            - (rates.gamma_hep + rates.alpha_hep + rates.alpha_d) * self.heii
+            + rates.alpha_hepp * self.heiii;
+        let heiii = rates.gamma_hep * self.heii - rates.alpha_hepp * self.heiii;
+        self.e.max(MIN_ELECTRON_ABUNDANCE) * (hii + heii + 2.0 * heiii)
+    }
+
+    /// Evolves the abundances over the time interval `dt` (in s) at
+    /// fixed temperature and hydrogen number density `num_density_hydrogen`
+    /// (in cm^-3). The rate equations are stiff, so every species is
+    /// updated with the backward Euler method, using the already
+    /// updated abundances of the preceding species (Anninos et al. 1997).
+    /// The update conserves the number of hydrogen and helium nuclei,
+    /// keeps all abundances positive for arbitrary `dt` and has the
+    /// equilibrium abundances as its fixed point, but is only
+    /// accurate if the electron abundance changes little over `dt`.
+    pub(super) fn evolve(
+        &mut self,
+        rates: &RateCoefficients,
+        num_density_hydrogen: Float,
+        dt: Float,
+    ) {
+        let hydrogen = self.hi + self.hii;
+        let helium = self.helium();
+        let ne_dt = self.e.max(MIN_ELECTRON_ABUNDANCE) * num_density_hydrogen * dt;
+        self.hii = (self.hii + ne_dt * rates.gamma_h0 * hydrogen)
+            / (1.0 + ne_dt * (rates.gamma_h0 + rates.alpha_hp));
+        self.hi = hydrogen - self.hii;
+        self.hei = (self.hei + ne_dt * (rates.alpha_hep + rates.alpha_d) * self.heii)
+            / (1.0 + ne_dt * rates.gamma_he0);
+        self.heii = (self.heii
+            + ne_dt * (rates.gamma_he0 * self.hei + rates.alpha_hepp * self.heiii))
+            / (1.0 + ne_dt * (rates.gamma_hep + rates.alpha_hep + rates.alpha_d));
+        self.heiii =
+            (self.heiii + ne_dt * rates.gamma_hep * self.heii) / (1.0 + ne_dt * rates.alpha_hepp);
+        // The sequential updates do not conserve the number of
+        // helium nuclei exactly.
+        let correction = helium / self.helium();
+        self.hei *= correction;
+        self.heii *= correction;
+        self.heiii *= correction;
+        self.e = self.hii + self.heii + 2.0 * self.heiii;
+    }
+}
+
+/// The number of helium nuclei per hydrogen nucleus.
+pub(super) fn helium_abundance(hydrogen_mass_fraction: Float) -> Float {
+    (1.0 - hydrogen_mass_fraction) / (4.0 * hydrogen_mass_fraction)
+}
+
+/// The cooling rate Lambda / n_H^2 in erg cm^3 / s due to
+/// collisional excitation, collisional ionization, recombination
+/// and free-free emission.
+pub(super) fn cooling_rate(temperature: Float, abundances: &Abundances) -> Float {
+    let t = temperature;
+    let Abundances {
+        hi,
+        hii,
+        hei,
+        heii,
+        heiii,
+        e,
+    } = *abundances;
+    let factor = 1.0 / (1.0 + (t / 1e5).sqrt());
+    let collisional_excitation = 7.50e-19 * (-118348.0 / t).exp() * factor * hi
+        + 5.54e-17 * t.powf(-0.397) * (-473638.0 / t).exp() * factor * heii;
+    let collisional_ionization = t.sqrt()
+        * factor
+        * (1.27e-21 * (-157809.1 / t).exp() * hi
+            + 9.38e-22 * (-285335.4 / t).exp() * hei
+            + 4.95e-22 * (-631515.0 / t).exp() * heii);
+    let recombination_factor = t.sqrt() * (t / 1e3).powf(-0.2) / (1.0 + (t / 1e6).powf(0.7));
+    let recombination = 8.70e-27 * recombination_factor * hii
+        + 1.55e-26 * t.powf(0.3647) * heii
+        + 3.48e-26 * recombination_factor * heiii;
+    let dielectronic_recombination =
+        1.24e-13 * t.powf(-1.5) * (-470000.0 / t).exp() * (1.0 + 0.3 * (-94000.0 / t).exp()) * heii;
+    let gaunt_factor = 1.1 + 0.34 * (-(5.5 - t.log10()).powi(2) / 3.0).exp();
+    let free_free = 1.42e-27 * gaunt_factor * t.sqrt() * (hii + heii + 4.0 * heiii);
+    e * (collisional_excitation
+        + collisional_ionization
+        + recombination
+        + dielectronic_recombination
+        + free_free)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cooling_rate;
+    use super::helium_abundance;
+    use super::Abundances;
+    use super::RateCoefficients;
+
+    const HYDROGEN_MASS_FRACTION: f64 = 0.76;
+
+    #[test]
+    fn equilibrium_abundances() {
+        let y_he = helium_abundance(HYDROGEN_MASS_FRACTION);
+        let neutral = Abundances::equilibrium(1e3, HYDROGEN_MASS_FRACTION);
+        assert!(neutral.hi > 0.999);
+        assert!(neutral.hei > 0.999 * y_he);
+        assert!((neutral.molecular_weight().value() - 1.22).abs() < 0.01);
+        let ionized = Abundances::equilibrium(1e8, HYDROGEN_MASS_FRACTION);
+        assert!(ionized.hii > 0.999);
+        assert!(ionized.heiii > 0.999 * y_he);
+        assert!((ionized.molecular_weight().value() - 0.59).abs() < 0.01);
+        for temperature in [1e1, 1e3, 1e4, 1e5, 1e6, 1e7] {
+            let abundances = Abundances::equilibrium(temperature, HYDROGEN_MASS_FRACTION);
+            assert!((abundances.hi + abundances.hii - 1.0).abs() < 1e-10);
+            assert!((abundances.hei + abundances.heii + abundances.heiii - y_he).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn cooling_rate_peaks_at_hydrogen_and_helium_lines() {
+        let rate = |t: f64| cooling_rate(t, &Abundances::equilibrium(t, HYDROGEN_MASS_FRACTION));
+        // Neutral gas barely cools.
+        assert!(rate(5e3) < 1e-30);
+        // The peaks of collisional excitation of HI and HeII.
+        assert!(rate(2e4) > 1e-23);
+        assert!(rate(1e5) > 1e-23);
+        // Bremsstrahlung at high temperatures.
+        assert!(rate(1e8) > 1e-23 && rate(1e8) < 1e-22);
+    }
+
+    #[test]
+    fn abundances_evolve_towards_equilibrium() {
+        let y_he = helium_abundance(HYDROGEN_MASS_FRACTION);
+        for temperature in [2e4, 1e5, 1e6] {
+            let equilibrium = Abundances::equilibrium(temperature, HYDROGEN_MASS_FRACTION);
+            let rates = RateCoefficients::new(temperature);
+            let mut abundances = Abundances::equilibrium(1e3, HYDROGEN_MASS_FRACTION);
+            for _ in 0..1000 {
+                abundances.evolve(&rates, 1.0, 1e12);
+                assert!((abundances.hi + abundances.hii - 1.0).abs() < 1e-10);
+                assert!((abundances.helium() - y_he).abs() < 1e-10);
+            }
+            assert!((abundances.hii - equilibrium.hii).abs() < 1e-6);
+            assert!((abundances.heii - equilibrium.heii).abs() < 1e-6);
+            assert!((abundances.heiii - equilibrium.heiii).abs() < 1e-6);
+            assert!((abundances.e - equilibrium.e).abs() < 1e-6);
+        }
+    }
+}
@@ -10,8 +10,11 @@ use bevy::prelude::IntoSystemDescriptor;
 use bevy::prelude::Res;
 use bevy::prelude::ResMut;
 use bevy::prelude::Resource;
+use mpi::datatype::UserDatatype;
+use mpi::internal::memoffset::offset_of;
 use mpi::traits::Equivalence;
 use mpi::traits::MatchesRaw;
+use mpi::Address;
 
 use super::DomainDecompositionStages;
 use crate::communication::CommunicationPlugin;
@@ -42,11 +45,42 @@ impl OutgoingEntities {
 #[derive(Default, Deref, DerefMut, Resource)]
 struct SpawnedEntities(DataByRank<Vec<Entity>>);
 
+/// The component of the particle at the given index within the
+/// entities that are sent to a rank. Not every particle carries
+/// every component (for example, only gas particles have
+/// hydrodynamical quantities), so the index identifies the
+/// particle on the receiving side.
+struct Indexed<T> {
+    index: u64,
+    data: T,
+}
+
+unsafe impl<T> Equivalence for Indexed<T>
+where
+    T: Equivalence,
+{
+    type Out = UserDatatype;
+
+    fn equivalent_datatype() -> Self::Out {
+        UserDatatype::structured(
+            &[1, 1],
+            &[
+                offset_of!(Indexed<T>, index) as Address,
+                offset_of!(Indexed<T>, data) as Address,
+            ],
+            &[
+                UserDatatype::contiguous(1, &u64::equivalent_datatype()),
+                UserDatatype::contiguous(1, &T::equivalent_datatype()),
+            ],
+        )
+    }
+}
+
 #[derive(Deref, DerefMut, Resource)]
-struct ExchangeBuffers<T>(DataByRank<Vec<T>>);
+struct ExchangeBuffers<T>(DataByRank<Vec<Indexed<T>>>);
 
 impl<T> ExchangeBuffers<T> {
-    fn take(&mut self) -> DataByRank<Vec<T>> {
+    fn take(&mut self) -> DataByRank<Vec<Indexed<T>>> {
         std::mem::take(&mut self.0)
     }
 }
@@ -116,7 +150,7 @@ where
                 .before(reset_outgoing_entities_system),
             Self::exchange_buffers_system.as_system_label(),
         )
-        .add_plugin(CommunicationPlugin::<T>::exchange())
+        .add_plugin(CommunicationPlugin::<Indexed<T>>::exchange())
         .add_system_to_stage(
             DomainDecompositionStages::Exchange,
             Self::fill_buffers_system,
@@ -131,7 +165,7 @@ where
 impl<T: Sync + Send + 'static + Component + Clone + Equivalence> ExchangeDataPlugin<T> {
     fn fill_buffers_system(
         entity_exchange: Res<OutgoingEntities>,
-        query: Particles<&T>,
+        query: Particles<Option<&T>>,
         mut buffer: ResMut<ExchangeBuffers<T>>,
     ) {
         for (rank, entities) in entity_exchange.iter() {
@@ -141,7 +175,13 @@ impl<T: Sync + Send + 'static + Component + Clone + Equivalence> ExchangeDataPlu
                 *rank,
                 entities
                     .iter()
-                    .map(|entity| query.get(*entity).unwrap().clone())
+                    .enumerate()
+                    .filter_map(|(index, entity)| {
+                        query.get(*entity).unwrap().map(|data| Indexed {
+                            index: index as u64,
+                            data: data.clone(),
+                        })
+                    })
                     .collect(),
             );
         }
@@ -149,16 +189,18 @@ impl<T: Sync + Send + 'static + Component + Clone + Equivalence> ExchangeDataPlu
 
     fn exchange_buffers_system(
         mut commands: Commands,
-        mut communicator: ExchangeCommunicator<T>,
+        mut communicator: ExchangeCommunicator<Indexed<T>>,
         mut buffers: ResMut<ExchangeBuffers<T>>,
         spawned_entities: Res<SpawnedEntities>,
     ) {
         let buffers = buffers.take();
         let mut incoming = communicator.exchange_all(buffers);
         for (rank, data) in incoming.drain_all() {
-            let spawned_entities = spawned_entities[rank].clone();
-            for (entity, component) in spawned_entities.iter().zip(data.into_iter()) {
-                commands.entity(*entity).insert(component);
+            let spawned_entities = &spawned_entities[rank];
+            for component in data.into_iter() {
+                commands
+                    .entity(spawned_entities[component.index as usize])
+                    .insert(component.data);
             }
         }
     }
@@ -249,11 +291,8 @@ mod tests {
                     .spawn((A { x: 0, y: 5.0 }, B { x: 0, y: false }, LocalParticle))
                     .id(),
             );
-            entities.push(
-                sim.world()
-                    .spawn((A { x: 1, y: 10.0 }, B { x: 1, y: true }, LocalParticle))
-                    .id(),
-            );
+            // Not every particle needs to have every component.
+            entities.push(sim.world().spawn((A { x: 1, y: 10.0 }, LocalParticle)).id());
             entities.push(
                 sim.world()
                     .spawn((A { x: 2, y: 20.0 }, B { x: 2, y: false }, LocalParticle))
@@ -286,6 +325,15 @@ mod tests {
         exchange_first_entity(&mut sim);
         sim.update();
         check_num_entities(&mut sim, 0, 3);
+        if !is_main {
+            let mut query = sim.world().query::<(&A, Option<&B>)>();
+            for (a, b) in query.iter(&sim.world()) {
+                assert_eq!(b.is_some(), a.x != 1);
+                if let Some(b) = b {
+                    assert_eq!(a.x as i64, b.x);
+                }
+            }
+        }
     }
 
     #[test]
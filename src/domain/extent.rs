@@ -125,10 +125,14 @@ impl Extent {
     }
 
     pub fn contains(&self, pos: &VecLength) -> bool {
-        self.min.x() <= pos.x()
+        let contains_2d = self.min.x() <= pos.x()
             && pos.x() <= self.max.x()
             && self.min.y() <= pos.y()
-            && pos.y() <= self.max.y()
+            && pos.y() <= self.max.y();
+        #[cfg(feature = "2d")]
+        return contains_2d;
+        #[cfg(not(feature = "2d"))]
+        return contains_2d && self.min.z() <= pos.z() && pos.z() <= self.max.z();
     }
 
     pub fn volume(&self) -> Volume {
@@ -357,6 +361,22 @@ mod tests {
             }
         }
 
+        #[test]
+        fn contains_checks_z_axis() {
+            let extent = Extent::new(
+                VecLength::meters(-1.0, -2.0, -3.0),
+                VecLength::meters(1.0, 2.0, 3.0),
+            );
+            assert!(extent.contains(&VecLength::meters(0.0, 0.0, 0.0)));
+            assert!(extent.contains(&VecLength::meters(1.0, 2.0, 3.0)));
+            assert!(extent.contains(&VecLength::meters(-1.0, -2.0, -3.0)));
+            // Inside the extent in x and y, but not in z.
+            assert!(!extent.contains(&VecLength::meters(0.0, 0.0, 3.5)));
+            assert!(!extent.contains(&VecLength::meters(0.0, 0.0, -3.5)));
+            // Inside the extent in x and z, but not in y.
+            assert!(!extent.contains(&VecLength::meters(0.0, 2.5, 0.0)));
+        }
+
         fn extent_equality(e1: &Extent, e2: &Extent) -> bool {
             (e1.min - e2.min).length() == Length::zero()
                 && (e1.max - e2.max).length() == Length::zero()
@@ -4,9 +4,11 @@ use mpi::traits::Equivalence;
 
 mod exchange_data_plugin;
 pub mod extent;
+mod peano_hilbert;
 pub use self::exchange_data_plugin::ExchangeDataPlugin;
 use self::exchange_data_plugin::OutgoingEntities;
 pub use self::extent::Extent;
+use self::peano_hilbert::PeanoHilbertKey;
 use crate::communication::CommunicatedOption;
 use crate::communication::CommunicationPlugin;
 use crate::communication::Communicator;
@@ -16,16 +18,24 @@ use crate::communication::WorldRank;
 use crate::communication::WorldSize;
 use crate::components::Mass;
 use crate::components::Position;
+use crate::components::SofteningLength;
 use crate::components::Velocity;
+use crate::components::Work;
+use crate::config::TWO_TO_NUM_DIMENSIONS;
 use crate::gravity;
 use crate::gravity::LeafData;
-use crate::gravity::MassMoments;
+use crate::gravity::NodeData;
+use crate::io::restart::RestartAttribute;
+use crate::io::restart::RestartPlugin;
+use crate::io::restart::ToRestartAttribute;
 use crate::named::Named;
+use crate::prelude::Float;
 use crate::prelude::Particles;
 use crate::quadtree::QuadTreeConfig;
 use crate::quadtree::QuadTreeIndex;
 use crate::simulation::RaxiomPlugin;
 use crate::simulation::Simulation;
+use crate::units::Length;
 
 pub type QuadTree = gravity::QuadTree;
 
@@ -35,16 +45,48 @@ pub type QuadTree = gravity::QuadTree;
 pub struct DomainParameters {
     #[serde(default)]
     tree: QuadTreeConfig,
+    /// Top-level nodes which contain more than this fraction of the
+    /// average number of particles per rank are refined, so that
+    /// the domains can be cut finely enough for a good load balance.
+    #[serde(default = "default_max_top_level_node_load")]
+    max_top_level_node_load: Float,
+    /// The load of a top-level node is a weighted mix of its
+    /// share of the work (the measured number of interactions of
+    /// its particles) and of the memory (the number of particles).
+    /// A weight of 1 balances the work only, a weight of 0 balances
+    /// the memory only.
+    #[serde(default = "default_work_weight")]
+    work_weight: Float,
+    /// The tolerated load imbalance (max - min) / max between the
+    /// ranks. The top-level nodes are refined until the imbalance
+    /// falls below this value.
+    #[serde(default = "default_max_load_imbalance")]
+    max_load_imbalance: Float,
 }
 
 impl Default for DomainParameters {
     fn default() -> Self {
         Self {
             tree: default_domain_tree_params(),
+            max_top_level_node_load: default_max_top_level_node_load(),
+            work_weight: default_work_weight(),
+            max_load_imbalance: default_max_load_imbalance(),
         }
     }
 }
 
+fn default_max_top_level_node_load() -> Float {
+    0.1
+}
+
+fn default_work_weight() -> Float {
+    0.5
+}
+
+fn default_max_load_imbalance() -> Float {
+    0.05
+}
+
 fn default_domain_tree_params() -> QuadTreeConfig {
     QuadTreeConfig {
         min_depth: 4,
@@ -57,6 +99,9 @@ pub enum DomainDecompositionStages {
     TopLevelTreeConstruction,
     Decomposition,
     Exchange,
+    /// For systems which need the components of the particles
+    /// received during the exchange.
+    AfterExchange,
 }
 
 #[derive(Named)]
@@ -66,6 +111,9 @@ impl RaxiomPlugin for DomainDecompositionPlugin {
     fn build_everywhere(&self, sim: &mut Simulation) {
         sim.insert_resource(GlobalExtent(Extent::default()))
             .insert_resource(TopLevelIndices::default())
+            .insert_resource(TopLevelNodes::default())
+            .insert_resource(MaxTopLevelNodeLoad::default())
+            .add_plugin(RestartPlugin::<RestartAttribute<MaxTopLevelNodeLoad>>::default())
             .add_parameter_type::<DomainParameters>()
             .insert_resource(QuadTree::make_empty_leaf_from_extent(Extent::default()))
             .add_system_to_stage(
@@ -79,7 +127,7 @@ impl RaxiomPlugin for DomainDecompositionPlugin {
             )
             .add_system_to_stage(
                 DomainDecompositionStages::TopLevelTreeConstruction,
-                communicate_mass_moments_system.after(construct_quad_tree_system),
+                determine_top_level_nodes_system.after(construct_quad_tree_system),
             )
             .add_system_to_stage(
                 DomainDecompositionStages::Decomposition,
@@ -90,7 +138,7 @@ impl RaxiomPlugin for DomainDecompositionPlugin {
                 domain_decomposition_system.after(distribute_top_level_nodes_system),
             )
             .add_plugin(CommunicationPlugin::<CommunicatedOption<Extent>>::default())
-            .add_plugin(CommunicationPlugin::<MassMoments>::default());
+            .add_plugin(CommunicationPlugin::<NodeData>::default());
     }
 }
 
@@ -119,42 +167,71 @@ pub(super) struct ParticleExchangeData {
     mass: Mass,
 }
 
+/// Constructs the tree of the local particles. The tree is
+/// subdivided such that all top-level nodes of the current
+/// decomposition exist in it, regardless of the local particles.
 pub fn construct_quad_tree_system(
     config: Res<DomainParameters>,
-    particles: Particles<(Entity, &Position, &Mass)>,
+    particles: Particles<(
+        Entity,
+        &Position,
+        &Mass,
+        Option<&SofteningLength>,
+        Option<&Work>,
+    )>,
     extent: Res<GlobalExtent>,
+    indices: Res<TopLevelIndices>,
     mut quadtree: ResMut<QuadTree>,
 ) {
     let particles: Vec<_> = particles
         .iter()
-        .map(|(entity, pos, mass)| LeafData {
+        .map(|(entity, pos, mass, softening_length, work)| LeafData {
             entity,
             pos: pos.0,
             mass: **mass,
+            softening_length: softening_length
+                .map(|softening_length| **softening_length)
+                .unwrap_or(Length::zero()),
+            work: work.map(|work| **work).unwrap_or(0.0),
         })
         .collect();
     *quadtree = QuadTree::new(&config, particles, &extent);
+    for (_, index) in indices.flat_iter() {
+        quadtree.subdivide_to_index(&config, index);
+    }
 }
 
-fn sum_vecs(mut data: DataByRank<Vec<MassMoments>>) -> Vec<MassMoments> {
+fn sum_vecs(mut data: DataByRank<Vec<NodeData>>) -> Vec<NodeData> {
     let mut sum = data.remove(&0).unwrap();
-    for (_, other_result) in data.drain_all() {
+    // Sum in the order of the ranks, so that the floating point
+    // results, and therefore all decisions based on them, are
+    // identical on every rank.
+    let mut other_results: Vec<_> = data.drain_all().collect();
+    other_results.sort_by_key(|(rank, _)| *rank);
+    for (_, other_result) in other_results {
         debug_assert_eq!(sum.len(), other_result.len());
         for i in 0..other_result.len() {
-            sum[i] += &other_result[i];
+            sum[i].moments += &other_result[i].moments;
+            sum[i].work += other_result[i].work;
+            sum[i].max_softening_length = sum[i]
+                .max_softening_length
+                .max(other_result[i].max_softening_length);
         }
     }
     sum
 }
 
-fn get_cutoffs(particle_counts: &[usize], num_ranks: usize) -> Vec<usize> {
-    let total_work: usize = particle_counts.iter().sum();
-    let mut work_per_rank = total_work / num_ranks;
+/// Cuts the list of loads into contiguous pieces of approximately
+/// equal load, one for each rank. Returns the cutoff indices and
+/// the resulting load imbalance.
+fn get_cutoffs(node_loads: &[Float], num_ranks: usize) -> (Vec<usize>, Float) {
+    let total_work: Float = node_loads.iter().sum();
+    let mut work_per_rank = total_work / num_ranks as Float;
     let mut key_cutoffs_by_rank = vec![0];
-    let mut load = 0;
+    let mut load = 0.0;
     let mut loads = vec![];
-    let remaining_work = |loads: &[usize]| total_work - loads.iter().sum::<usize>();
-    for (i, count) in particle_counts.iter().enumerate() {
+    let remaining_work = |loads: &[Float]| total_work - loads.iter().sum::<Float>();
+    for (i, node_load) in node_loads.iter().enumerate() {
         if load >= work_per_rank {
             key_cutoffs_by_rank.push(i);
             loads.push(load);
@@ -162,24 +239,59 @@ fn get_cutoffs(particle_counts: &[usize], num_ranks: usize) -> Vec<usize> {
             if key_cutoffs_by_rank.len() >= num_ranks {
                 break;
             }
-            work_per_rank = remaining_work(&loads) / (num_ranks - loads.len());
-            load = 0;
+            work_per_rank = remaining_work(&loads) / (num_ranks - loads.len()) as Float;
+            load = 0.0;
         }
-        load += count;
+        load += node_load;
     }
     loads.push(remaining_work(&loads));
-    let max_load = *loads.iter().max().unwrap() as f64;
-    let min_load = *loads.iter().min().unwrap() as f64;
-    let load_imbalance = (max_load - min_load) / max_load;
-    debug!("Load imbalance: {:.1}%", (load_imbalance * 100.0));
+    let max_load = loads.iter().cloned().fold(0.0, Float::max);
+    let min_load = loads.iter().cloned().fold(Float::INFINITY, Float::min);
+    let load_imbalance = if max_load > 0.0 {
+        (max_load - min_load) / max_load
+    } else {
+        0.0
+    };
     let num_entries_to_fill = num_ranks as i32 - key_cutoffs_by_rank.len() as i32;
-    if num_entries_to_fill > 0 {
-        error!("One rank has no work - increase domain min_depth");
-    }
     // Even if num_entries_to_fill is zero, we add the final index once to make calculating the index
     // ranges later easier (since we can just use cutoffs[rank]..cutoffs[rank+1], even for the last rank)
-    key_cutoffs_by_rank.extend((0..1 + num_entries_to_fill).map(|_| particle_counts.len()));
-    key_cutoffs_by_rank
+    key_cutoffs_by_rank.extend((0..1 + num_entries_to_fill).map(|_| node_loads.len()));
+    (key_cutoffs_by_rank, load_imbalance)
+}
+
+/// The load of a node is a weighted mix of its share of the total
+/// number of particles and its share of the total work.
+#[derive(Clone, Copy)]
+struct LoadModel {
+    total_count: usize,
+    total_work: Float,
+    work_weight: Float,
+}
+
+impl LoadModel {
+    fn new<'a>(data: impl Iterator<Item = &'a NodeData>, work_weight: Float) -> Self {
+        let (total_count, total_work) = data.fold((0, 0.0), |(count, work), data| {
+            (count + data.moments.count(), work + data.work)
+        });
+        // Without any measured work, e.g. in the first
+        // decomposition, balance the memory only.
+        let work_weight = if total_work > 0.0 { work_weight } else { 0.0 };
+        Self {
+            total_count,
+            total_work,
+            work_weight,
+        }
+    }
+
+    fn load(&self, data: &NodeData) -> Float {
+        let count = data.moments.count() as Float / self.total_count.max(1) as Float;
+        let work = if self.total_work > 0.0 {
+            data.work / self.total_work
+        } else {
+            0.0
+        };
+        (1.0 - self.work_weight) * count + self.work_weight * work
+    }
 }
 
 #[derive(Default, Deref, DerefMut, Resource)]
@@ -191,47 +303,224 @@ impl TopLevelIndices {
             .iter()
             .flat_map(|(rank, indices)| indices.iter().map(|index| (*rank, index)))
     }
+
+    /// Iterates over the top-level nodes of all ranks in an order
+    /// which is the same on every rank, namely along the
+    /// Peano-Hilbert curve.
+    fn ordered_iter(&self) -> impl Iterator<Item = &QuadTreeIndex> {
+        let num_ranks = self.0.iter().count() as Rank;
+        (0..num_ranks).flat_map(|rank| self.0[rank].iter())
+    }
+}
+
+/// The top-level nodes of the domain tree, in the order along the
+/// Peano-Hilbert curve, which are distributed among the ranks.
+#[derive(Default, Deref, DerefMut, Resource)]
+struct TopLevelNodes(Vec<QuadTreeIndex>);
+
+/// The factor by which the maximum load of a top-level node can at
+/// most be decreased below the value given in the parameters in order
+/// to reach the tolerated load imbalance. Prevents excessive numbers
+/// of top-level nodes if the imbalance cannot be reduced by
+/// refinement.
+const MAX_ADDITIONAL_REFINEMENT_FACTOR: Float = 64.0;
+
+/// The maximum load of a top-level node as a fraction of the average
+/// load per rank, as used in the previous decomposition.
+#[derive(Default, Named, Resource)]
+#[name = "max_top_level_node_load"]
+struct MaxTopLevelNodeLoad(Option<Float>);
+
+impl ToRestartAttribute for MaxTopLevelNodeLoad {
+    type Output = (bool, Float);
+
+    fn to_restart_value(&self) -> (bool, Float) {
+        (self.0.is_some(), self.0.unwrap_or(0.0))
+    }
+
+    fn from_restart_value((is_some, value): (bool, Float)) -> Self {
+        Self(is_some.then_some(value))
+    }
 }
 
-fn get_top_level_indices(depth: usize) -> Vec<QuadTreeIndex> {
-    QuadTreeIndex::iter_all_nodes_at_depth(depth).collect()
+/// Sums the mass moments and the work of the given nodes over all
+/// ranks and determines their largest softening length. The order
+/// of the nodes needs to be the same on every rank.
+fn communicate_mass_moments<'a>(
+    tree: &mut QuadTree,
+    indices: impl Iterator<Item = &'a QuadTreeIndex>,
+    comm: &mut Communicator<NodeData>,
+) {
+    let indices: Vec<_> = indices.collect();
+    let data: Vec<_> = indices
+        .iter()
+        .map(|index| tree[index].data.clone())
+        .collect();
+    // replace with allreduce over buffer at some point
+    let total_data = sum_vecs(comm.all_gather_vec(&data));
+    for (index, data) in indices.into_iter().zip(total_data.into_iter()) {
+        tree[index].data = data;
+    }
 }
 
 pub fn communicate_mass_moments_system(
+    mut tree: ResMut<QuadTree>,
+    indices: Res<TopLevelIndices>,
+    mut comm: Communicator<NodeData>,
+) {
+    communicate_mass_moments(&mut tree, indices.ordered_iter(), &mut comm);
+}
+
+/// Determines the top-level nodes of the domain decomposition.
+/// Starting from all nodes at the minimum depth of the tree, every
+/// node with too large a load to allow for a good load balance is
+/// replaced by its children. The resulting nodes are sorted along
+/// the Peano-Hilbert curve, so that cutting the list into contiguous
+/// pieces results in compact domains. As long as the cut does not
+/// balance the load within the tolerance, the maximum load per node
+/// is decreased and the nodes are refined further.
+fn determine_top_level_nodes_system(
     mut tree: ResMut<QuadTree>,
     config: Res<DomainParameters>,
-    mut comm: Communicator<MassMoments>,
+    num_ranks: Res<WorldSize>,
+    mut comm: Communicator<NodeData>,
+    mut top_level_nodes: ResMut<TopLevelNodes>,
+    mut max_top_level_node_load: ResMut<MaxTopLevelNodeLoad>,
 ) {
-    // Use the particle counts at depth config.min_depth for
-    // decomposition for now. This obviously needs to be fixed and
-    // replaced by a proper peano hilbert curve on an actual tree
-    let top_level_tree_leaf_indices = get_top_level_indices(config.min_depth);
-    let mass_moments: Vec<_> = top_level_tree_leaf_indices
-        .iter()
-        .map(|index| tree[index].data.moments.clone())
-        .collect();
-    // replace with allreduce over buffer at some point
-    let total_mass_moments = sum_vecs(comm.all_gather_vec(&mass_moments));
-    for (index, moments) in top_level_tree_leaf_indices
-        .iter()
-        .zip(total_mass_moments.iter())
-    {
-        tree[index].data.moments = moments.clone();
+    let (nodes, max_load) = determine_top_level_nodes(
+        &mut tree,
+        &config,
+        **num_ranks,
+        max_top_level_node_load.0,
+        |tree, indices| communicate_mass_moments(tree, indices.iter(), &mut comm),
+    );
+    max_top_level_node_load.0 = Some(max_load);
+    *top_level_nodes = TopLevelNodes(nodes);
+}
+
+/// Returns the top-level nodes and the maximum load per node which
+/// was used to determine them. `communicate` sums the data of the
+/// given nodes over all ranks.
+fn determine_top_level_nodes(
+    tree: &mut QuadTree,
+    config: &DomainParameters,
+    num_ranks: usize,
+    previous_max_load: Option<Float>,
+    mut communicate: impl FnMut(&mut QuadTree, &[QuadTreeIndex]),
+) -> (Vec<QuadTreeIndex>, Float) {
+    let min_max_load = config.max_top_level_node_load / MAX_ADDITIONAL_REFINEMENT_FACTOR;
+    // Start from the refinement of the previous decomposition, but
+    // allow it to become coarser again over time.
+    let mut max_load = previous_max_load
+        .map(|max_load| (max_load * 1.25).min(config.max_top_level_node_load))
+        .unwrap_or(config.max_top_level_node_load);
+    let mut candidates: Vec<_> = QuadTreeIndex::iter_all_nodes_at_depth(config.min_depth).collect();
+    let mut nodes = vec![];
+    // The loads are normalized with respect to the totals at the
+    // minimum depth, which are the totals of the whole domain.
+    let mut load_model = None;
+    loop {
+        while !candidates.is_empty() {
+            for index in candidates.iter() {
+                tree.subdivide_to_index(config, index);
+            }
+            communicate(tree, &candidates);
+            let load_model = *load_model.get_or_insert_with(|| {
+                LoadModel::new(
+                    candidates.iter().map(|index| &tree[index].data),
+                    config.work_weight,
+                )
+            });
+            let (overloaded, balanced): (Vec<_>, Vec<_>) =
+                candidates.into_iter().partition(|index| {
+                    should_be_refined(
+                        tree,
+                        config,
+                        &load_model,
+                        index,
+                        max_load / num_ranks as Float,
+                    )
+                });
+            nodes.extend(balanced);
+            candidates = get_children(overloaded);
+        }
+        nodes.sort_by_cached_key(|index| {
+            PeanoHilbertKey::new(&tree.extent, &tree[index].extent.center())
+        });
+        let (_, load_imbalance) = get_cutoffs(&get_node_loads(tree, &nodes, config), num_ranks);
+        if load_imbalance <= config.max_load_imbalance || max_load <= min_max_load {
+            break;
+        }
+        max_load = (max_load * 0.5).max(min_max_load);
+        let load_model = load_model.unwrap();
+        let (overloaded, balanced): (Vec<_>, Vec<_>) = nodes.into_iter().partition(|index| {
+            should_be_refined(
+                tree,
+                config,
+                &load_model,
+                index,
+                max_load / num_ranks as Float,
+            )
+        });
+        nodes = balanced;
+        candidates = get_children(overloaded);
     }
+    (nodes, max_load)
+}
+
+/// Whether the load of the node exceeds the maximum load and
+/// splitting the node into its children can improve the balance.
+fn should_be_refined(
+    tree: &QuadTree,
+    config: &DomainParameters,
+    load_model: &LoadModel,
+    index: &QuadTreeIndex,
+    max_load: Float,
+) -> bool {
+    let data = &tree[index].data;
+    index.depth() < config.max_depth && data.moments.count() > 1 && load_model.load(data) > max_load
+}
+
+fn get_children(indices: Vec<QuadTreeIndex>) -> Vec<QuadTreeIndex> {
+    indices
+        .into_iter()
+        .flat_map(|index| (0..TWO_TO_NUM_DIMENSIONS).map(move |num| index.child(num)))
+        .collect()
+}
+
+fn get_node_loads(
+    tree: &QuadTree,
+    nodes: &[QuadTreeIndex],
+    config: &DomainParameters,
+) -> Vec<Float> {
+    let load_model = LoadModel::new(
+        nodes.iter().map(|index| &tree[index].data),
+        config.work_weight,
+    );
+    nodes
+        .iter()
+        .map(|index| load_model.load(&tree[index].data))
+        .collect()
 }
 
 fn distribute_top_level_nodes_system(
     tree: Res<QuadTree>,
     config: Res<DomainParameters>,
+    top_level_nodes: Res<TopLevelNodes>,
     num_ranks: Res<WorldSize>,
     mut indices: ResMut<TopLevelIndices>,
 ) {
-    let top_level_tree_leaf_indices = get_top_level_indices(config.min_depth);
-    let particles_per_leaf: Vec<usize> = top_level_tree_leaf_indices
-        .iter()
-        .map(|index| tree[index].data.moments.count())
-        .collect();
-    let cutoffs = get_cutoffs(&particles_per_leaf, **num_ranks);
+    let top_level_tree_leaf_indices = &**top_level_nodes;
+    let node_loads = get_node_loads(&tree, top_level_tree_leaf_indices, &config);
+    let (cutoffs, load_imbalance) = get_cutoffs(&node_loads, **num_ranks);
+    debug!(
+        "Load imbalance: {:.1}% with {} top-level nodes",
+        (load_imbalance * 100.0),
+        top_level_tree_leaf_indices.len()
+    );
+    if cutoffs.windows(2).any(|range| range[0] == range[1]) {
+        error!("One rank has no work - increase domain max_depth");
+    }
     *indices = TopLevelIndices(
         (0..**num_ranks)
             .map(|rank| {
@@ -264,3 +553,218 @@ fn domain_decomposition_system(
         }
     }
 }
+
+#[cfg(test)]
+#[cfg(not(feature = "2d"))]
+mod tests {
+    use std::collections::HashSet;
+
+    use bevy::prelude::Entity;
+
+    use super::determine_top_level_nodes;
+    use super::get_cutoffs;
+    use super::get_node_loads;
+    use super::DomainParameters;
+    use super::QuadTree;
+    use super::TopLevelIndices;
+    use crate::domain::extent::Extent;
+    use crate::gravity::LeafData;
+    use crate::quadtree::QuadTreeConfig;
+    use crate::quadtree::QuadTreeIndex;
+    use crate::units::Length;
+    use crate::units::Mass;
+    use crate::units::VecLength;
+
+    /// Particles on a grid which is strongly compressed towards
+    /// the origin, so that a few nodes contain most of the particles.
+    fn clustered_particles(n: usize) -> Vec<LeafData> {
+        let coordinate = |i: usize| (i as f64 / n as f64).powi(3);
+        (0..n * n * n)
+            .map(|i| LeafData {
+                entity: Entity::from_raw(i as u32),
+                pos: VecLength::meters(
+                    coordinate(i % n),
+                    coordinate((i / n) % n),
+                    coordinate(i / (n * n)),
+                ),
+                mass: Mass::kilograms(1.0),
+                softening_length: Length::zero(),
+                work: 0.0,
+            })
+            .collect()
+    }
+
+    fn build_tree(config: &QuadTreeConfig, particles: Vec<LeafData>) -> QuadTree {
+        let extent = Extent::from_positions(particles.iter().map(|particle| &particle.pos))
+            .unwrap()
+            .pad();
+        QuadTree::new(config, particles, &extent)
+    }
+
+    #[test]
+    fn refinement_reaches_tolerated_load_imbalance() {
+        let num_ranks = 4;
+        let particles = clustered_particles(20);
+        let num_particles = particles.len();
+        let config = DomainParameters {
+            tree: QuadTreeConfig {
+                min_depth: 1,
+                ..Default::default()
+            },
+            // Coarse enough that the load imbalance can only be
+            // reached by refining further.
+            max_top_level_node_load: 1.0,
+            max_load_imbalance: 0.05,
+            ..Default::default()
+        };
+        let mut tree = build_tree(&config, particles);
+        // All particles are on this rank, so there is nothing to
+        // communicate.
+        let (nodes, max_load) =
+            determine_top_level_nodes(&mut tree, &config, num_ranks, None, |_, _| {});
+        assert!(max_load < config.max_top_level_node_load);
+        let (_, load_imbalance) = get_cutoffs(&get_node_loads(&tree, &nodes, &config), num_ranks);
+        assert!(load_imbalance <= config.max_load_imbalance);
+        let num_particles_in_nodes: usize = nodes
+            .iter()
+            .map(|index| tree[index].data.moments.count())
+            .sum();
+        assert_eq!(num_particles_in_nodes, num_particles);
+        // The refinement is adaptive, so the nodes are at different
+        // depths.
+        let depths: HashSet<_> = nodes.iter().map(|index| index.depth()).collect();
+        assert!(depths.len() > 1);
+    }
+
+    #[test]
+    fn mixed_depth_top_level_indices_contain_every_particle_once() {
+        let particles = clustered_particles(10);
+        let config = QuadTreeConfig {
+            min_depth: 0,
+            max_num_particles_per_leaf: 2000,
+            ..Default::default()
+        };
+        let root = QuadTreeIndex::default();
+        let indices = TopLevelIndices(
+            [
+                (0, vec![root.child(0)]),
+                (1, (0..7).map(|num| root.child(1).child(num)).collect()),
+                (
+                    2,
+                    (2..8)
+                        .map(|num| root.child(num))
+                        .chain((0..8).map(|num| root.child(1).child(7).child(num)))
+                        .collect(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let mut tree = build_tree(&config, particles.clone());
+        for (_, index) in indices.flat_iter() {
+            tree.subdivide_to_index(&config, index);
+        }
+        let mut entities = HashSet::new();
+        for (rank, index) in indices.flat_iter() {
+            let extent = tree[index].extent.clone();
+            tree[index].depth_first_map_leaf(&mut |_, leaf| {
+                for particle in leaf.iter() {
+                    assert!(extent.contains(&particle.pos), "{rank}");
+                    assert!(entities.insert(particle.entity));
+                }
+            });
+        }
+        assert_eq!(entities.len(), particles.len());
+    }
+
+    /// Sums the particle counts and the work of the nodes on each
+    /// rank, given the cutoffs.
+    fn count_and_work_by_rank(
+        tree: &QuadTree,
+        nodes: &[QuadTreeIndex],
+        cutoffs: &[usize],
+    ) -> Vec<(usize, f64)> {
+        cutoffs
+            .windows(2)
+            .map(|range| {
+                nodes[range[0]..range[1]]
+                    .iter()
+                    .map(|index| &tree[index].data)
+                    .fold((0, 0.0), |(count, work), data| {
+                        (count + data.moments.count(), work + data.work)
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn load_model_balances_unequal_work() {
+        let num_ranks = 2;
+        let n = 8;
+        let coordinate = |i: usize| (i as f64 + 0.5) / n as f64;
+        // The particles in one octant of the box are seven times as
+        // expensive as the others.
+        let particles: Vec<_> = (0..n * n * n)
+            .map(|i| {
+                let pos = VecLength::meters(
+                    coordinate(i % n),
+                    coordinate((i / n) % n),
+                    coordinate(i / (n * n)),
+                );
+                let is_expensive = pos.x().value_unchecked() < 0.5
+                    && pos.y().value_unchecked() < 0.5
+                    && pos.z().value_unchecked() < 0.5;
+                LeafData {
+                    entity: Entity::from_raw(i as u32),
+                    pos,
+                    mass: Mass::kilograms(1.0),
+                    softening_length: Length::zero(),
+                    work: if is_expensive { 7.0 } else { 1.0 },
+                }
+            })
+            .collect();
+        let tree_config = QuadTreeConfig {
+            min_depth: 3,
+            ..Default::default()
+        };
+        let extent = Extent::new(
+            VecLength::meters(0.0, 0.0, 0.0),
+            VecLength::meters(1.0, 1.0, 1.0),
+        );
+        let tree = QuadTree::new(&tree_config, particles, &extent);
+        let nodes: Vec<_> = QuadTreeIndex::iter_all_nodes_at_depth(3).collect();
+        let balance = |work_weight| {
+            let config = DomainParameters {
+                tree: tree_config.clone(),
+                work_weight,
+                ..Default::default()
+            };
+            let (cutoffs, load_imbalance) =
+                get_cutoffs(&get_node_loads(&tree, &nodes, &config), num_ranks);
+            assert!(load_imbalance < 0.05);
+            count_and_work_by_rank(&tree, &nodes, &cutoffs)
+        };
+        let relative_difference = |a: f64, b: f64| (a - b).abs() / a.max(b);
+        // Balancing the work only leaves the particle counts
+        // unequal ...
+        let by_rank = balance(1.0);
+        assert!(relative_difference(by_rank[0].1, by_rank[1].1) < 0.05);
+        assert!(relative_difference(by_rank[0].0 as f64, by_rank[1].0 as f64) > 0.1);
+        // ... while balancing the particle counts only leaves the
+        // work unequal.
+        let by_rank = balance(0.0);
+        assert_eq!(by_rank[0].0, by_rank[1].0);
+        assert!(relative_difference(by_rank[0].1, by_rank[1].1) > 0.1);
+    }
+
+    #[test]
+    fn get_cutoffs_splits_unequal_loads() {
+        let loads = [4.0, 1.0, 1.0, 1.0, 1.0, 4.0, 1.0, 1.0, 1.0, 1.0];
+        let (cutoffs, load_imbalance) = get_cutoffs(&loads, 2);
+        assert_eq!(cutoffs, vec![0, 5, 10]);
+        assert_eq!(load_imbalance, 0.0);
+        let (cutoffs, load_imbalance) = get_cutoffs(&loads, 4);
+        assert_eq!(cutoffs, vec![0, 1, 5, 6, 10]);
+        assert_eq!(load_imbalance, 0.0);
+    }
+}
@@ -0,0 +1,128 @@
+use super::Extent;
+use crate::config::NUM_DIMENSIONS;
+use crate::units::VecLength;
+
+/// The number of bits of the key per dimension.
+const NUM_BITS_PER_DIMENSION: u32 = 64 / NUM_DIMENSIONS as u32;
+
+/// The position of a point along the Peano-Hilbert curve through an
+/// extent. Points which are close along the curve are close in space,
+/// so that cutting the curve into contiguous pieces gives compact
+/// domains. The keys are hierarchical: all points within a node of a
+/// tree on the extent share the leading bits of their keys, so
+/// sorting nodes of different depths by the keys of their centers
+/// orders them along the curve as well.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PeanoHilbertKey(pub u64);
+
+impl PeanoHilbertKey {
+    pub fn new(extent: &Extent, pos: &VecLength) -> Self {
+        let max_coordinate = (1u64 << NUM_BITS_PER_DIMENSION) - 1;
+        let to_integer = |x: f64, min: f64, max: f64| {
+            let relative = (x - min) / (max - min);
+            ((relative * (max_coordinate + 1) as f64) as u64).min(max_coordinate) as u32
+        };
+        let pos = pos.0;
+        let min = extent.min.0;
+        let max = extent.max.0;
+        #[cfg(feature = "2d")]
+        let coordinates = [
+            to_integer(pos.x, min.x, max.x),
+            to_integer(pos.y, min.y, max.y),
+        ];
+        #[cfg(not(feature = "2d"))]
+        let coordinates = [
+            to_integer(pos.x, min.x, max.x),
+            to_integer(pos.y, min.y, max.y),
+            to_integer(pos.z, min.z, max.z),
+        ];
+        Self::from_integer_coordinates(coordinates)
+    }
+
+    /// Computes the key from the integer coordinates of a cell
+    /// using the algorithm of Skilling (2004).
+    fn from_integer_coordinates(mut x: [u32; NUM_DIMENSIONS]) -> Self {
+        // Inverse undo excess work
+        let mut q = 1u32 << (NUM_BITS_PER_DIMENSION - 1);
+        while q > 1 {
+            let p = q - 1;
+            for i in 0..NUM_DIMENSIONS {
+                if x[i] & q != 0 {
+                    x[0] ^= p;
+                } else {
+                    let t = (x[0] ^ x[i]) & p;
+                    x[0] ^= t;
+                    x[i] ^= t;
+                }
+            }
+            q >>= 1;
+        }
+        // Gray encode
+        for i in 1..NUM_DIMENSIONS {
+            x[i] ^= x[i - 1];
+        }
+        let mut t = 0;
+        let mut q = 1u32 << (NUM_BITS_PER_DIMENSION - 1);
+        while q > 1 {
+            if x[NUM_DIMENSIONS - 1] & q != 0 {
+                t ^= q - 1;
+            }
+            q >>= 1;
+        }
+        for coordinate in x.iter_mut() {
+            *coordinate ^= t;
+        }
+        // Interleave the bits of the transposed coordinates
+        let mut key = 0u64;
+        for bit in (0..NUM_BITS_PER_DIMENSION).rev() {
+            for coordinate in x.iter() {
+                key = (key << 1) | ((coordinate >> bit) & 1) as u64;
+            }
+        }
+        Self(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeanoHilbertKey;
+    use crate::config::NUM_DIMENSIONS;
+
+    #[test]
+    fn consecutive_keys_are_neighbouring_cells() {
+        // Only use the lowest few bits of the coordinates, which
+        // correspond to the curve within a single cell of the coarse grid.
+        let num_bits = 3;
+        let side = 1u32 << num_bits;
+        let num_cells = (side as usize).pow(NUM_DIMENSIONS as u32);
+        let mut cells: Vec<_> = (0..num_cells)
+            .map(|index| {
+                let mut coordinates = [0; NUM_DIMENSIONS];
+                let mut remainder = index as u32;
+                for coordinate in coordinates.iter_mut() {
+                    *coordinate = remainder % side;
+                    remainder /= side;
+                }
+                (
+                    PeanoHilbertKey::from_integer_coordinates(coordinates),
+                    coordinates,
+                )
+            })
+            .collect();
+        cells.sort_by_key(|(key, _)| *key);
+        for (i, (key, _)) in cells.iter().enumerate() {
+            // All cells lie within the first cell of the coarse grid
+            assert_eq!(key.0 >> (NUM_DIMENSIONS as u32 * num_bits), 0);
+            assert_eq!(key.0 as usize, i);
+        }
+        for pair in cells.windows(2) {
+            let distance: u32 = pair[0]
+                .1
+                .iter()
+                .zip(pair[1].1.iter())
+                .map(|(x, y)| x.abs_diff(*y))
+                .sum();
+            assert_eq!(distance, 1);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use bevy::prelude::Resource;
+
+use crate::parameters::SimulationBox;
+use crate::prelude::Float;
+use crate::prelude::MVec;
+use crate::units::Length;
+use crate::units::Mass;
+use crate::units::VecAcceleration;
+use crate::units::VecLength;
+use crate::units::GRAVITY_CONSTANT;
+
+/// The number of intervals of the correction table along each
+/// axis of the octant [0, L / 2]^3.
+const TABLE_SIZE: usize = 32;
+
+/// The splitting parameter between the real space and the Fourier
+/// space sums, in units of the inverse box size.
+const ALPHA: Float = 2.0;
+
+/// The number of periodic images and wave vectors taken into account
+/// along each axis in either direction. With ALPHA = 2, the terms
+/// beyond this range are below 1e-4 of the leading terms.
+const NUM_IMAGES: i32 = 2;
+
+/// A table of the Ewald correction to the acceleration between two
+/// particles, i.e. the difference between the force of a mass and all
+/// of its periodic images and the force of its nearest image alone.
+/// Adding the correction to the nearest image forces yields the
+/// forces in an infinite periodic lattice, in which a uniform density
+/// distribution feels no force. The table is computed once for the
+/// octant [0, L / 2]^3 of the distance vector in box units. The
+/// correction is odd in each component of the distance vector, which
+/// gives the values in all other octants. Only implemented for cubic
+/// boxes in 3D.
+#[derive(Resource)]
+pub struct EwaldCorrection {
+    box_size: Length,
+    table: Vec<MVec>,
+}
+
+/// The complementary error function, with a fractional error below
+/// 1.2e-7 everywhere (Numerical Recipes, erfcc).
+fn erfc(x: Float) -> Float {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let ans = t
+        * (-z * z - 1.26551223
+            + t * (1.00002368
+                + t * (0.37409196
+                    + t * (0.09678418
+                        + t * (-0.18628806
+                            + t * (0.27886807
+                                + t * (-1.13520398
+                                    + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+            .exp();
+    if x >= 0.0 {
+        ans
+    } else {
+        2.0 - ans
+    }
+}
+
+/// The Ewald correction at the distance vector x (from the mass to
+/// the particle) in box units for G = m = L = 1, following Hernquist,
+/// Bouchet & Suto (1991).
+fn ewald_correction(x: MVec) -> MVec {
+    use std::f64::consts::PI;
+    let r = x.length();
+    if r == 0.0 {
+        return MVec::ZERO;
+    }
+    // Remove the nearest image force, which is computed separately.
+    let mut force = x / r.powi(3);
+    let images = || {
+        (-NUM_IMAGES..=NUM_IMAGES).flat_map(|i| {
+            (-NUM_IMAGES..=NUM_IMAGES).flat_map(move |j| {
+                (-NUM_IMAGES..=NUM_IMAGES).map(move |k| MVec::new(i as f64, j as f64, k as f64))
+            })
+        })
+    };
+    for n in images() {
+        let dx = x - n;
+        let r = dx.length();
+        let val = erfc(ALPHA * r) + 2.0 * ALPHA * r / PI.sqrt() * (-ALPHA * ALPHA * r * r).exp();
+        force -= dx / r.powi(3) * val;
+    }
+    for h in images() {
+        let h2 = h.length_squared();
+        if h2 == 0.0 {
+            continue;
+        }
+        let val = 2.0 / h2 * (-PI * PI * h2 / (ALPHA * ALPHA)).exp() * (2.0 * PI * h.dot(x)).sin();
+        force -= h * val;
+    }
+    force
+}
+
+impl EwaldCorrection {
+    pub fn new(box_: &SimulationBox) -> Self {
+        let side_lengths = box_.side_lengths();
+        let box_size = side_lengths.x();
+        assert!(
+            side_lengths.y() == box_size && side_lengths.z() == box_size,
+            "Ewald summation requires a cubic simulation box"
+        );
+        let num_points = TABLE_SIZE + 1;
+        let mut table = Vec::with_capacity(num_points.pow(3));
+        for i in 0..num_points {
+            for j in 0..num_points {
+                for k in 0..num_points {
+                    let x = MVec::new(i as f64, j as f64, k as f64) * 0.5 / TABLE_SIZE as f64;
+                    table.push(ewald_correction(x));
+                }
+            }
+        }
+        Self { box_size, table }
+    }
+
+    fn get(&self, i: usize, j: usize, k: usize) -> MVec {
+        let num_points = TABLE_SIZE + 1;
+        self.table[(i * num_points + j) * num_points + k]
+    }
+
+    /// The correction to the acceleration of a particle due to a
+    /// mass, given the (nearest image) distance vector from the mass
+    /// to the particle, i.e. the position of the particle minus that
+    /// of the mass.
+    pub fn correction(&self, distance: VecLength, mass: Mass) -> VecAcceleration {
+        let x = (distance / self.box_size).0;
+        let signs = MVec::new(x.x.signum(), x.y.signum(), x.z.signum());
+        let u = x.abs() * 2.0 * TABLE_SIZE as f64;
+        let index = |u: Float| (u.floor() as usize).min(TABLE_SIZE - 1);
+        let (i, j, k) = (index(u.x), index(u.y), index(u.z));
+        let (dx, dy, dz) = (u.x - i as f64, u.y - j as f64, u.z - k as f64);
+        let mut result = MVec::ZERO;
+        for (di, wx) in [(0, 1.0 - dx), (1, dx)] {
+            for (dj, wy) in [(0, 1.0 - dy), (1, dy)] {
+                for (dk, wz) in [(0, 1.0 - dz), (1, dz)] {
+                    result += self.get(i + di, j + dj, k + dk) * wx * wy * wz;
+                }
+            }
+        }
+        VecAcceleration::from_vector_and_scale(
+            result * signs,
+            GRAVITY_CONSTANT * mass / self.box_size.squared(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EwaldCorrection;
+    use crate::gravity::MultipoleOrder;
+    use crate::gravity::OpeningCriterion;
+    use crate::gravity::Softening;
+    use crate::gravity::Solver;
+    use crate::parameters::SimulationBox;
+    use crate::units::Dimensionless;
+    use crate::units::Length;
+    use crate::units::Mass;
+    use crate::units::VecAcceleration;
+    use crate::units::VecLength;
+    use crate::units::GRAVITY_CONSTANT;
+
+    #[test]
+    fn uniform_lattice_feels_no_force() {
+        let box_size = Length::meters(1.0);
+        let box_ = SimulationBox::cube_from_side_length(box_size);
+        let ewald_correction = EwaldCorrection::new(&box_);
+        // An even number of particles per dimension, so that some
+        // particles are exactly half a box size apart, for which the
+        // nearest image is ambiguous.
+        let num_per_dimension = 4;
+        let spacing = box_size / num_per_dimension as f64;
+        let positions: Vec<_> = (0..num_per_dimension)
+            .flat_map(|i| {
+                (0..num_per_dimension).flat_map(move |j| {
+                    (0..num_per_dimension).map(move |k| {
+                        VecLength::new(i as f64 * spacing, j as f64 * spacing, k as f64 * spacing)
+                    })
+                })
+            })
+            .collect();
+        let mass = Mass::kilograms(1.0);
+        let total_acceleration = |ewald_correction| {
+            let solver = Solver {
+                softening: Softening::Plummer,
+                opening_angle: Dimensionless::zero(),
+                opening_criterion: OpeningCriterion::Geometric,
+                relative_tolerance: Dimensionless::zero(),
+                multipole_order: MultipoleOrder::Monopole,
+                box_: box_.clone(),
+                ewald_correction,
+            };
+            positions
+                .iter()
+                .map(|pos| {
+                    solver.calc_gravity_acceleration(
+                        &positions[5],
+                        pos,
+                        mass,
+                        Length::zero(),
+                        Length::zero(),
+                    )
+                })
+                .sum::<VecAcceleration>()
+        };
+        let typical_acceleration = GRAVITY_CONSTANT * mass / spacing.squared();
+        let nearest_image = total_acceleration(None).length() / typical_acceleration;
+        let ewald = total_acceleration(Some(&ewald_correction)).length() / typical_acceleration;
+        assert!(nearest_image.value() > 1e-2);
+        assert!(ewald.value() < 1e-4);
+    }
+
+    #[test]
+    fn correction_pulls_towards_the_next_image() {
+        // For a particle at 0.4 L from the mass, the next image
+        // of the mass on the other side of the particle (at a
+        // distance of 0.6 L) pulls the particle away from the mass.
+        let box_size = Length::meters(1.0);
+        let box_ = SimulationBox::cube_from_side_length(box_size);
+        let ewald_correction = EwaldCorrection::new(&box_);
+        let mass = Mass::kilograms(1.0);
+        let pos_particle = VecLength::meters(0.7, 0.5, 0.5);
+        let pos_mass = VecLength::meters(0.3, 0.5, 0.5);
+        let correction =
+            ewald_correction.correction(box_.periodic_distance_vec(&pos_particle, &pos_mass), mass);
+        assert!(correction.x().value_unchecked() > 0.0);
+        let correction =
+            ewald_correction.correction(box_.periodic_distance_vec(&pos_mass, &pos_particle), mass);
+        assert!(correction.x().value_unchecked() < 0.0);
+    }
+}
@@ -3,15 +3,112 @@ use std::ops::AddAssign;
 
 use mpi::traits::Equivalence;
 
+use crate::prelude::Float;
+use crate::units::helpers::VecQuantity;
+use crate::units::Dimension;
 use crate::units::Mass;
+use crate::units::Quantity;
 use crate::units::VecLength;
 use crate::units::VecLengthMass;
+use crate::units::NONE;
+
+type MassLengthSquared = Quantity<
+    Float,
+    {
+        Dimension {
+            mass: 1,
+            length: 2,
+            ..NONE
+        }
+    },
+>;
+
+type VecMassLengthSquared = VecQuantity<
+    {
+        Dimension {
+            mass: 1,
+            length: 2,
+            ..NONE
+        }
+    },
+>;
+
+type VecMassLengthCubed = VecQuantity<
+    {
+        Dimension {
+            mass: 1,
+            length: 3,
+            ..NONE
+        }
+    },
+>;
+
+/// The symmetric tensor sum_i m_i d_i d_i^T of second moments of a
+/// mass distribution, where d_i is the position of the i-th particle
+/// relative to the center of mass. Stored column by column.
+#[derive(Clone, Default, Equivalence)]
+pub struct SecondMoments {
+    x: VecMassLengthSquared,
+    y: VecMassLengthSquared,
+    #[cfg(not(feature = "2d"))]
+    z: VecMassLengthSquared,
+}
+
+impl SecondMoments {
+    /// The second moments m d d^T of a point mass m at offset d.
+    fn of_point_mass(mass: Mass, d: VecLength) -> Self {
+        Self {
+            x: d * (mass * d.x()),
+            y: d * (mass * d.y()),
+            #[cfg(not(feature = "2d"))]
+            z: d * (mass * d.z()),
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.x += other.x;
+        self.y += other.y;
+        #[cfg(not(feature = "2d"))]
+        {
+            self.z += other.z;
+        }
+    }
+
+    fn trace(&self) -> MassLengthSquared {
+        #[cfg(feature = "2d")]
+        return self.x.x() + self.y.y();
+        #[cfg(not(feature = "2d"))]
+        return self.x.x() + self.y.y() + self.z.z();
+    }
+
+    /// The matrix-vector product S v.
+    fn apply(&self, v: VecLength) -> VecMassLengthCubed {
+        #[cfg(feature = "2d")]
+        return self.x * v.x() + self.y * v.y();
+        #[cfg(not(feature = "2d"))]
+        return self.x * v.x() + self.y * v.y() + self.z * v.z();
+    }
+}
+
+/// The traceless quadrupole moment
+/// Q = sum_i m_i (3 d_i d_i^T - |d_i|^2 I)
+/// of a mass distribution with respect to its center of mass,
+/// represented by its second moments.
+pub struct Quadrupole<'a>(&'a SecondMoments);
+
+impl<'a> Quadrupole<'a> {
+    /// The matrix-vector product Q v.
+    pub fn apply(&self, v: VecLength) -> VecMassLengthCubed {
+        self.0.apply(v) * 3.0 - v * self.0.trace()
+    }
+}
 
 #[derive(Clone, Default, Equivalence)]
 pub struct MassMoments {
     total: Mass,
     weighted_position_sum: VecLengthMass,
     count: usize,
+    second_moments: SecondMoments,
 }
 
 impl MassMoments {
@@ -26,10 +123,17 @@ impl MassMoments {
         self.weighted_position_sum / self.total
     }
 
+    pub fn quadrupole(&self) -> Quadrupole {
+        Quadrupole(&self.second_moments)
+    }
+
     pub fn add_mass_at(&mut self, pos: &VecLength, mass: &Mass) {
-        self.count += 1;
-        self.total += *mass;
-        self.weighted_position_sum += *pos * *mass;
+        *self += &MassMoments {
+            total: *mass,
+            weighted_position_sum: *pos * *mass,
+            count: 1,
+            second_moments: SecondMoments::default(),
+        };
     }
 
     pub fn count(&self) -> usize {
@@ -47,11 +151,36 @@ impl Sum<(Mass, VecLength)> for MassMoments {
     }
 }
 
+/// Combines the moments of two mass distributions. The second moments
+/// are shifted to the new center of mass using the parallel axis
+/// theorem, so that the result does not depend on the order in which
+/// moments are combined (up to floating point errors).
 impl AddAssign<&MassMoments> for MassMoments {
     fn add_assign(&mut self, rhs: &MassMoments) {
+        if rhs.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = rhs.clone();
+            return;
+        }
+        let lhs = self.clone();
         self.count += rhs.count;
         self.total += rhs.total;
         self.weighted_position_sum += rhs.weighted_position_sum;
+        self.second_moments.add(&rhs.second_moments);
+        if self.total == Mass::zero() {
+            return;
+        }
+        let center_of_mass = self.center_of_mass();
+        for moments in [&lhs, rhs] {
+            if moments.total != Mass::zero() {
+                self.second_moments.add(&SecondMoments::of_point_mass(
+                    moments.total,
+                    moments.center_of_mass() - center_of_mass,
+                ));
+            }
+        }
     }
 }
 
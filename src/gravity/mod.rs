@@ -5,11 +5,16 @@ use crate::communication::DataByRank;
 use crate::communication::ExchangeCommunicator;
 use crate::communication::Identified;
 use crate::communication::WorldRank;
+use crate::components::Acceleration;
+use crate::components::GravitationalAcceleration;
 use crate::components::Position;
-use crate::components::Timestep;
-use crate::components::Velocity;
+use crate::components::SofteningLength;
+use crate::components::Species;
+use crate::components::Work;
 use crate::domain::TopLevelIndices;
 use crate::parameters::SimulationBox;
+use crate::prelude::ActiveParticles;
+use crate::prelude::Float;
 use crate::prelude::Particles;
 use crate::quadtree::Node;
 use crate::quadtree::*;
@@ -20,114 +25,355 @@ use crate::units::VecAcceleration;
 use crate::units::VecLength;
 use crate::units::GRAVITY_CONSTANT;
 
+#[cfg(not(feature = "2d"))]
+mod ewald;
 pub(super) mod mass_moments;
 mod parameters;
 pub(super) mod plugin;
 mod quadtree;
+mod softening;
 #[cfg(test)]
 pub(crate) mod tests;
+mod timestep;
 
+#[cfg(not(feature = "2d"))]
+pub use ewald::EwaldCorrection;
 pub use mass_moments::MassMoments;
 pub use parameters::GravityParameters;
+pub use parameters::MultipoleOrder;
+pub use parameters::OpeningCriterion;
+pub use parameters::Periodicity;
 pub use plugin::GravityPlugin;
 pub use quadtree::LeafData;
 pub use quadtree::NodeData;
 pub use quadtree::QuadTree;
+pub use softening::Softening;
+pub(crate) use softening::SPLINE_SUPPORT_FACTOR;
 
-struct Solver {
-    softening_length: Length,
+struct Solver<'a> {
+    softening: Softening,
     opening_angle: Dimensionless,
+    opening_criterion: OpeningCriterion,
+    relative_tolerance: Dimensionless,
+    multipole_order: MultipoleOrder,
     box_: SimulationBox,
+    ewald_correction: Option<&'a EwaldCorrection>,
 }
 
-impl Solver {
-    pub fn new(parameters: &GravityParameters, box_: &SimulationBox) -> Self {
+impl<'a> Solver<'a> {
+    pub fn new(
+        parameters: &GravityParameters,
+        box_: &SimulationBox,
+        ewald_correction: Option<&'a EwaldCorrection>,
+    ) -> Self {
         Self {
-            softening_length: parameters.softening_length,
+            softening: parameters.softening,
             opening_angle: parameters.opening_angle,
+            opening_criterion: parameters.opening_criterion,
+            relative_tolerance: parameters.relative_tolerance,
+            multipole_order: parameters.multipole_order,
             box_: box_.clone(),
+            ewald_correction,
         }
     }
 }
 
-impl Solver {
+impl<'a> Solver<'a> {
+    /// The acceleration of a particle at pos1 with softening length
+    /// softening_length1 due to a mass at pos2 with softening length
+    /// softening_length2. The softened forces of the two softening
+    /// lengths are averaged, so that the forces between two particles
+    /// are antisymmetric.
     fn calc_gravity_acceleration(
         &self,
         pos1: &VecLength,
         pos2: &VecLength,
         mass2: units::Mass,
+        softening_length1: Length,
+        softening_length2: Length,
     ) -> VecAcceleration {
         let distance_vector = self.box_.periodic_distance_vec(pos1, pos2);
-        let distance = distance_vector.length() + self.softening_length;
-        -distance_vector * GRAVITY_CONSTANT * mass2 / distance.cubed()
+        let distance = distance_vector.length();
+        if distance == Length::zero() {
+            return VecAcceleration::zero();
+        }
+        let acceleration = -distance_vector
+            * GRAVITY_CONSTANT
+            * mass2
+            * self.softening.symmetric_inverse_distance_cubed(
+                distance,
+                softening_length1,
+                softening_length2,
+            );
+        match self.ewald_correction {
+            Some(ewald_correction) => {
+                acceleration + ewald_correction.correction(distance_vector, mass2)
+            }
+            None => acceleration,
+        }
     }
 
-    pub fn calc_gravity_acceleration_for_moments(
+    /// The acceleration due to the multipole expansion of a node
+    /// around its center of mass. With d = pos - center_of_mass and
+    /// the quadrupole moment Q, the quadrupole term of the
+    /// acceleration is G (Q d / |d|^5 - 5 / 2 (d^T Q d) d / |d|^7).
+    /// The monopole term is softened with the largest softening
+    /// length of the node.
+    pub fn calc_gravity_acceleration_for_node(
         &self,
         pos: &VecLength,
-        moments: &MassMoments,
+        node: &NodeData,
+        softening_length: Length,
     ) -> VecAcceleration {
-        self.calc_gravity_acceleration(pos, &moments.center_of_mass(), moments.total())
+        let moments = &node.moments;
+        let center_of_mass = moments.center_of_mass();
+        let monopole = self.calc_gravity_acceleration(
+            pos,
+            &center_of_mass,
+            moments.total(),
+            softening_length,
+            node.max_softening_length,
+        );
+        match self.multipole_order {
+            MultipoleOrder::Monopole => monopole,
+            MultipoleOrder::Quadrupole => {
+                let distance_vector = self.box_.periodic_distance_vec(pos, &center_of_mass);
+                let distance = distance_vector.length();
+                let quadrupole = moments.quadrupole().apply(distance_vector);
+                let distance_to_the_fifth = distance.cubed() * distance.squared();
+                let distance_to_the_seventh = distance_to_the_fifth * distance.squared();
+                monopole
+                    + (quadrupole / distance_to_the_fifth
+                        - distance_vector * (2.5 * quadrupole.dot(distance_vector))
+                            / distance_to_the_seventh)
+                        * GRAVITY_CONSTANT
+            }
+        }
     }
 
-    pub fn traverse_tree(&self, tree: &QuadTree, pos: &VecLength) -> VecAcceleration {
+    /// Computes the acceleration of a particle at the given position
+    /// with the given softening length due to all particles in the tree.
+    /// The softened forces of the two softening lengths of a
+    /// particle-particle interaction are averaged, which keeps the
+    /// forces symmetric. Nodes within the softening radius of the
+    /// particle or of any of their own particles are always opened.
+    /// The previous acceleration of the particle is only used by the
+    /// relative opening criterion. The number of
+    /// particle-particle and particle-node interactions is added to
+    /// num_interactions.
+    pub fn traverse_tree(
+        &self,
+        tree: &QuadTree,
+        pos: &VecLength,
+        softening_length: Length,
+        previous_acceleration: units::Acceleration,
+        num_interactions: &mut usize,
+    ) -> VecAcceleration {
         match tree.node {
             Node::Tree(ref children) => children
                 .iter()
                 .map(|child| {
-                    if self.should_be_opened(child, pos) {
-                        self.traverse_tree(child, pos)
+                    if self.should_be_opened(child, pos, softening_length, previous_acceleration) {
+                        self.traverse_tree(
+                            child,
+                            pos,
+                            softening_length,
+                            previous_acceleration,
+                            num_interactions,
+                        )
                     } else {
-                        self.calc_gravity_acceleration_for_moments(pos, &child.data.moments)
+                        *num_interactions += 1;
+                        self.calc_gravity_acceleration_for_node(pos, &child.data, softening_length)
                     }
                 })
                 .sum(),
-            Node::Leaf(ref leaf) => leaf
-                .iter()
-                .map(|particle| self.calc_gravity_acceleration(pos, &particle.pos, particle.mass))
-                .sum(),
+            Node::Leaf(ref leaf) => {
+                *num_interactions += leaf.len();
+                leaf.iter()
+                    .map(|particle| {
+                        self.calc_gravity_acceleration(
+                            pos,
+                            &particle.pos,
+                            particle.mass,
+                            softening_length,
+                            particle.softening_length,
+                        )
+                    })
+                    .sum()
+            }
         }
     }
 
-    fn should_be_opened(&self, child: &QuadTree, pos: &VecLength) -> bool {
-        let distance = self.box_.periodic_distance(pos, &child.extent.center());
+    fn should_be_opened(
+        &self,
+        child: &QuadTree,
+        pos: &VecLength,
+        softening_length: Length,
+        previous_acceleration: units::Acceleration,
+    ) -> bool {
+        // The multipole expansion does not converge for particles
+        // inside the node, regardless of what the criteria say.
+        if child.extent.contains(pos) {
+            return true;
+        }
+        // Neither is it accurate if the softening of any of the
+        // interactions modifies the force.
+        let softening_length = softening_length.max(child.data.max_softening_length);
+        if self.is_within_radius_of_node(
+            child,
+            pos,
+            self.softening.support_radius(softening_length),
+        ) {
+            return true;
+        }
         let length = child.extent.max_side_length();
-        length / distance > self.opening_angle
+        match self.opening_criterion {
+            OpeningCriterion::Relative if previous_acceleration != units::Acceleration::zero() => {
+                let distance = self
+                    .box_
+                    .periodic_distance(pos, &child.data.moments.center_of_mass());
+                let error_estimate =
+                    GRAVITY_CONSTANT * child.data.moments.total() * length.squared()
+                        / distance.squared().squared();
+                error_estimate > self.relative_tolerance * previous_acceleration
+            }
+            _ => {
+                let distance = self.box_.periodic_distance(pos, &child.extent.center());
+                length / distance > self.opening_angle
+            }
+        }
+    }
+}
+
+impl<'a> Solver<'a> {
+    /// Whether the position lies within the extent of the node,
+    /// enlarged by the given radius in every direction.
+    fn is_within_radius_of_node(&self, node: &QuadTree, pos: &VecLength, radius: Length) -> bool {
+        let distance = self.box_.periodic_distance_vec(pos, &node.extent.center());
+        let side_lengths = node.extent.side_lengths();
+        let within =
+            |distance: Length, side_length: Length| distance.abs() < 0.5 * side_length + radius;
+        let within_2d =
+            within(distance.x(), side_lengths.x()) && within(distance.y(), side_lengths.y());
+        #[cfg(feature = "2d")]
+        return within_2d;
+        #[cfg(not(feature = "2d"))]
+        return within_2d && within(distance.z(), side_lengths.z());
     }
 }
 
 #[derive(Equivalence, Debug)]
 pub(super) struct GravityCalculationRequest {
     pos: VecLength,
+    softening_length: Length,
+    previous_acceleration: units::Acceleration,
     index: QuadTreeIndex,
 }
 
 #[derive(Equivalence, Debug)]
 pub(super) struct GravityCalculationReply {
     acc: VecAcceleration,
+    num_interactions: usize,
+}
+
+/// Ewald summation is not available in 2D (see
+/// [Periodicity](crate::parameters::Periodicity)), so there are
+/// no values of this type.
+#[cfg(feature = "2d")]
+#[derive(Resource)]
+pub enum EwaldCorrection {}
+
+#[cfg(feature = "2d")]
+impl EwaldCorrection {
+    pub fn correction(&self, _distance: VecLength, _mass: units::Mass) -> VecAcceleration {
+        match *self {}
+    }
+}
+
+/// Tabulates the Ewald correction if periodic gravity is requested.
+#[cfg(not(feature = "2d"))]
+pub(super) fn insert_ewald_correction_system(
+    mut commands: Commands,
+    parameters: Res<GravityParameters>,
+    box_: Res<SimulationBox>,
+) {
+    if parameters.periodicity == Periodicity::Ewald {
+        commands.insert_resource(EwaldCorrection::new(&box_));
+    }
 }
 
-pub(super) fn gravity_system(
+pub(super) fn insert_gravitational_acceleration_system(
+    mut commands: Commands,
+    particles: Particles<Entity, Without<GravitationalAcceleration>>,
+) {
+    for entity in particles.iter() {
+        commands
+            .entity(entity)
+            .insert(GravitationalAcceleration(VecAcceleration::zero()));
+    }
+}
+
+/// Gives all particles without an individual softening length the
+/// softening length of their species from the parameters.
+pub(super) fn insert_softening_length_system(
+    mut commands: Commands,
+    particles: Particles<(Entity, Option<&Species>), Without<SofteningLength>>,
+    parameters: Res<GravityParameters>,
+) {
+    for (entity, species) in particles.iter() {
+        let softening_length = parameters
+            .species_softening_lengths
+            .get(&species.copied().unwrap_or_default())
+            .copied()
+            .unwrap_or(parameters.softening_length);
+        commands
+            .entity(entity)
+            .insert(SofteningLength(softening_length));
+    }
+}
+
+pub(crate) fn gravity_system(
     tree: Res<QuadTree>,
     world_rank: Res<WorldRank>,
     indices: Res<TopLevelIndices>,
-    mut particles: Particles<(Entity, &Position, &mut Velocity, &Timestep)>,
+    mut particles: ActiveParticles<(
+        Entity,
+        &Position,
+        &SofteningLength,
+        &mut Acceleration,
+        &mut GravitationalAcceleration,
+        &mut Work,
+    )>,
     parameters: Res<GravityParameters>,
     mut request_comm: ExchangeCommunicator<Identified<GravityCalculationRequest>>,
     mut reply_comm: ExchangeCommunicator<Identified<GravityCalculationReply>>,
     box_: Res<SimulationBox>,
+    ewald_correction: Option<Res<EwaldCorrection>>,
 ) {
-    let gravity = Solver::new(&parameters, &box_);
+    let gravity = Solver::new(&parameters, &box_, ewald_correction.as_deref());
     let mut outgoing_requests = DataByRank::from_communicator(&*request_comm);
-    let add_acceleration = |vel: &mut Velocity, acceleration, timestep: &Timestep| {
-        **vel += acceleration * **timestep;
-    };
-    for (entity, pos, mut vel, timestep) in particles.iter_mut() {
+    for (entity, pos, softening_length, mut acc, mut gravitational_acc, mut work) in
+        particles.iter_mut()
+    {
+        let previous_acceleration = gravitational_acc.length();
+        let mut local_acc = VecAcceleration::zero();
+        let mut num_interactions = 0;
         for (rank, index) in indices.flat_iter() {
             let sub_tree = &tree[index];
             if rank == **world_rank {
-                add_acceleration(&mut vel, gravity.traverse_tree(sub_tree, pos), timestep);
-            } else if gravity.should_be_opened(sub_tree, pos) {
+                local_acc += gravity.traverse_tree(
+                    sub_tree,
+                    pos,
+                    **softening_length,
+                    previous_acceleration,
+                    &mut num_interactions,
+                );
+            } else if gravity.should_be_opened(
+                sub_tree,
+                pos,
+                **softening_length,
+                previous_acceleration,
+            ) {
                 outgoing_requests.push(
                     rank,
                     Identified::new(
@@ -135,17 +381,23 @@ pub(super) fn gravity_system(
                         GravityCalculationRequest {
                             index: *index,
                             pos: *pos.clone(),
+                            softening_length: **softening_length,
+                            previous_acceleration,
                         },
                     ),
                 );
             } else {
-                add_acceleration(
-                    &mut vel,
-                    gravity.calc_gravity_acceleration_for_moments(pos, &sub_tree.data.moments),
-                    timestep,
+                num_interactions += 1;
+                local_acc += gravity.calc_gravity_acceleration_for_node(
+                    pos,
+                    &sub_tree.data,
+                    **softening_length,
                 );
             }
         }
+        **acc += local_acc;
+        **gravitational_acc = local_acc;
+        **work += num_interactions as Float;
     }
     let num_outgoing_requests = outgoing_requests.size();
     let incoming_requests = request_comm.exchange_all(outgoing_requests);
@@ -153,12 +405,22 @@ pub(super) fn gravity_system(
     for (rank, requests) in incoming_requests {
         for request in requests {
             let tree = &tree[&request.data.index];
-            let acc = gravity.traverse_tree(tree, &request.data.pos);
+            let mut num_interactions = 0;
+            let acc = gravity.traverse_tree(
+                tree,
+                &request.data.pos,
+                request.data.softening_length,
+                request.data.previous_acceleration,
+                &mut num_interactions,
+            );
             result.push(
                 rank,
                 Identified {
                     key: request.key,
-                    data: GravityCalculationReply { acc },
+                    data: GravityCalculationReply {
+                        acc,
+                        num_interactions,
+                    },
                 },
             );
         }
@@ -168,8 +430,11 @@ pub(super) fn gravity_system(
     for (_, accelerations) in accelerations.iter() {
         for acc in accelerations {
             let entity = acc.entity();
-            let (_, _, mut vel, timestep) = particles.get_mut(entity).unwrap();
-            add_acceleration(&mut vel, acc.data.acc, timestep);
+            let (_, _, _, mut particle_acc, mut gravitational_acc, mut work) =
+                particles.get_mut(entity).unwrap();
+            **particle_acc += acc.data.acc;
+            **gravitational_acc += acc.data.acc;
+            **work += acc.data.num_interactions as Float;
         }
     }
 }
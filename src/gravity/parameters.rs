@@ -1,5 +1,9 @@
+use std::collections::HashMap;
+
 use derive_custom::raxiom_parameters;
 
+use super::softening::Softening;
+use crate::components::Species;
 use crate::units::Dimensionless;
 use crate::units::Length;
 
@@ -8,16 +12,95 @@ use crate::units::Length;
 /// to the simulation.
 #[raxiom_parameters("gravity")]
 pub struct GravityParameters {
-    /// The minimum length in the gravity calculations. Should be
-    /// large enough to prevent extremely high accelerations on
-    /// particles that are very close, but low enough for the results
-    /// to still be accurate.
+    /// The softening length of all particles which are not given
+    /// an individual softening length. Should be large enough to
+    /// prevent extremely high accelerations on particles that are
+    /// very close, but low enough for the results to still be
+    /// accurate.
     #[serde(default)]
     pub softening_length: Length,
+    /// Softening lengths for the particles of individual species,
+    /// which take precedence over the
+    /// [softening_length](GravityParameters::softening_length).
+    /// Example value: {dark_matter: 1 kpc, star: 0.1 kpc}
+    #[serde(default)]
+    pub species_softening_lengths: HashMap<Species, Length>,
+    /// The form of the softened gravitational force.
+    #[serde(default)]
+    pub softening: Softening,
     /// During the tree walk in the gravity calculation, any encountered node
     /// which is seen from the particle under an angle less than the opening_angle
     /// (meaning the node is far away compared to its size), will not be opened
     /// and the force will instead be approximated by mass moments of the node.
     #[serde(default)]
     pub opening_angle: Dimensionless,
+    /// The criterion which decides whether a node is opened during
+    /// the tree walk.
+    #[serde(default)]
+    pub opening_criterion: OpeningCriterion,
+    /// The tolerance alpha of the relative opening criterion.
+    /// Only used if the
+    /// [opening_criterion](GravityParameters::opening_criterion)
+    /// is [Relative](OpeningCriterion::Relative).
+    #[serde(default = "default_relative_tolerance")]
+    pub relative_tolerance: Dimensionless,
+    /// The highest order of the multipole expansion used to
+    /// approximate the force of nodes which are not opened.
+    /// Defaults to [Monopole](MultipoleOrder::Monopole).
+    #[serde(default)]
+    pub multipole_order: MultipoleOrder,
+    /// How the periodic images of the particles are taken into
+    /// account.
+    #[serde(default)]
+    pub periodicity: Periodicity,
+}
+
+fn default_relative_tolerance() -> Dimensionless {
+    Dimensionless::dimensionless(0.0025)
+}
+
+#[derive(Default, Copy, Debug, PartialEq, Eq)]
+#[raxiom_parameters]
+pub enum OpeningCriterion {
+    /// Open a node if it is seen from the particle under an angle
+    /// larger than the
+    /// [opening_angle](GravityParameters::opening_angle).
+    #[default]
+    Geometric,
+    /// The relative criterion of Gadget-2 (Springel 2005): Open a
+    /// node of mass M and side length l at distance r if the estimated
+    /// error G M l^2 / r^4 of its force exceeds alpha |a_old|, where
+    /// |a_old| is the gravitational acceleration of the particle in
+    /// the previous force calculation and alpha is the
+    /// [relative_tolerance](GravityParameters::relative_tolerance).
+    /// Falls back to the geometric criterion for particles without a
+    /// previous acceleration.
+    Relative,
+}
+
+#[derive(Default, Copy, Debug, PartialEq, Eq)]
+#[raxiom_parameters]
+pub enum MultipoleOrder {
+    /// Approximate nodes by their total mass at the center of mass.
+    #[default]
+    Monopole,
+    /// Additionally take the quadrupole moment of the nodes into
+    /// account. This is more expensive per interaction, but allows
+    /// for larger opening angles at the same accuracy.
+    Quadrupole,
+}
+
+#[derive(Default, Copy, Debug, PartialEq, Eq)]
+#[raxiom_parameters]
+pub enum Periodicity {
+    /// Only take the nearest periodic image of every particle
+    /// into account. Cheap, but a uniform density distribution
+    /// will feel spurious forces.
+    #[default]
+    NearestImage,
+    /// Add the Ewald correction to the force of the nearest image,
+    /// which takes all periodic images into account. Only available
+    /// for cubic boxes in 3D, the option does not exist in 2D builds.
+    #[cfg(not(feature = "2d"))]
+    Ewald,
 }
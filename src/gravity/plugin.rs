@@ -1,18 +1,28 @@
 use bevy::prelude::IntoSystemDescriptor;
 
 use super::gravity_system;
+#[cfg(not(feature = "2d"))]
+use super::insert_ewald_correction_system;
+use super::insert_gravitational_acceleration_system;
+use super::insert_softening_length_system;
 use super::parameters::GravityParameters;
+use super::timestep::AccelerationCriterion;
 use super::GravityCalculationReply;
 use super::GravityCalculationRequest;
 use crate::communication::CommunicationPlugin;
 use crate::communication::Identified;
+use crate::components::GravitationalAcceleration;
+use crate::components::SofteningLength;
 use crate::domain::communicate_mass_moments_system;
 use crate::domain::construct_quad_tree_system;
 use crate::named::Named;
+use crate::prelude::SimulationStartupStages;
 use crate::simulation::RaxiomPlugin;
 use crate::simulation::Simulation;
-use crate::simulation_plugin::integrate_motion_system;
+use crate::simulation_plugin::reset_accelerations_system;
+use crate::simulation_plugin::reset_work_system;
 use crate::simulation_plugin::SimulationStages;
+use crate::timestep::TimestepPlugin;
 
 #[derive(Named)]
 pub struct GravityPlugin;
@@ -20,6 +30,16 @@ pub struct GravityPlugin;
 impl RaxiomPlugin for GravityPlugin {
     fn build_everywhere(&self, sim: &mut Simulation) {
         sim.add_parameter_type::<GravityParameters>()
+            .add_derived_component::<SofteningLength>()
+            .add_component_no_io::<GravitationalAcceleration>()
+            .add_startup_system_to_stage(
+                SimulationStartupStages::InsertDerivedComponents,
+                insert_softening_length_system,
+            )
+            .add_startup_system_to_stage(
+                SimulationStartupStages::InsertDerivedComponents,
+                insert_gravitational_acceleration_system,
+            )
             .add_system_to_stage(
                 SimulationStages::ForceCalculation,
                 construct_quad_tree_system,
@@ -32,9 +52,13 @@ impl RaxiomPlugin for GravityPlugin {
                 SimulationStages::ForceCalculation,
                 gravity_system
                     .after(communicate_mass_moments_system)
-                    .before(integrate_motion_system),
+                    .after(reset_accelerations_system)
+                    .after(reset_work_system),
             )
             .add_plugin(CommunicationPlugin::<Identified<GravityCalculationRequest>>::exchange())
-            .add_plugin(CommunicationPlugin::<Identified<GravityCalculationReply>>::exchange());
+            .add_plugin(CommunicationPlugin::<Identified<GravityCalculationReply>>::exchange())
+            .add_plugin(TimestepPlugin::<AccelerationCriterion>::default());
+        #[cfg(not(feature = "2d"))]
+        sim.add_startup_system(insert_ewald_correction_system);
     }
 }
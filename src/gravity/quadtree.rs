@@ -1,9 +1,12 @@
 use bevy::prelude::Entity;
+use mpi::traits::Equivalence;
 
 use crate::gravity::MassMoments;
+use crate::prelude::Float;
 use crate::quadtree::LeafDataType;
 use crate::quadtree::NodeDataType;
 use crate::quadtree::{self};
+use crate::units::Length;
 use crate::units::Mass;
 use crate::units::VecLength;
 
@@ -14,11 +17,16 @@ pub struct LeafData {
     pub entity: Entity,
     pub mass: Mass,
     pub pos: VecLength,
+    pub softening_length: Length,
+    pub work: Float,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Equivalence)]
 pub struct NodeData {
     pub moments: MassMoments,
+    pub work: Float,
+    /// The largest softening length of all particles in the node.
+    pub max_softening_length: Length,
 }
 
 impl LeafDataType for LeafData {
@@ -30,5 +38,7 @@ impl LeafDataType for LeafData {
 impl NodeDataType<LeafData> for NodeData {
     fn update_with(&mut self, leaf: &LeafData) {
         self.moments.add_mass_at(&leaf.pos, &leaf.mass);
+        self.work += leaf.work;
+        self.max_softening_length = self.max_softening_length.max(leaf.softening_length);
     }
 }
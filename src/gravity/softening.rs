@@ -0,0 +1,220 @@
+use derive_custom::raxiom_parameters;
+
+use crate::prelude::Float;
+use crate::units::Dimension;
+use crate::units::Length;
+use crate::units::NumberDensity3D;
+use crate::units::Quantity;
+use crate::units::NONE;
+
+type InverseLength = Quantity<Float, { Dimension { length: -1, ..NONE } }>;
+type InverseArea = Quantity<Float, { Dimension { length: -2, ..NONE } }>;
+
+/// The factor between the softening length and the radius of the
+/// compact support of the spline kernel. Chosen such that the
+/// potential of a point mass at the origin matches that of a
+/// Plummer sphere with the same softening length.
+pub(crate) const SPLINE_SUPPORT_FACTOR: f64 = 2.8;
+
+/// The form of the gravitational softening.
+#[derive(Default, Copy, Debug, PartialEq, Eq)]
+#[raxiom_parameters]
+pub enum Softening {
+    /// Plummer softening, i.e. forces proportional to
+    /// r / (r^2 + epsilon^2)^(3 / 2).
+    #[default]
+    Plummer,
+    /// The spline softening of Gadget (Springel 2001), in which the
+    /// mass of a particle is smoothed with the cubic spline kernel
+    /// on a support radius of 2.8 epsilon. Forces are exactly
+    /// Newtonian beyond the support radius.
+    Spline,
+}
+
+impl Softening {
+    /// The softened version of 1 / r^3, such that the acceleration
+    /// due to a mass m at distance vector r is given by
+    /// -G m r inverse_distance_cubed(|r|, epsilon).
+    pub fn inverse_distance_cubed(
+        &self,
+        distance: Length,
+        softening_length: Length,
+    ) -> NumberDensity3D {
+        match self {
+            Softening::Plummer => {
+                let distance_squared = distance.squared() + softening_length.squared();
+                1.0 / (distance_squared * distance_squared.sqrt())
+            }
+            Softening::Spline => {
+                let support_radius = SPLINE_SUPPORT_FACTOR * softening_length;
+                if distance >= support_radius {
+                    return 1.0 / distance.cubed();
+                }
+                let u = (distance / support_radius).value();
+                let factor = if u < 0.5 {
+                    10.666666666667 + u.powi(2) * (32.0 * u - 38.4)
+                } else {
+                    21.333333333333 - 48.0 * u + 38.4 * u.powi(2)
+                        - 10.666666666667 * u.powi(3)
+                        - 0.066666666667 / u.powi(3)
+                };
+                factor / support_radius.cubed()
+            }
+        }
+    }
+
+    /// The softened version of 1 / r, such that the potential due
+    /// to a mass m at distance r is given by -G m inverse_distance(r, epsilon).
+    /// Its derivative with respect to r is -r inverse_distance_cubed(r, epsilon).
+    pub fn inverse_distance(&self, distance: Length, softening_length: Length) -> InverseLength {
+        match self {
+            Softening::Plummer => 1.0 / (distance.squared() + softening_length.squared()).sqrt(),
+            Softening::Spline => {
+                let support_radius = SPLINE_SUPPORT_FACTOR * softening_length;
+                if distance >= support_radius {
+                    return 1.0 / distance;
+                }
+                let u = (distance / support_radius).value();
+                let factor = if u < 0.5 {
+                    2.8 - u.powi(2) * (5.333333333333 + u.powi(2) * (6.4 * u - 9.6))
+                } else {
+                    3.2 - 0.066666666667 / u
+                        - u.powi(2)
+                            * (10.666666666667 + u * (-16.0 + u * (9.6 - 2.133333333333 * u)))
+                };
+                factor / support_radius
+            }
+        }
+    }
+
+    /// The derivative of
+    /// [inverse_distance](Softening::inverse_distance) with respect
+    /// to the softening length, which is needed for the correction
+    /// terms of adaptive softening lengths. It is negative, since a
+    /// larger softening length makes the potential shallower.
+    pub fn inverse_distance_softening_derivative(
+        &self,
+        distance: Length,
+        softening_length: Length,
+    ) -> InverseArea {
+        match self {
+            Softening::Plummer => {
+                -softening_length * self.inverse_distance_cubed(distance, softening_length)
+            }
+            Softening::Spline => {
+                let support_radius = SPLINE_SUPPORT_FACTOR * softening_length;
+                if distance >= support_radius {
+                    return InverseArea::zero();
+                }
+                let u = (distance / support_radius).value();
+                let factor = if u < 0.5 {
+                    -2.8 + u.powi(2) * (16.0 + u.powi(2) * (38.4 * u - 48.0))
+                } else {
+                    -3.2 + u.powi(2) * (32.0 + u * (-64.0 + u * (48.0 - 12.8 * u)))
+                };
+                SPLINE_SUPPORT_FACTOR * factor / support_radius.squared()
+            }
+        }
+    }
+
+    /// The symmetrised version of
+    /// [inverse_distance_cubed](Softening::inverse_distance_cubed)
+    /// for an interaction between two particles with different
+    /// softening lengths. Averaging the forces (instead of the
+    /// softening lengths) makes them the gradient of the pairwise
+    /// potential -G m_1 m_2 (phi(r, epsilon_1) + phi(r, epsilon_2)) / 2
+    /// (Price & Monaghan 2007), so that both momentum and energy are
+    /// conserved.
+    pub fn symmetric_inverse_distance_cubed(
+        &self,
+        distance: Length,
+        softening_length1: Length,
+        softening_length2: Length,
+    ) -> NumberDensity3D {
+        if softening_length1 == softening_length2 {
+            return self.inverse_distance_cubed(distance, softening_length1);
+        }
+        0.5 * (self.inverse_distance_cubed(distance, softening_length1)
+            + self.inverse_distance_cubed(distance, softening_length2))
+    }
+
+    /// The distance beyond which the softening does not modify
+    /// the forces of a particle with the given softening length.
+    /// Plummer softening has no compact support, but its forces are
+    /// within 20 percent of the Newtonian forces beyond the support
+    /// radius of the equivalent spline softening.
+    pub fn support_radius(&self, softening_length: Length) -> Length {
+        SPLINE_SUPPORT_FACTOR * softening_length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Softening;
+    use super::SPLINE_SUPPORT_FACTOR;
+    use crate::units::Length;
+
+    #[test]
+    fn softened_potential_is_consistent_with_forces() {
+        let softening_length = Length::meters(1.0);
+        let step = Length::meters(1e-6);
+        for softening in [Softening::Plummer, Softening::Spline] {
+            for i in 1..40 {
+                let distance = Length::meters(i as f64 * 0.1);
+                let potential = |distance, softening_length| {
+                    softening
+                        .inverse_distance(distance, softening_length)
+                        .value()
+                };
+                let radial_derivative = (potential(distance + step, softening_length)
+                    - potential(distance - step, softening_length))
+                    / (2.0 * step.value());
+                let expected = -(distance
+                    * softening.inverse_distance_cubed(distance, softening_length))
+                .value();
+                assert!((radial_derivative - expected).abs() < 1e-6);
+                let softening_derivative = (potential(distance, softening_length + step)
+                    - potential(distance, softening_length - step))
+                    / (2.0 * step.value());
+                let expected = softening
+                    .inverse_distance_softening_derivative(distance, softening_length)
+                    .value();
+                assert!((softening_derivative - expected).abs() < 1e-6);
+            }
+            // The potential of a point mass at the origin.
+            let central = softening.inverse_distance(Length::zero(), softening_length);
+            assert!((central.value() - 1.0).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn spline_softening_is_newtonian_beyond_support_radius() {
+        let softening_length = Length::meters(1.0);
+        for factor in [1.0, 1.5, 10.0] {
+            let distance = SPLINE_SUPPORT_FACTOR * softening_length * factor;
+            let softened = Softening::Spline.inverse_distance_cubed(distance, softening_length);
+            assert_eq!(softened, 1.0 / distance.cubed());
+        }
+    }
+
+    #[test]
+    fn softened_forces_are_continuous_and_bounded() {
+        let softening_length = Length::meters(1.0);
+        for softening in [Softening::Plummer, Softening::Spline] {
+            let force = |distance: Length| {
+                (distance * softening.inverse_distance_cubed(distance, softening_length)).value()
+            };
+            let mut previous = force(Length::meters(1e-3));
+            for i in 2..1000 {
+                let distance = Length::meters(i as f64 * 1e-2);
+                let current = force(distance);
+                assert!((current - previous).abs() < 0.1);
+                assert!(current < 1.0);
+                previous = current;
+            }
+            let distance = Length::meters(100.0);
+            let newtonian = 1.0 / distance.squared().value();
+            assert!((force(distance) - newtonian).abs() / newtonian < 1e-3);
+        }
+    }
+}
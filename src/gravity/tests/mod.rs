@@ -5,7 +5,11 @@ use bevy::prelude::Entity;
 use super::LeafData;
 use super::QuadTree;
 use crate::domain::extent::Extent;
+use crate::gravity::MultipoleOrder;
+use crate::gravity::OpeningCriterion;
+use crate::gravity::Softening;
 use crate::gravity::Solver;
+use crate::parameters::SimulationBox;
 use crate::quadtree;
 use crate::quadtree::QuadTreeConfig;
 use crate::test_utils::assert_is_close;
@@ -26,6 +30,8 @@ pub(crate) fn get_particles(n: i32, m: i32) -> Vec<LeafData> {
                 #[cfg(not(feature = "2d"))]
                 pos: VecLength::meters(x as f64, y as f64, x as f64 * y as f64),
                 mass: Mass::kilograms(x as f64 * y as f64),
+                softening_length: Length::zero(),
+                work: 0.0,
             })
         })
         .collect()
@@ -71,10 +77,23 @@ fn compare_quadtree_gravity_to_direct_sum() {
     let pos = VecLength::meters(3.5, 3.5, 3.5);
     let solver = Solver {
         opening_angle: Dimensionless::zero(),
-        softening_length: Length::zero(),
+        opening_criterion: OpeningCriterion::Geometric,
+        relative_tolerance: Dimensionless::zero(),
+        softening: Softening::Plummer,
+        multipole_order: MultipoleOrder::Monopole,
         box_: tree.extent.clone().into(),
+        ewald_correction: None,
     };
-    let acc1 = solver.traverse_tree(&tree, &pos);
+    let mut num_interactions = 0;
+    let acc1 = solver.traverse_tree(
+        &tree,
+        &pos,
+        Length::zero(),
+        Acceleration::zero(),
+        &mut num_interactions,
+    );
+    // With an opening angle of zero, every particle is visited.
+    assert_eq!(num_interactions, (n_particles * n_particles) as usize);
     let acc2 = direct_sum(
         &solver,
         &pos,
@@ -86,6 +105,122 @@ fn compare_quadtree_gravity_to_direct_sum() {
     compare_accelerations(acc1, acc2);
 }
 
+#[test]
+fn quadrupole_moments_improve_accuracy() {
+    let n_particles = 10;
+    let tree = get_tree_for_particles(n_particles);
+    #[cfg(feature = "2d")]
+    let pos = VecLength::meters(3000.0, 2000.0);
+    #[cfg(not(feature = "2d"))]
+    let pos = VecLength::meters(3000.0, 2000.0, 2500.0);
+    let relative_error = |multipole_order| {
+        let solver = Solver {
+            opening_angle: Dimensionless::dimensionless(1.0),
+            opening_criterion: OpeningCriterion::Geometric,
+            relative_tolerance: Dimensionless::zero(),
+            softening: Softening::Plummer,
+            multipole_order,
+            box_: SimulationBox::cube_from_side_length_centered(Length::meters(1e5)),
+            ewald_correction: None,
+        };
+        let acc1 = solver.traverse_tree(&tree, &pos, Length::zero(), Acceleration::zero(), &mut 0);
+        let acc2 = direct_sum(
+            &solver,
+            &pos,
+            get_particles(n_particles, n_particles)
+                .iter()
+                .map(|part| (part.pos, part.mass))
+                .collect(),
+        );
+        ((acc1 - acc2).length() / acc2.length()).value()
+    };
+    let monopole_error = relative_error(MultipoleOrder::Monopole);
+    let quadrupole_error = relative_error(MultipoleOrder::Quadrupole);
+    assert!(quadrupole_error < 0.2 * monopole_error);
+}
+
+#[test]
+fn relative_opening_criterion_is_accurate() {
+    let n_particles = 10;
+    let tree = get_tree_for_particles(n_particles);
+    #[cfg(feature = "2d")]
+    let pos = VecLength::meters(5.5, 5.5);
+    #[cfg(not(feature = "2d"))]
+    let pos = VecLength::meters(5.5, 5.5, 30.0);
+    let solver = Solver {
+        // Would never open any node on its own.
+        opening_angle: Dimensionless::dimensionless(1e5),
+        opening_criterion: OpeningCriterion::Relative,
+        relative_tolerance: Dimensionless::dimensionless(1e-4),
+        softening: Softening::Plummer,
+        multipole_order: MultipoleOrder::Monopole,
+        box_: SimulationBox::cube_from_side_length_centered(Length::meters(1e5)),
+        ewald_correction: None,
+    };
+    let exact = direct_sum(
+        &solver,
+        &pos,
+        get_particles(n_particles, n_particles)
+            .iter()
+            .map(|part| (part.pos, part.mass))
+            .collect(),
+    );
+    let acc = solver.traverse_tree(&tree, &pos, Length::zero(), exact.length(), &mut 0);
+    assert!(((acc - exact).length() / exact.length()).value() < 1e-2);
+}
+
+#[test]
+fn nodes_within_softening_radius_are_opened() {
+    let n_particles = 10;
+    let softening_length = Length::meters(100.0);
+    let particles: Vec<_> = get_particles(n_particles, n_particles)
+        .into_iter()
+        .map(|particle| LeafData {
+            softening_length,
+            ..particle
+        })
+        .collect();
+    let extent = Extent::from_positions(particles.iter().map(|part| &part.pos)).unwrap();
+    let tree = QuadTree::new(&QuadTreeConfig::default(), particles.clone(), &extent);
+    #[cfg(feature = "2d")]
+    let pos = VecLength::meters(100.0, 100.0);
+    #[cfg(not(feature = "2d"))]
+    let pos = VecLength::meters(100.0, 100.0, 50.0);
+    let solver = Solver {
+        // Would never open any node on its own.
+        opening_angle: Dimensionless::dimensionless(1e5),
+        opening_criterion: OpeningCriterion::Geometric,
+        relative_tolerance: Dimensionless::zero(),
+        softening: Softening::Spline,
+        multipole_order: MultipoleOrder::Monopole,
+        box_: SimulationBox::cube_from_side_length_centered(Length::meters(1e5)),
+        ewald_correction: None,
+    };
+    let mut num_interactions = 0;
+    let acc = solver.traverse_tree(
+        &tree,
+        &pos,
+        Length::zero(),
+        Acceleration::zero(),
+        &mut num_interactions,
+    );
+    // The position lies within the softening radius of every particle.
+    assert_eq!(num_interactions, (n_particles * n_particles) as usize);
+    let exact = particles
+        .iter()
+        .map(|part| {
+            solver.calc_gravity_acceleration(
+                &pos,
+                &part.pos,
+                part.mass,
+                Length::zero(),
+                softening_length,
+            )
+        })
+        .sum();
+    compare_accelerations(acc, exact);
+}
+
 pub(super) fn compare_accelerations(acc1: VecAcceleration, acc2: VecAcceleration) {
     let min_acc = Acceleration::meters_per_second_squared(1e-15);
     let relative_diff = (acc1 - acc2).length() / (acc1.length() + acc2.length() + min_acc);
@@ -99,7 +234,7 @@ pub(super) fn direct_sum(
 ) -> VecAcceleration {
     let mut total = VecAcceleration::zero();
     for (pos, mass) in other_positions.into_iter() {
-        total += solver.calc_gravity_acceleration(pos1, &pos, mass);
+        total += solver.calc_gravity_acceleration(pos1, &pos, mass, Length::zero(), Length::zero());
     }
     total
 }
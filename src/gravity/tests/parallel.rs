@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use bevy::prelude::Commands;
 use bevy::prelude::Component;
 use bevy::prelude::Res;
@@ -10,13 +12,17 @@ use super::get_particles;
 use crate::communication::local_sim_building::build_local_communication_sim_with_custom_logic;
 use crate::communication::WorldRank;
 use crate::components;
+use crate::components::Acceleration;
 use crate::components::Position;
-use crate::components::Timestep;
 use crate::components::Velocity;
 use crate::domain::DomainDecompositionPlugin;
 use crate::gravity::plugin::GravityPlugin;
 use crate::gravity::GravityParameters;
 use crate::gravity::LeafData;
+use crate::gravity::MultipoleOrder;
+use crate::gravity::OpeningCriterion;
+use crate::gravity::Periodicity;
+use crate::gravity::Softening;
 use crate::gravity::Solver;
 use crate::prelude::Extent;
 use crate::prelude::LocalParticle;
@@ -31,11 +37,11 @@ pub const NUM_PARTICLES_ONE_DIMENSION: i32 = 20;
 
 fn check_system(
     parameters: Res<GravityParameters>,
-    query: Particles<(&Velocity, &IndexIntoArray, &Timestep)>,
+    query: Particles<(&Acceleration, &IndexIntoArray)>,
     box_: Res<SimulationBox>,
 ) {
-    let solver = Solver::new(&parameters, &box_);
-    for (vel, index, timestep) in query.iter() {
+    let solver = Solver::new(&parameters, &box_, None);
+    for (acceleration, index) in query.iter() {
         let particles = get_particles(NUM_PARTICLES_ONE_DIMENSION, NUM_PARTICLES_ONE_DIMENSION);
         // We can't use the particle position from a query here,
         // because that has already been integrated
@@ -49,7 +55,7 @@ fn check_system(
                 .collect(),
         );
         let acc1 = direct_sum;
-        let acc2 = **vel / **timestep;
+        let acc2 = **acceleration;
         compare_accelerations(acc1, acc2);
     }
     // Check that we haven't accidentally broken this test by removing all the particles
@@ -97,10 +103,18 @@ fn build_parallel_gravity_sim(sim: &mut Simulation) {
         .add_parameters_explicitly(TimestepParameters {
             max_timestep: Time::seconds(1.0),
             num_levels: 1,
+            courant_factor: 0.15,
+            acceleration_factor: 0.025,
         })
         .add_parameters_explicitly(GravityParameters {
             opening_angle: Dimensionless::dimensionless(0.0),
+            opening_criterion: OpeningCriterion::Geometric,
+            relative_tolerance: Dimensionless::dimensionless(0.0025),
             softening_length: Length::meters(1e-30),
+            softening: Softening::Plummer,
+            species_softening_lengths: HashMap::default(),
+            multipole_order: MultipoleOrder::Quadrupole,
+            periodicity: Periodicity::NearestImage,
         })
         .add_parameters_explicitly(SimulationBox::from(get_extent_this_test()))
         .write_output(false)
@@ -0,0 +1,23 @@
+use crate::components::Acceleration;
+use crate::components::SofteningLength;
+use crate::timestep::TimestepCriterion;
+use crate::timestep::TimestepParameters;
+use crate::units::Time;
+
+/// The acceleration based criterion dt = sqrt(2 eta epsilon / |a|)
+/// of Power et al. (2003), where epsilon is the gravitational
+/// softening length of the particle.
+pub struct AccelerationCriterion;
+
+impl TimestepCriterion for AccelerationCriterion {
+    type Filter = ();
+
+    type Query = (&'static Acceleration, &'static SofteningLength);
+
+    fn timestep(
+        parameters: &TimestepParameters,
+        (acceleration, softening_length): (&Acceleration, &SofteningLength),
+    ) -> Time {
+        (2.0 * parameters.acceleration_factor * **softening_length / acceleration.length()).sqrt()
+    }
+}
@@ -5,7 +5,12 @@ use derive_more::From;
 use hdf5::H5Type;
 use mpi::traits::Equivalence;
 
+use crate::io::to_dataset::ToDataset;
 use crate::named::Named;
+use crate::prelude::Float;
+use crate::units::Dimension;
+use crate::units::Dimensionless;
+use crate::units::NONE;
 
 #[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Default, Named)]
 #[repr(transparent)]
@@ -26,3 +31,99 @@ pub struct SmoothingLength(pub crate::units::Length);
 #[name = "internal_energy"]
 #[repr(transparent)]
 pub struct InternalEnergy(pub crate::units::Energy);
+
+#[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Default, Named)]
+#[name = "balsara_factor"]
+#[repr(transparent)]
+pub struct BalsaraFactor(pub crate::units::Dimensionless);
+
+#[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Default, Named)]
+#[name = "signal_velocity"]
+#[repr(transparent)]
+pub struct SignalVelocity(pub crate::units::Velocity);
+
+/// The correction zeta = epsilon xi / (dN / dln h) to the
+/// gravitational forces of gas particles whose softening length
+/// epsilon follows their smoothing length h. Here, xi is the sum of
+/// m_j d phi(r_j, epsilon) / d epsilon over all neighbours and
+/// N is the kernel-weighted number of neighbours. Remains zero
+/// unless the
+/// [SelfGravityPlugin](crate::prelude::SelfGravityPlugin)
+/// is added.
+#[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, From, Default, Named)]
+#[name = "softening_correction"]
+#[repr(transparent)]
+pub struct SofteningCorrection(pub crate::units::MassPerLength);
+
+/// The ionization state of a gas particle: the number densities of
+/// neutral and ionized hydrogen (HI, HII), of neutral, singly and
+/// doubly ionized helium (HeI, HeII, HeIII) and of free electrons,
+/// each relative to the number density of hydrogen nuclei.
+#[derive(H5Type, Component, Debug, Clone, Copy, PartialEq, Equivalence, Named)]
+#[name = "abundances"]
+#[repr(C)]
+pub struct Abundances {
+    pub hi: Float,
+    pub hii: Float,
+    pub hei: Float,
+    pub heii: Float,
+    pub heiii: Float,
+    pub e: Float,
+}
+
+impl Abundances {
+    /// The molecular weight of neutral primordial gas with a hydrogen
+    /// mass fraction of 0.76, which is assumed for gas particles
+    /// without abundances.
+    pub const NEUTRAL_MOLECULAR_WEIGHT: Float = 1.22;
+
+    /// The number of helium nuclei per hydrogen nucleus.
+    pub fn helium(&self) -> Float {
+        self.hei + self.heii + self.heiii
+    }
+
+    /// The mean mass of the particles in the gas in units of the
+    /// proton mass.
+    pub fn molecular_weight(&self) -> Dimensionless {
+        let num_particles = self.hi + self.hii + self.helium() + self.e;
+        Dimensionless::dimensionless((1.0 + 4.0 * self.helium()) / num_particles)
+    }
+
+    /// The molecular weight of a gas particle with the given
+    /// abundances, or of neutral primordial gas if the particle
+    /// has no abundances.
+    pub fn molecular_weight_of(abundances: Option<&Self>) -> Dimensionless {
+        abundances
+            .map(|abundances| abundances.molecular_weight())
+            .unwrap_or(Dimensionless::dimensionless(Self::NEUTRAL_MOLECULAR_WEIGHT))
+    }
+}
+
+impl ToDataset for Abundances {
+    fn dimension() -> Dimension {
+        NONE
+    }
+
+    fn convert_base_units(self, _factor: f64) -> Self {
+        self
+    }
+
+    fn num_floats() -> Option<usize> {
+        Some(6)
+    }
+
+    fn to_floats(&self, floats: &mut Vec<f64>) {
+        floats.extend([self.hi, self.hii, self.hei, self.heii, self.heiii, self.e]);
+    }
+
+    fn from_floats(floats: &[f64]) -> Self {
+        Self {
+            hi: floats[0],
+            hii: floats[1],
+            hei: floats[2],
+            heii: floats[3],
+            heiii: floats[4],
+            e: floats[5],
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::f64::consts::PI;
+
+use derive_custom::raxiom_parameters;
+
+use crate::config::per_dimension;
+use crate::config::NUM_DIMENSIONS;
+use crate::prelude::Float;
+use crate::units::Dimension;
+use crate::units::Length;
+use crate::units::NumberDensity;
+use crate::units::Quantity;
+use crate::units::Volume;
+use crate::units::NONE;
+
+/// The derivative of the kernel with respect to the distance.
+pub type KernelDerivative = Quantity<
+    Float,
+    {
+        Dimension {
+            length: -(NUM_DIMENSIONS as i32) - 1,
+            ..NONE
+        }
+    },
+>;
+
+/// The smoothing kernel used in the SPH equations. All kernels
+/// have compact support, i.e. they vanish at distances larger than
+/// the smoothing length. See Dehnen & Aly (2012) for the definitions
+/// and normalisations.
+#[derive(Default, Copy, Debug)]
+#[raxiom_parameters]
+pub enum SphKernel {
+    /// The cubic spline of Monaghan & Lattanzio (1985).
+    #[default]
+    CubicSpline,
+    /// The quintic spline of Morris (1996).
+    QuinticSpline,
+    /// The Wendland C2 kernel. Unlike the splines, the Wendland
+    /// kernels do not suffer from the pairing instability at large
+    /// neighbour numbers.
+    WendlandC2,
+    /// The Wendland C4 kernel.
+    WendlandC4,
+    /// The Wendland C6 kernel.
+    WendlandC6,
+}
+
+/// h^d where d is the number of dimensions.
+pub(super) fn smoothing_volume(h: Length) -> Volume {
+    h.powi::<{ NUM_DIMENSIONS as i32 }>()
+}
+
+/// A kernel W(r, h) = sigma_d w(r / h) / h^d with compact support
+/// of radius h, given by its dimensionless shape w(q) and its
+/// normalisation sigma_d in d dimensions.
+pub trait Kernel {
+    /// The normalisation sigma_2 in two dimensions.
+    const NORMALISATION_2D: Float;
+    /// The normalisation sigma_3 in three dimensions.
+    const NORMALISATION_3D: Float;
+    /// The normalisation in the number of dimensions of the simulation.
+    const NORMALISATION: Float = per_dimension(Self::NORMALISATION_2D, Self::NORMALISATION_3D);
+
+    /// The shape w(q) of the kernel as a function of q = r / h < 1.
+    fn shape(q: Float) -> Float;
+
+    /// The derivative dw / dq of the kernel shape for q < 1.
+    fn shape_derivative(q: Float) -> Float;
+
+    /// The value W(r, h) of the kernel.
+    fn value(r: Length, h: Length) -> NumberDensity {
+        let q = (r / h).value();
+        if q >= 1.0 {
+            return NumberDensity::zero();
+        }
+        Self::NORMALISATION * Self::shape(q) / smoothing_volume(h)
+    }
+
+    /// The derivative dW / dr of the kernel with respect to the distance.
+    fn radial_derivative(r: Length, h: Length) -> KernelDerivative {
+        let q = (r / h).value();
+        if q >= 1.0 {
+            return KernelDerivative::zero();
+        }
+        Self::NORMALISATION * Self::shape_derivative(q) / (smoothing_volume(h) * h)
+    }
+}
+
+/// The cubic spline of Monaghan & Lattanzio (1985).
+pub struct CubicSpline;
+
+impl Kernel for CubicSpline {
+    const NORMALISATION_2D: Float = 40.0 / (7.0 * PI);
+    const NORMALISATION_3D: Float = 8.0 / PI;
+
+    fn shape(q: Float) -> Float {
+        if q < 0.5 {
+            1.0 - 6.0 * q.powi(2) + 6.0 * q.powi(3)
+        } else {
+            2.0 * (1.0 - q).powi(3)
+        }
+    }
+
+    fn shape_derivative(q: Float) -> Float {
+        if q < 0.5 {
+            -12.0 * q + 18.0 * q.powi(2)
+        } else {
+            -6.0 * (1.0 - q).powi(2)
+        }
+    }
+}
+
+/// The quintic spline of Morris (1996).
+pub struct QuinticSpline;
+
+impl Kernel for QuinticSpline {
+    const NORMALISATION_2D: Float = 15309.0 / (478.0 * PI);
+    const NORMALISATION_3D: Float = 2187.0 / (40.0 * PI);
+
+    fn shape(q: Float) -> Float {
+        let term = |x: Float| x.max(0.0).powi(5);
+        term(1.0 - q) - 6.0 * term(2.0 / 3.0 - q) + 15.0 * term(1.0 / 3.0 - q)
+    }
+
+    fn shape_derivative(q: Float) -> Float {
+        let term = |x: Float| x.max(0.0).powi(4);
+        -5.0 * term(1.0 - q) + 30.0 * term(2.0 / 3.0 - q) - 75.0 * term(1.0 / 3.0 - q)
+    }
+}
+
+/// The Wendland C2 kernel.
+pub struct WendlandC2;
+
+impl Kernel for WendlandC2 {
+    const NORMALISATION_2D: Float = 7.0 / PI;
+    const NORMALISATION_3D: Float = 21.0 / (2.0 * PI);
+
+    fn shape(q: Float) -> Float {
+        (1.0 - q).powi(4) * (1.0 + 4.0 * q)
+    }
+
+    fn shape_derivative(q: Float) -> Float {
+        -20.0 * q * (1.0 - q).powi(3)
+    }
+}
+
+/// The Wendland C4 kernel.
+pub struct WendlandC4;
+
+impl Kernel for WendlandC4 {
+    const NORMALISATION_2D: Float = 9.0 / PI;
+    const NORMALISATION_3D: Float = 495.0 / (32.0 * PI);
+
+    fn shape(q: Float) -> Float {
+        (1.0 - q).powi(6) * (1.0 + 6.0 * q + 35.0 / 3.0 * q.powi(2))
+    }
+
+    fn shape_derivative(q: Float) -> Float {
+        -56.0 / 3.0 * q * (1.0 + 5.0 * q) * (1.0 - q).powi(5)
+    }
+}
+
+/// The Wendland C6 kernel.
+pub struct WendlandC6;
+
+impl Kernel for WendlandC6 {
+    const NORMALISATION_2D: Float = 78.0 / (7.0 * PI);
+    const NORMALISATION_3D: Float = 1365.0 / (64.0 * PI);
+
+    fn shape(q: Float) -> Float {
+        (1.0 - q).powi(8) * (1.0 + 8.0 * q + 25.0 * q.powi(2) + 32.0 * q.powi(3))
+    }
+
+    fn shape_derivative(q: Float) -> Float {
+        -22.0 * q * (1.0 + 7.0 * q + 16.0 * q.powi(2)) * (1.0 - q).powi(7)
+    }
+}
+
+/// Calls the function of the [Kernel] that corresponds
+/// to the selected [SphKernel].
+macro_rules! dispatch {
+    ($kernel: expr, $function: ident ($($arg: expr),*)) => {
+        match $kernel {
+            SphKernel::CubicSpline => CubicSpline::$function($($arg),*),
+            SphKernel::QuinticSpline => QuinticSpline::$function($($arg),*),
+            SphKernel::WendlandC2 => WendlandC2::$function($($arg),*),
+            SphKernel::WendlandC4 => WendlandC4::$function($($arg),*),
+            SphKernel::WendlandC6 => WendlandC6::$function($($arg),*),
+        }
+    };
+}
+
+impl SphKernel {
+    /// The value W(r, h) of the kernel.
+    pub fn value(&self, r: Length, h: Length) -> NumberDensity {
+        dispatch!(self, value(r, h))
+    }
+
+    /// The derivative dW / dr of the kernel with respect to the distance.
+    pub fn radial_derivative(&self, r: Length, h: Length) -> KernelDerivative {
+        dispatch!(self, radial_derivative(r, h))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::CubicSpline;
+    use super::Kernel;
+    use super::QuinticSpline;
+    use super::WendlandC2;
+    use super::WendlandC4;
+    use super::WendlandC6;
+    use crate::prelude::Float;
+
+    const NUM_STEPS: usize = 10000;
+
+    fn integrate(f: impl Fn(Float) -> Float) -> Float {
+        let dq = 1.0 / NUM_STEPS as Float;
+        (0..NUM_STEPS)
+            .map(|i| {
+                let q = (i as Float + 0.5) * dq;
+                f(q) * dq
+            })
+            .sum()
+    }
+
+    fn check_normalisation<K: Kernel>() {
+        let integral_2d = integrate(|q| 2.0 * PI * q * K::NORMALISATION_2D * K::shape(q));
+        let integral_3d = integrate(|q| 4.0 * PI * q.powi(2) * K::NORMALISATION_3D * K::shape(q));
+        assert!((integral_2d - 1.0).abs() < 1e-6);
+        assert!((integral_3d - 1.0).abs() < 1e-6);
+    }
+
+    fn check_derivative<K: Kernel>() {
+        let epsilon = 1e-6;
+        for i in 1..100 {
+            let q = i as Float / 100.0;
+            let numerical = (K::shape(q + epsilon) - K::shape(q - epsilon)) / (2.0 * epsilon);
+            assert!((numerical - K::shape_derivative(q)).abs() < 1e-6, "{}", q);
+        }
+    }
+
+    #[test]
+    fn kernels_are_normalized() {
+        check_normalisation::<CubicSpline>();
+        check_normalisation::<QuinticSpline>();
+        check_normalisation::<WendlandC2>();
+        check_normalisation::<WendlandC4>();
+        check_normalisation::<WendlandC6>();
+    }
+
+    #[test]
+    fn kernel_derivatives_are_consistent() {
+        check_derivative::<CubicSpline>();
+        check_derivative::<QuinticSpline>();
+        check_derivative::<WendlandC2>();
+        check_derivative::<WendlandC4>();
+        check_derivative::<WendlandC6>();
+    }
+}
@@ -3,24 +3,44 @@ use std::f64::consts::PI;
 use bevy::prelude::*;
 use mpi::traits::Equivalence;
 
+use self::hydro_components::Abundances;
+use self::hydro_components::BalsaraFactor;
 use self::hydro_components::InternalEnergy;
 use self::hydro_components::Pressure;
+use self::hydro_components::SignalVelocity;
 use self::hydro_components::SmoothingLength;
+use self::hydro_components::SofteningCorrection;
+use self::kernel::smoothing_volume;
 use self::quadtree::bounding_boxes_overlap_periodic;
 use self::quadtree::construct_quad_tree_system;
+use self::quadtree::LeafData;
+use self::smoothing_length::communicate_smoothing_length_iteration_system;
+use self::smoothing_length::compute_smoothing_lengths_system;
+use self::smoothing_length::smoothing_length_iteration_criterion;
+use self::smoothing_length::HaloRegion;
+use self::smoothing_length::SmoothingLengthIterationResult;
+use self::smoothing_length::SmoothingLengthIterationState;
+use self::timestep::CourantCriterion;
 use crate::communication::CommunicationPlugin;
 use crate::communication::Rank;
 use crate::communication::SyncCommunicator;
 use crate::components;
+use crate::components::Acceleration;
 use crate::components::Mass;
 use crate::components::Position;
+use crate::components::Species;
 use crate::components::Timestep;
 use crate::components::Velocity;
+use crate::components::Work;
+use crate::config::per_dimension;
+use crate::config::NUM_DIMENSIONS;
 use crate::domain;
 use crate::domain::extent::Extent;
 use crate::domain::TopLevelIndices;
+use crate::io::input::ComponentInput;
 use crate::named::Named;
 use crate::performance_parameters::PerformanceParameters;
+use crate::prelude::ActiveParticles;
 use crate::prelude::Float;
 use crate::prelude::LocalParticle;
 use crate::prelude::MVec;
@@ -30,26 +50,47 @@ use crate::prelude::SimulationStartupStages;
 use crate::prelude::WorldRank;
 use crate::simulation::RaxiomPlugin;
 use crate::simulation::Simulation;
+use crate::simulation_plugin::reset_accelerations_system;
+use crate::simulation_plugin::reset_work_system;
 use crate::simulation_plugin::SimulationStages;
+use crate::timestep::TimestepPlugin;
 use crate::units;
 use crate::units::helpers::VecQuantity;
 use crate::units::Density;
 use crate::units::Dimension;
+use crate::units::Dimensionless;
 use crate::units::Energy;
+use crate::units::InverseTime;
 use crate::units::Length;
-use crate::units::NumberDensity;
+use crate::units::Quantity;
 use crate::units::VecAcceleration;
 use crate::units::VecLength;
+use crate::units::VecVelocity;
+use crate::units::Volume;
 use crate::units::GAMMA;
 use crate::units::NONE;
 
 pub(crate) mod hydro_components;
+mod kernel;
 mod parameters;
 pub mod quadtree;
+mod self_gravity;
+mod smoothing_length;
+mod timestep;
 
+pub use self::kernel::CubicSpline;
+pub use self::kernel::Kernel;
+pub use self::kernel::QuinticSpline;
+pub use self::kernel::SphKernel;
+pub use self::kernel::WendlandC2;
+pub use self::kernel::WendlandC4;
+pub use self::kernel::WendlandC6;
+pub use self::parameters::ArtificialViscosity;
 pub use self::parameters::HydrodynamicsParameters;
 pub use self::parameters::InitialGasEnergy;
+pub use self::parameters::SmoothingLengthIteration;
 pub use self::quadtree::QuadTree;
+pub use self::self_gravity::SelfGravityPlugin;
 
 // Could eventually become a more dynamic approach (similar to ExchangeDataPlugin)
 // but for now this is probably fine
@@ -62,6 +103,8 @@ struct RemoteParticleData {
     pub mass: Mass,
     pub velocity: components::Velocity,
     pub internal_energy: InternalEnergy,
+    pub balsara_factor: BalsaraFactor,
+    pub softening_correction: SofteningCorrection,
 }
 
 #[derive(Component)]
@@ -77,67 +120,192 @@ pub type HaloParticles<'world, 'state, T, F = ()> =
 pub type HydroParticles<'world, 'state, T, F = ()> =
     Query<'world, 'state, T, (Or<(With<HaloParticle>, With<LocalParticle>)>, F)>;
 
-fn kernel_function(r: Length, h: Length) -> f64 {
-    // Spline Kernel, Monaghan & Lattanzio 1985
-    let ratio = (r / h).value();
-    if ratio < 0.5 {
-        1.0 - 6.0 * ratio.powi(2) + 6.0 * ratio.powi(3)
-    } else if ratio < 1.0 {
-        2.0 * (1.0 - ratio).powi(3)
-    } else {
-        0.0
-    }
-}
-
-fn kernel_derivative_function(r: Length, h: Length) -> f64 {
-    let ratio = (r / h).value();
-    if ratio < 0.5 {
-        -2.0 * ratio + 3.0 * ratio.powi(2)
-    } else if ratio < 1.0 {
-        -(1.0 - ratio).powi(2)
-    } else {
-        0.0
-    }
-}
+/// The gradient of the kernel with respect to the position of the
+/// first particle.
+type KernelGradient = VecQuantity<
+    {
+        Dimension {
+            length: -(NUM_DIMENSIONS as i32) - 1,
+            ..NONE
+        }
+    },
+>;
 
+/// The dimension of the P / rho^2 terms in the SPH equations of motion.
 #[cfg(feature = "2d")]
-fn kernel(r: Length, h: Length) -> NumberDensity {
-    80.0 / (7.0 * PI * h.squared()) * kernel_function(r, h)
-}
+type PressureTerm = Quantity<
+    Float,
+    {
+        Dimension {
+            length: 4,
+            mass: -1,
+            time: -2,
+            ..NONE
+        }
+    },
+>;
 
+/// The dimension of the P / rho^2 terms in the SPH equations of motion.
 #[cfg(not(feature = "2d"))]
-fn kernel(r: Length, h: Length) -> NumberDensity {
-    8.0 / (PI * h.cubed()) * kernel_function(r, h)
+type PressureTerm = Quantity<
+    Float,
+    {
+        Dimension {
+            length: 5,
+            mass: -1,
+            time: -2,
+            ..NONE
+        }
+    },
+>;
+
+/// The volume of the kernel support of radius h.
+fn kernel_volume(h: Length) -> Volume {
+    per_dimension(PI, 4.0 / 3.0 * PI) * smoothing_volume(h)
 }
 
-#[cfg(feature = "2d")]
-fn symmetric_kernel_derivative(
+fn kernel_gradient(
+    kernel: &SphKernel,
     box_: &SimulationBox,
     r1: VecLength,
     r2: VecLength,
-    h1: Length,
-    h2: Length,
-) -> VecQuantity<{ Dimension { length: -3, ..NONE } }> {
+    h: Length,
+) -> KernelGradient {
     let dist = box_.periodic_distance_vec(&r1, &r2);
     let length = dist.length();
-    dist / length
-        * (48.0 / (7.0 * PI * h1.powi::<3>()) * kernel_derivative_function(length, h1)
-            + 48.0 / (7.0 * PI * h2.powi::<3>()) * kernel_derivative_function(length, h2))
+    dist / length * kernel.radial_derivative(length, h)
 }
 
-#[cfg(not(feature = "2d"))]
 fn symmetric_kernel_derivative(
+    kernel: &SphKernel,
     box_: &SimulationBox,
     r1: VecLength,
     r2: VecLength,
     h1: Length,
     h2: Length,
-) -> VecQuantity<{ Dimension { length: -4, ..NONE } }> {
-    let dist = box_.periodic_distance_vec(&r1, &r2);
-    let length = dist.length();
-    dist / length
-        * (48.0 / (PI * h1.powi::<4>()) * kernel_derivative_function(length, h1)
-            + 48.0 / (PI * h2.powi::<4>()) * kernel_derivative_function(length, h2))
+) -> KernelGradient {
+    kernel_gradient(kernel, box_, r1, r2, h1) + kernel_gradient(kernel, box_, r1, r2, h2)
+}
+
+fn sound_speed(pressure: units::Pressure, density: Density) -> units::Velocity {
+    (GAMMA * pressure / density).sqrt()
+}
+
+/// The data of a single particle which enters the artificial viscosity.
+struct ViscosityData {
+    pos: VecLength,
+    velocity: VecVelocity,
+    smoothing_length: Length,
+    density: Density,
+    sound_speed: units::Velocity,
+    balsara_factor: Dimensionless,
+}
+
+/// Prevents a singularity in the viscosity for particles at very small
+/// separations, in units of the squared mean smoothing length.
+const VISCOSITY_EPSILON: Float = 0.01;
+
+/// The number of loops over the neighbours of an active particle
+/// per force calculation (density, energy change and forces),
+/// which determines the work of a particle.
+const NUM_NEIGHBOUR_LOOPS: usize = 3;
+
+/// The Monaghan (1992) artificial viscosity Pi_ij between two particles.
+/// It is only active for approaching particles and enters the momentum
+/// and energy equations alongside the P / rho^2 terms.
+fn artificial_viscosity(
+    parameters: &ArtificialViscosity,
+    box_: &SimulationBox,
+    p1: &ViscosityData,
+    p2: &ViscosityData,
+) -> PressureTerm {
+    let distance = box_.periodic_distance_vec(&p1.pos, &p2.pos);
+    let approach = (p1.velocity - p2.velocity).dot(distance);
+    if approach.value_unchecked() >= 0.0 {
+        return PressureTerm::zero();
+    }
+    let smoothing_length = 0.5 * (p1.smoothing_length + p2.smoothing_length);
+    let mu = smoothing_length * approach
+        / (distance.length().squared() + VISCOSITY_EPSILON * smoothing_length.squared());
+    let sound_speed = 0.5 * (p1.sound_speed + p2.sound_speed);
+    let density = 0.5 * (p1.density + p2.density);
+    let balsara_factor = 0.5 * (p1.balsara_factor + p2.balsara_factor);
+    balsara_factor * (parameters.beta * mu.squared() - parameters.alpha * sound_speed * mu)
+        / density
+}
+
+/// The signal velocity v_sig = c_1 + c_2 - 3 w_12 of Monaghan (1997)
+/// between two particles, where w_12 is the relative velocity projected
+/// onto the separation if the particles approach each other and zero
+/// otherwise.
+fn signal_velocity(
+    box_: &SimulationBox,
+    p1: &ViscosityData,
+    p2: &ViscosityData,
+) -> units::Velocity {
+    let distance = box_.periodic_distance_vec(&p1.pos, &p2.pos);
+    let approach = (p1.velocity - p2.velocity).dot(distance) / distance.length();
+    p1.sound_speed + p2.sound_speed - 3.0 * approach.min(units::Velocity::zero())
+}
+
+/// The curl of the velocity field. Accumulated component-wise, since
+/// it is a scalar in 2D but a vector in 3D.
+#[derive(Default)]
+struct VelocityCurl {
+    #[cfg(not(feature = "2d"))]
+    x: InverseTime,
+    #[cfg(not(feature = "2d"))]
+    y: InverseTime,
+    z: InverseTime,
+}
+
+impl VelocityCurl {
+    fn add(&mut self, volume: Volume, v: VecVelocity, gradient: KernelGradient) {
+        #[cfg(not(feature = "2d"))]
+        {
+            self.x += volume * (v.y() * gradient.z() - v.z() * gradient.y());
+            self.y += volume * (v.z() * gradient.x() - v.x() * gradient.z());
+        }
+        self.z += volume * (v.x() * gradient.y() - v.y() * gradient.x());
+    }
+
+    fn length(&self) -> InverseTime {
+        #[cfg(feature = "2d")]
+        return self.z.abs();
+        #[cfg(not(feature = "2d"))]
+        return (self.x.squared() + self.y.squared() + self.z.squared()).sqrt();
+    }
+}
+
+/// The Balsara (1995) switch
+/// f = |div v| / (|div v| + |curl v| + 1e-4 c / h)
+/// which is close to one in compressive flows and close to zero in
+/// pure shear flows.
+/// The neighbours are given together with their masses.
+fn balsara_factor<'a>(
+    kernel: &SphKernel,
+    box_: &SimulationBox,
+    neighbours: impl Iterator<Item = (&'a LeafData, units::Mass)>,
+    pos: VecLength,
+    velocity: VecVelocity,
+    smoothing_length: Length,
+    density: Density,
+    sound_speed: units::Velocity,
+) -> Dimensionless {
+    let mut divergence = InverseTime::zero();
+    let mut curl = VelocityCurl::default();
+    for (neighbour, mass) in neighbours {
+        if neighbour.pos == pos {
+            continue;
+        }
+        let volume = mass / density;
+        let relative_velocity = neighbour.velocity - velocity;
+        let gradient = kernel_gradient(kernel, box_, pos, neighbour.pos, smoothing_length);
+        divergence += volume * relative_velocity.dot(gradient);
+        curl.add(volume, relative_velocity, gradient);
+    }
+    let divergence = divergence.abs();
+    divergence / (divergence + curl.length() + 1e-4 * sound_speed / smoothing_length)
 }
 
 #[derive(StageLabel)]
@@ -151,18 +319,35 @@ pub struct HydrodynamicsPlugin;
 impl RaxiomPlugin for HydrodynamicsPlugin {
     fn build_everywhere(&self, sim: &mut Simulation) {
         let initial_halo_exchange = halo_exchange_system.label("initial_halo_exchange");
+        let smoothing_length_halo_exchange =
+            halo_exchange_system.label("smoothing_length_halo_exchange");
         let density_pressure_halo_exchange =
             halo_exchange_system.label("density_pressure_halo_exchange");
         sim.add_parameter_type::<HydrodynamicsParameters>()
             .add_plugin(CommunicationPlugin::<RemoteParticleData>::sync())
             .insert_resource(QuadTree::make_empty_leaf_from_extent(Extent::default()))
+            .add_plugin(CommunicationPlugin::<SmoothingLengthIterationResult>::default())
+            .insert_resource(HaloRegion::default())
+            .insert_resource(SmoothingLengthIterationState::default())
             .add_system_to_stage(
                 HydrodynamicsStages::BeforeForceCalculation,
-                set_smoothing_lengths_system.before("initial_halo_exchange"),
+                initial_halo_exchange,
             )
-            .add_system_to_stage(
+            .add_system_set_to_stage(
                 HydrodynamicsStages::BeforeForceCalculation,
-                initial_halo_exchange,
+                SystemSet::new()
+                    .with_run_criteria(smoothing_length_iteration_criterion)
+                    .after("initial_halo_exchange")
+                    .with_system(construct_quad_tree_system)
+                    .with_system(compute_smoothing_lengths_system.after(construct_quad_tree_system))
+                    .with_system(
+                        communicate_smoothing_length_iteration_system
+                            .after(compute_smoothing_lengths_system),
+                    )
+                    .with_system(
+                        smoothing_length_halo_exchange
+                            .after(communicate_smoothing_length_iteration_system),
+                    ),
             )
             .add_system_to_stage(
                 SimulationStages::ForceCalculation,
@@ -170,7 +355,9 @@ impl RaxiomPlugin for HydrodynamicsPlugin {
             )
             .add_system_to_stage(
                 SimulationStages::ForceCalculation,
-                compute_pressure_and_density_system.after(construct_quad_tree_system),
+                compute_pressure_and_density_system
+                    .after(construct_quad_tree_system)
+                    .after(reset_work_system),
             )
             .add_system_to_stage(
                 SimulationStages::ForceCalculation,
@@ -186,6 +373,7 @@ impl RaxiomPlugin for HydrodynamicsPlugin {
                 SimulationStages::ForceCalculation,
                 compute_forces_system
                     .after(compute_energy_change_system)
+                    .after(reset_accelerations_system)
                     .after("density_pressure_halo_exchange"),
             )
             .add_startup_system_to_stage(
@@ -194,40 +382,12 @@ impl RaxiomPlugin for HydrodynamicsPlugin {
             )
             .add_derived_component::<components::Pressure>()
             .add_derived_component::<components::SmoothingLength>()
-            .add_derived_component::<components::InternalEnergy>()
-            .add_derived_component::<components::Density>();
-    }
-}
-
-fn get_smoothing_length(
-    parameters: &HydrodynamicsParameters,
-    mass: units::Mass,
-    density: units::Density,
-) -> Length {
-    let num_neighbours = parameters.num_smoothing_neighbours as Float;
-
-    #[cfg(feature = "2d")]
-    let smoothing_length = (2.0 * num_neighbours * mass / (PI * density)).sqrt();
-
-    #[cfg(not(feature = "2d"))]
-    let smoothing_length = (8.0 * num_neighbours * PI * mass / (3.0 * density)).cbrt();
-
-    smoothing_length.clamp(
-        parameters.min_smoothing_length,
-        parameters.max_smoothing_length,
-    )
-}
-
-fn set_smoothing_lengths_system(
-    parameters: Res<HydrodynamicsParameters>,
-    mut query: Particles<(
-        &mut SmoothingLength,
-        &components::Mass,
-        &components::Density,
-    )>,
-) {
-    for (mut smoothing_length, mass, density) in query.iter_mut() {
-        **smoothing_length = get_smoothing_length(&parameters, **mass, **density);
+            .add_component::<components::InternalEnergy>(ComponentInput::Optional)
+            .add_derived_component::<components::Density>()
+            .add_derived_component::<components::BalsaraFactor>()
+            .add_component_no_io::<SignalVelocity>()
+            .add_component_no_io::<SofteningCorrection>()
+            .add_plugin(TimestepPlugin::<CourantCriterion>::default());
     }
 }
 
@@ -243,6 +403,8 @@ fn halo_exchange_system(
             &Mass,
             &InternalEnergy,
             &components::Velocity,
+            &BalsaraFactor,
+            &SofteningCorrection,
         ),
         Without<HaloParticle>,
     >,
@@ -254,15 +416,28 @@ fn halo_exchange_system(
         &mut Mass,
         &mut InternalEnergy,
         &mut components::Velocity,
+        &mut BalsaraFactor,
+        &mut SofteningCorrection,
     )>,
     mut communicator: SyncCommunicator<RemoteParticleData>,
     indices: Res<TopLevelIndices>,
     tree: Res<domain::QuadTree>,
     box_: Res<SimulationBox>,
     world_rank: Res<WorldRank>,
+    halo_region: Res<HaloRegion>,
 ) {
-    for (entity, pos, smoothing_length, density, pressure, mass, internal_energy, velocity) in
-        particles.iter()
+    for (
+        entity,
+        pos,
+        smoothing_length,
+        density,
+        pressure,
+        mass,
+        internal_energy,
+        velocity,
+        balsara_factor,
+        softening_correction,
+    ) in particles.iter()
     {
         for (rank, index) in indices
             .iter()
@@ -275,7 +450,7 @@ fn halo_exchange_system(
             if bounding_boxes_overlap_periodic(
                 &box_,
                 pos,
-                &(MVec::ONE * **smoothing_length),
+                &(MVec::ONE * halo_region.radius(rank, **smoothing_length)),
                 &tree.extent.center(),
                 &tree.extent.side_lengths(),
             ) {
@@ -290,6 +465,8 @@ fn halo_exchange_system(
                         mass: mass.clone(),
                         internal_energy: internal_energy.clone(),
                         velocity: velocity.clone(),
+                        balsara_factor: balsara_factor.clone(),
+                        softening_correction: softening_correction.clone(),
                     },
                 );
             }
@@ -309,6 +486,8 @@ fn halo_exchange_system(
             *particle.4 = new_data.mass;
             *particle.5 = new_data.internal_energy;
             *particle.6 = new_data.velocity;
+            *particle.7 = new_data.balsara_factor;
+            *particle.8 = new_data.softening_correction;
         }
     }
 }
@@ -316,65 +495,120 @@ fn halo_exchange_system(
 fn insert_pressure_and_density_system(
     mut commands: Commands,
     particles: Particles<
-        (Entity, &Mass),
+        (
+            Entity,
+            &Mass,
+            Option<&InternalEnergy>,
+            Option<&Species>,
+            Option<&Abundances>,
+        ),
         (Without<components::Pressure>, Without<components::Density>),
     >,
     parameters: Res<HydrodynamicsParameters>,
 ) {
-    for (entity, mass) in particles.iter() {
-        let energy = match parameters.initial_gas_energy {
-            InitialGasEnergy::TemperatureAndMolecularWeight {
-                temperature,
-                molecular_weight,
-            } => temperature.to_internal_energy(molecular_weight) * **mass,
-            InitialGasEnergy::Energy(energy) => energy * **mass,
-            InitialGasEnergy::Explicit => {
-                panic!("InitialGasEnergy is supposed to be initialized explicitly, but there are particles without an internal energy!")
-            }
+    for (entity, mass, internal_energy, species, abundances) in particles.iter() {
+        // The species markers are not inserted yet at this point.
+        if species.copied().unwrap_or_default() != Species::Gas {
+            continue;
+        }
+        // Keep the internal energy if it was read from the
+        // initial conditions.
+        let energy = match internal_energy {
+            Some(internal_energy) => **internal_energy,
+            None => match parameters.initial_gas_energy {
+                InitialGasEnergy::TemperatureAndMolecularWeight {
+                    temperature,
+                    molecular_weight,
+                } => temperature.to_internal_energy(molecular_weight) * **mass,
+                InitialGasEnergy::Temperature { temperature } => {
+                    let abundances = abundances.expect(
+                        "InitialGasEnergy::Temperature requires the abundances of the gas particles. Add the CoolingPlugin.",
+                    );
+                    temperature.to_internal_energy(abundances.molecular_weight()) * **mass
+                }
+                InitialGasEnergy::Energy(energy) => energy * **mass,
+                InitialGasEnergy::Explicit => {
+                    panic!("InitialGasEnergy is supposed to be initialized explicitly, but there are particles without an internal energy!")
+                }
+            },
         };
         commands.entity(entity).insert((
             components::Pressure::default(),
             components::Density::default(),
             SmoothingLength(parameters.min_smoothing_length),
             components::InternalEnergy(energy),
+            BalsaraFactor(Dimensionless::dimensionless(1.0)),
+            SignalVelocity::default(),
+            SofteningCorrection::default(),
         ));
     }
 }
 
 fn compute_pressure_and_density_system(
-    mut pressures: Particles<(
+    mut pressures: ActiveParticles<(
         &mut components::Pressure,
         &mut components::Density,
+        &mut BalsaraFactor,
         &InternalEnergy,
         &SmoothingLength,
         &Position,
+        &Velocity,
         &Mass,
+        &mut Work,
     )>,
     masses: HydroParticles<&Mass>,
     tree: Res<QuadTree>,
     box_: Res<SimulationBox>,
+    parameters: Res<HydrodynamicsParameters>,
     performance_parameters: Res<PerformanceParameters>,
 ) {
     pressures.par_for_each_mut(
         performance_parameters.batch_size(),
-        |(mut pressure, mut density, internal_energy, smoothing_length, pos, mass)| {
+        |(
+            mut pressure,
+            mut density,
+            mut balsara,
+            internal_energy,
+            smoothing_length,
+            pos,
+            velocity,
+            mass,
+            mut work,
+        )| {
             **density = Density::zero();
             let particles = tree.get_particles_in_radius(&box_, pos, smoothing_length);
             debug_assert!(!particles.is_empty());
+            **work += (NUM_NEIGHBOUR_LOOPS * particles.len()) as Float;
             for particle in particles.iter() {
                 let mass2 = masses.get(particle.entity).unwrap();
                 let distance = box_.periodic_distance(&particle.pos, pos);
-                **density += **mass2 * kernel(distance, **smoothing_length);
+                **density += **mass2 * parameters.kernel.value(distance, **smoothing_length);
             }
             // P = (gamma - 1) * rho * u
             // u = energy / mass
-            **pressure = (GAMMA - 1.0) * **density * **internal_energy / **mass
+            **pressure = (GAMMA - 1.0) * **density * **internal_energy / **mass;
+            **balsara = if parameters.viscosity.balsara_switch {
+                balsara_factor(
+                    &parameters.kernel,
+                    &box_,
+                    particles
+                        .iter()
+                        .map(|particle| (*particle, **masses.get(particle.entity).unwrap())),
+                    **pos,
+                    **velocity,
+                    **smoothing_length,
+                    **density,
+                    sound_speed(**pressure, **density),
+                )
+            } else {
+                Dimensionless::dimensionless(1.0)
+            };
         },
     );
 }
 
-fn compute_energy_change_system(
-    mut particles1: Particles<(
+pub(crate) fn compute_energy_change_system(
+    mut particles1: ActiveParticles<(
         &mut InternalEnergy,
         &Mass,
         &Velocity,
@@ -382,6 +616,7 @@ fn compute_energy_change_system(
         &SmoothingLength,
         &components::Pressure,
         &components::Density,
+        &BalsaraFactor,
         &Timestep,
     )>,
     particles2: HydroParticles<(
@@ -391,9 +626,11 @@ fn compute_energy_change_system(
         &components::Density,
         &components::Mass,
         &SmoothingLength,
+        &BalsaraFactor,
     )>,
     tree: Res<QuadTree>,
     box_: Res<SimulationBox>,
+    parameters: Res<HydrodynamicsParameters>,
     performance_parameters: Res<PerformanceParameters>,
 ) {
     particles1.par_for_each_mut(
@@ -406,8 +643,17 @@ fn compute_energy_change_system(
             smoothing_length1,
             pressure1,
             density1,
+            balsara_factor1,
             timestep,
         )| {
+            let viscosity_data1 = ViscosityData {
+                pos: **position1,
+                velocity: **velocity1,
+                smoothing_length: **smoothing_length1,
+                density: **density1,
+                sound_speed: sound_speed(**pressure1, **density1),
+                balsara_factor: **balsara_factor1,
+            };
             let mut d_energy = Energy::zero()
                 / crate::units::Mass::one_unchecked()
                 / crate::units::Time::one_unchecked();
@@ -415,23 +661,46 @@ fn compute_energy_change_system(
                 .get_particles_in_radius(&box_, position1, smoothing_length1)
                 .iter()
             {
-                let (position2, velocity2, pressure2, density2, mass2, smoothing_length2) =
-                    particles2.get(particle.entity).unwrap();
+                let (
+                    position2,
+                    velocity2,
+                    pressure2,
+                    density2,
+                    mass2,
+                    smoothing_length2,
+                    balsara_factor2,
+                ) = particles2.get(particle.entity).unwrap();
                 if **position1 == **position2 {
                     continue;
                 }
                 let relative_velocity = **velocity1 - **velocity2;
                 let kernel_derivative = symmetric_kernel_derivative(
+                    &parameters.kernel,
                     &box_,
                     **position1,
                     **position2,
                     **smoothing_length1,
                     **smoothing_length2,
                 );
-                // TODO: viscosity
+                let viscosity_data2 = ViscosityData {
+                    pos: **position2,
+                    velocity: **velocity2,
+                    smoothing_length: **smoothing_length2,
+                    density: **density2,
+                    sound_speed: sound_speed(**pressure2, **density2),
+                    balsara_factor: **balsara_factor2,
+                };
+                let viscosity = artificial_viscosity(
+                    &parameters.viscosity,
+                    &box_,
+                    &viscosity_data1,
+                    &viscosity_data2,
+                );
                 d_energy += 0.5
                     * **mass2
-                    * ((**pressure1 / density1.squared()) + (**pressure2 / density2.squared()))
+                    * ((**pressure1 / density1.squared())
+                        + (**pressure2 / density2.squared())
+                        + viscosity)
                     * relative_velocity.dot(kernel_derivative);
             }
             **energy1 += d_energy * **timestep * **mass1;
@@ -440,52 +709,222 @@ fn compute_energy_change_system(
 }
 
 fn compute_forces_system(
-    mut particles1: Particles<(
-        &mut Velocity,
+    mut particles1: ActiveParticles<(
+        &mut Acceleration,
+        &mut SignalVelocity,
+        &Velocity,
         &Position,
         &SmoothingLength,
         &components::Pressure,
         &components::Density,
-        &Timestep,
+        &BalsaraFactor,
     )>,
     particles2: HydroParticles<(
         &Position,
+        &Velocity,
         &components::Pressure,
         &components::Density,
         &components::Mass,
         &SmoothingLength,
+        &BalsaraFactor,
     )>,
     tree: Res<QuadTree>,
     box_: Res<SimulationBox>,
+    parameters: Res<HydrodynamicsParameters>,
     performance_parameters: Res<PerformanceParameters>,
 ) {
     particles1.par_for_each_mut(
         performance_parameters.batch_size(),
-        |(mut velocity1, position1, smoothing_length1, pressure1, density1, timestep)| {
-            let mut d_vel = VecAcceleration::zero();
+        |(
+            mut acceleration1,
+            mut signal_velocity1,
+            velocity1,
+            position1,
+            smoothing_length1,
+            pressure1,
+            density1,
+            balsara_factor1,
+        )| {
+            let viscosity_data1 = ViscosityData {
+                pos: **position1,
+                velocity: **velocity1,
+                smoothing_length: **smoothing_length1,
+                density: **density1,
+                sound_speed: sound_speed(**pressure1, **density1),
+                balsara_factor: **balsara_factor1,
+            };
+            let mut acceleration = VecAcceleration::zero();
+            let mut max_signal_velocity = 2.0 * viscosity_data1.sound_speed;
             for particle in tree
                 .get_particles_in_radius(&box_, position1, smoothing_length1)
                 .iter()
             {
-                let (position2, pressure2, density2, mass2, smoothing_length2) =
-                    particles2.get(particle.entity).unwrap();
+                let (
+                    position2,
+                    velocity2,
+                    pressure2,
+                    density2,
+                    mass2,
+                    smoothing_length2,
+                    balsara_factor2,
+                ) = particles2.get(particle.entity).unwrap();
                 if **position1 == **position2 {
                     continue;
                 }
                 let kernel_derivative = symmetric_kernel_derivative(
+                    &parameters.kernel,
                     &box_,
                     **position1,
                     **position2,
                     **smoothing_length1,
                     **smoothing_length2,
                 );
-                // TODO: viscosity
-                d_vel += -0.5
+                let viscosity_data2 = ViscosityData {
+                    pos: **position2,
+                    velocity: **velocity2,
+                    smoothing_length: **smoothing_length2,
+                    density: **density2,
+                    sound_speed: sound_speed(**pressure2, **density2),
+                    balsara_factor: **balsara_factor2,
+                };
+                let viscosity = artificial_viscosity(
+                    &parameters.viscosity,
+                    &box_,
+                    &viscosity_data1,
+                    &viscosity_data2,
+                );
+                max_signal_velocity = max_signal_velocity.max(signal_velocity(
+                    &box_,
+                    &viscosity_data1,
+                    &viscosity_data2,
+                ));
+                acceleration += -0.5
                     * **mass2
-                    * ((**pressure1 / density1.squared()) + (**pressure2 / density2.squared()))
+                    * ((**pressure1 / density1.squared())
+                        + (**pressure2 / density2.squared())
+                        + viscosity)
                     * kernel_derivative;
             }
-            **velocity1 += d_vel * **timestep;
+            **acceleration1 += acceleration;
+            **signal_velocity1 = max_signal_velocity;
         },
     );
 }
+
+#[cfg(test)]
+#[cfg(not(feature = "2d"))]
+mod tests {
+    use bevy::prelude::Entity;
+
+    use super::artificial_viscosity;
+    use super::balsara_factor;
+    use super::quadtree::LeafData;
+    use super::ArtificialViscosity;
+    use super::PressureTerm;
+    use super::SphKernel;
+    use super::ViscosityData;
+    use crate::prelude::SimulationBox;
+    use crate::units::Density;
+    use crate::units::Dimensionless;
+    use crate::units::Length;
+    use crate::units::Mass;
+    use crate::units::VecLength;
+    use crate::units::VecVelocity;
+    use crate::units::Velocity;
+
+    fn viscosity_data(pos: VecLength, velocity: VecVelocity) -> ViscosityData {
+        ViscosityData {
+            pos,
+            velocity,
+            smoothing_length: Length::meters(1.0),
+            density: Density::kilogram_per_cubic_meter(1.0),
+            sound_speed: Velocity::meters_per_second(1.0),
+            balsara_factor: Dimensionless::dimensionless(1.0),
+        }
+    }
+
+    #[test]
+    fn viscosity_only_acts_on_approaching_particles() {
+        let box_ = SimulationBox::cube_from_side_length_centered(Length::meters(100.0));
+        let parameters = ArtificialViscosity::default();
+        let pos1 = VecLength::meters(0.0, 0.0, 0.0);
+        let pos2 = VecLength::meters(0.5, 0.0, 0.0);
+        let velocity = VecVelocity::meters_per_second(1.0, 0.0, 0.0);
+        let receding = artificial_viscosity(
+            &parameters,
+            &box_,
+            &viscosity_data(pos1, -velocity),
+            &viscosity_data(pos2, velocity),
+        );
+        assert_eq!(receding, PressureTerm::zero());
+        let approaching = artificial_viscosity(
+            &parameters,
+            &box_,
+            &viscosity_data(pos1, velocity),
+            &viscosity_data(pos2, -velocity),
+        );
+        assert!(approaching > PressureTerm::zero());
+        // Perpendicular motion neither approaches nor recedes.
+        let perpendicular = VecVelocity::meters_per_second(0.0, 1.0, 0.0);
+        let sliding = artificial_viscosity(
+            &parameters,
+            &box_,
+            &viscosity_data(pos1, perpendicular),
+            &viscosity_data(pos2, -perpendicular),
+        );
+        assert_eq!(sliding, PressureTerm::zero());
+    }
+
+    /// The Balsara factor of a particle at the origin whose
+    /// neighbours on a lattice move with the given velocity field.
+    fn balsara_factor_of_flow(velocity: impl Fn(VecLength) -> VecVelocity) -> Dimensionless {
+        let box_ = SimulationBox::cube_from_side_length_centered(Length::meters(100.0));
+        let spacing = 0.25;
+        let coordinates = || (-4..=4).map(move |i| i as f64 * spacing);
+        let neighbours: Vec<_> = coordinates()
+            .flat_map(|x| coordinates().flat_map(move |y| coordinates().map(move |z| (x, y, z))))
+            .enumerate()
+            .map(|(i, (x, y, z))| {
+                let pos = VecLength::meters(x, y, z);
+                LeafData {
+                    entity: Entity::from_raw(i as u32),
+                    pos,
+                    smoothing_length: Length::meters(1.0),
+                    velocity: velocity(pos),
+                }
+            })
+            .collect();
+        balsara_factor(
+            &SphKernel::default(),
+            &box_,
+            neighbours
+                .iter()
+                .map(|neighbour| (neighbour, Mass::kilograms(1.0))),
+            VecLength::zero(),
+            VecVelocity::zero(),
+            Length::meters(1.0),
+            Density::kilogram_per_cubic_meter(1.0),
+            Velocity::meters_per_second(1e-10),
+        )
+    }
+
+    #[test]
+    fn balsara_factor_vanishes_in_pure_shear() {
+        let factor = balsara_factor_of_flow(|pos| {
+            VecVelocity::meters_per_second(pos.y().in_meters(), 0.0, 0.0)
+        });
+        assert!(factor.value() < 1e-6);
+    }
+
+    #[test]
+    fn balsara_factor_is_one_in_pure_compression() {
+        let factor = balsara_factor_of_flow(|pos| {
+            VecVelocity::meters_per_second(
+                -pos.x().in_meters(),
+                -pos.y().in_meters(),
+                -pos.z().in_meters(),
+            )
+        });
+        assert!((factor.value() - 1.0).abs() < 1e-6);
+    }
+}
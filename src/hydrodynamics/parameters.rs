@@ -1,5 +1,7 @@
 use derive_custom::raxiom_parameters;
 
+use super::kernel::SphKernel;
+use crate::prelude::Float;
 use crate::quadtree::QuadTreeConfig;
 use crate::units::Dimensionless;
 use crate::units::EnergyPerMass;
@@ -25,6 +27,79 @@ pub struct HydrodynamicsParameters {
     /// [QuadTreeConfig](crate::quadtree::QuadTreeConfig)
     #[serde(default = "default_hydro_tree")]
     pub tree: QuadTreeConfig,
+    /// Parameters of the artificial viscosity, which is always
+    /// active (with alpha = 1 and beta = 2 by default). See
+    /// [ArtificialViscosity](crate::parameters::ArtificialViscosity)
+    #[serde(default)]
+    pub viscosity: ArtificialViscosity,
+    /// Parameters of the iterative computation of the smoothing
+    /// lengths. See
+    /// [SmoothingLengthIteration](crate::parameters::SmoothingLengthIteration)
+    #[serde(default)]
+    pub smoothing_length_iteration: SmoothingLengthIteration,
+    /// The smoothing kernel. See
+    /// [SphKernel](crate::parameters::SphKernel)
+    #[serde(default)]
+    pub kernel: SphKernel,
+}
+
+/// Parameters of the iterative solver which determines the smoothing
+/// length of each particle such that the kernel-weighted number of
+/// neighbours matches
+/// [num_smoothing_neighbours](HydrodynamicsParameters::num_smoothing_neighbours).
+#[raxiom_parameters]
+pub struct SmoothingLengthIteration {
+    /// The relative tolerance in the number of neighbours
+    /// at which the iteration is considered converged.
+    #[serde(default = "default_smoothing_length_tolerance")]
+    pub tolerance: Float,
+    /// The maximum number of Newton-Raphson / bisection steps
+    /// per particle and round.
+    #[serde(default = "default_smoothing_length_max_iterations")]
+    pub max_iterations: usize,
+    /// The maximum number of rounds. In each round, the halo
+    /// particles are re-exchanged if the smoothing length on any
+    /// rank grew beyond the region covered by the previous exchange.
+    #[serde(default = "default_smoothing_length_max_rounds")]
+    pub max_rounds: usize,
+}
+
+impl Default for SmoothingLengthIteration {
+    fn default() -> Self {
+        Self {
+            tolerance: default_smoothing_length_tolerance(),
+            max_iterations: default_smoothing_length_max_iterations(),
+            max_rounds: default_smoothing_length_max_rounds(),
+        }
+    }
+}
+
+/// Parameters of the Monaghan (1992) artificial viscosity
+/// which is needed to capture shocks.
+#[raxiom_parameters]
+pub struct ArtificialViscosity {
+    /// The coefficient of the linear (bulk viscosity) term.
+    /// Defaults to 1.
+    #[serde(default = "default_viscosity_alpha")]
+    pub alpha: Dimensionless,
+    /// The coefficient of the quadratic term which prevents particle
+    /// interpenetration in strong shocks. Defaults to 2.
+    #[serde(default = "default_viscosity_beta")]
+    pub beta: Dimensionless,
+    /// Whether to reduce the viscosity in shear flows via the
+    /// Balsara (1995) switch. Disabled by default.
+    #[serde(default)]
+    pub balsara_switch: bool,
+}
+
+impl Default for ArtificialViscosity {
+    fn default() -> Self {
+        Self {
+            alpha: default_viscosity_alpha(),
+            beta: default_viscosity_beta(),
+            balsara_switch: false,
+        }
+    }
 }
 
 #[raxiom_parameters]
@@ -37,10 +112,21 @@ pub enum InitialGasEnergy {
     /// u = kB T_init / (mu m_p (gamma - 1))
     /// where kB is the Boltzmann constant, m_p is the proton mass
     /// and gamma is the adiabatic index.
+    /// The given mu is used as-is, even if the
+    /// [CoolingPlugin](crate::prelude::CoolingPlugin) assigns the
+    /// particles abundances with a different molecular weight. Use
+    /// `Temperature` to keep the two consistent.
     TemperatureAndMolecularWeight {
         temperature: Temperature,
         molecular_weight: Dimensionless,
     },
+    /// Set the initial thermal energy u of the gas via the initial
+    /// temperature T_init, using the molecular weight of the
+    /// ionization state of each particle, which the
+    /// [CoolingPlugin](crate::prelude::CoolingPlugin) initializes to
+    /// the equilibrium state at T_init unless it is given in the
+    /// initial conditions. Requires the CoolingPlugin.
+    Temperature { temperature: Temperature },
     /// Specify the initial thermal energy u directly
     Energy(EnergyPerMass),
     /// All thermal energies will be set by initial conditions or a
@@ -49,6 +135,26 @@ pub enum InitialGasEnergy {
     Explicit,
 }
 
+fn default_viscosity_alpha() -> Dimensionless {
+    Dimensionless::dimensionless(1.0)
+}
+
+fn default_viscosity_beta() -> Dimensionless {
+    Dimensionless::dimensionless(2.0)
+}
+
+fn default_smoothing_length_tolerance() -> Float {
+    1e-3
+}
+
+fn default_smoothing_length_max_iterations() -> usize {
+    50
+}
+
+fn default_smoothing_length_max_rounds() -> usize {
+    10
+}
+
 fn default_hydro_tree() -> QuadTreeConfig {
     QuadTreeConfig {
         min_depth: 0,
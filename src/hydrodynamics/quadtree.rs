@@ -4,6 +4,7 @@ use super::parameters::HydrodynamicsParameters;
 use super::HydroParticles;
 use crate::components::Position;
 use crate::components::SmoothingLength;
+use crate::components::Velocity;
 use crate::domain::GlobalExtent;
 use crate::parameters::SimulationBox;
 use crate::prelude::MVec;
@@ -12,6 +13,7 @@ use crate::quadtree::NodeDataType;
 use crate::quadtree::{self};
 use crate::units::Length;
 use crate::units::VecLength;
+use crate::units::VecVelocity;
 
 pub type QuadTree = quadtree::QuadTree<NodeData, LeafData>;
 
@@ -20,6 +22,11 @@ pub struct LeafData {
     pub entity: Entity,
     pub pos: VecLength,
     pub smoothing_length: Length,
+    /// The velocity of the particle at the time of tree
+    /// construction. Used for the artificial viscosity, so that
+    /// every pair sees the same relative velocity, independent of
+    /// the order in which the particles are kicked.
+    pub velocity: VecVelocity,
 }
 
 #[derive(Debug, Default)]
@@ -143,20 +150,35 @@ impl QuadTree {
             })
             .collect()
     }
+
+    /// Returns all particles within the given radius around pos,
+    /// irrespective of their own smoothing length.
+    pub fn get_particles_in_gather_radius<'a>(
+        &'a self,
+        box_size: &SimulationBox,
+        pos: &VecLength,
+        radius: &Length,
+    ) -> Vec<&'a LeafData> {
+        get_particles_in_box(self, box_size, pos, radius)
+            .into_iter()
+            .filter(|particle| box_size.periodic_distance(pos, particle.pos()) < *radius)
+            .collect()
+    }
 }
 
 pub(super) fn construct_quad_tree_system(
     parameters: Res<HydrodynamicsParameters>,
-    particles: HydroParticles<(Entity, &Position, &SmoothingLength)>,
+    particles: HydroParticles<(Entity, &Position, &Velocity, &SmoothingLength)>,
     extent: Res<GlobalExtent>,
     mut quadtree: ResMut<QuadTree>,
 ) {
     let particles: Vec<_> = particles
         .iter()
-        .map(|(entity, pos, smoothing_length)| LeafData {
+        .map(|(entity, pos, velocity, smoothing_length)| LeafData {
             entity,
             pos: pos.0,
             smoothing_length: **smoothing_length,
+            velocity: velocity.0,
         })
         .collect();
     *quadtree = QuadTree::new(&parameters.tree, particles, &extent);
@@ -175,6 +197,7 @@ mod tests {
     use crate::quadtree::QuadTreeConfig;
     use crate::units::Length;
     use crate::units::VecLength;
+    use crate::units::VecVelocity;
 
     pub(super) fn direct_neighbour_search<'a>(
         particles: &'a [LeafData],
@@ -208,6 +231,7 @@ mod tests {
                 entity: particle.entity,
                 pos: particle.pos,
                 smoothing_length: particle.pos.x() * 0.2,
+                velocity: VecVelocity::zero(),
             })
             .collect();
         let extent = Extent::from_positions(particles.iter().map(|leaf| &leaf.pos)).unwrap();
@@ -0,0 +1,423 @@
+use bevy::prelude::*;
+
+use super::compute_forces_system;
+use super::kernel_gradient;
+use super::kernel_volume;
+use super::quadtree::construct_quad_tree_system;
+use super::smoothing_length::weighted_num_neighbours;
+use super::HydroParticles;
+use super::HydrodynamicsParameters;
+use super::QuadTree;
+use super::SphKernel;
+use crate::components::Acceleration;
+use crate::components::GravitationalAcceleration;
+use crate::components::Mass;
+use crate::components::Position;
+use crate::components::SmoothingLength;
+use crate::components::SofteningCorrection;
+use crate::components::SofteningLength;
+use crate::domain;
+use crate::gravity::gravity_system;
+use crate::gravity::GravityParameters;
+use crate::gravity::Softening;
+use crate::gravity::SPLINE_SUPPORT_FACTOR;
+use crate::named::Named;
+use crate::parameters::SimulationBox;
+use crate::performance_parameters::PerformanceParameters;
+use crate::prelude::ActiveParticles;
+use crate::prelude::Particles;
+use crate::simulation::RaxiomPlugin;
+use crate::simulation::Simulation;
+use crate::simulation_plugin::SimulationStages;
+use crate::units;
+use crate::units::Length;
+use crate::units::VecAcceleration;
+use crate::units::VecLength;
+use crate::units::GRAVITY_CONSTANT;
+
+/// Couples the gravitational softening of the gas to the
+/// hydrodynamics. The softening length of every gas particle
+/// follows its smoothing length h as epsilon = h / 2.8, so that the
+/// support radius of the spline softening equals that of the SPH
+/// kernel. Since the smoothing lengths depend on the positions of
+/// the neighbours, the gravitational forces then receive the
+/// additional "grad-h" terms of Price & Monaghan (2007)
+/// a_i = G / 2 sum_j (zeta_i V(h_i) grad W_ij(h_i)
+///     + m_j / m_i zeta_j V(h_j) grad W_ij(h_j))
+/// where V(h) is the volume of the kernel support and zeta is the
+/// [SofteningCorrection](crate::components::SofteningCorrection).
+/// Together with the averaged forces between particles of different
+/// softening lengths, this conserves momentum and energy. Since only
+/// the neighbours within the smoothing length contribute to the
+/// correction, it requires
+/// [spline softening](crate::parameters::Softening::Spline), whose
+/// potential is Newtonian beyond the smoothing length. Requires the
+/// [HydrodynamicsPlugin](crate::prelude::HydrodynamicsPlugin)
+/// and the [GravityPlugin](crate::prelude::GravityPlugin).
+#[derive(Named)]
+pub struct SelfGravityPlugin;
+
+impl RaxiomPlugin for SelfGravityPlugin {
+    fn build_everywhere(&self, sim: &mut Simulation) {
+        let softening = sim
+            .add_parameter_type_and_get_result::<GravityParameters>()
+            .softening;
+        if softening != Softening::Spline {
+            panic!("The SelfGravityPlugin requires spline softening, but the softening is {softening:?}. Set softening: Spline in the gravity parameters.");
+        }
+        sim.add_system_to_stage(
+            SimulationStages::ForceCalculation,
+            adaptive_softening_system.before(domain::construct_quad_tree_system),
+        )
+        .add_system_to_stage(
+            SimulationStages::ForceCalculation,
+            compute_softening_corrections_system
+                .after(construct_quad_tree_system)
+                .before("density_pressure_halo_exchange"),
+        )
+        .add_system_to_stage(
+            SimulationStages::ForceCalculation,
+            softening_correction_forces_system
+                .after("density_pressure_halo_exchange")
+                .after(compute_forces_system)
+                .after(gravity_system),
+        );
+    }
+}
+
+/// The softening length of a gas particle with the given
+/// smoothing length.
+fn adaptive_softening_length(smoothing_length: Length) -> Length {
+    smoothing_length / SPLINE_SUPPORT_FACTOR
+}
+
+fn adaptive_softening_system(mut particles: Particles<(&SmoothingLength, &mut SofteningLength)>) {
+    for (smoothing_length, mut softening_length) in particles.iter_mut() {
+        **softening_length = adaptive_softening_length(**smoothing_length);
+    }
+}
+
+/// Computes the correction zeta = epsilon xi / (dN / dln h) of a
+/// particle from the distances to and the masses of all particles
+/// within its smoothing length (including itself).
+fn softening_correction(
+    kernel: &SphKernel,
+    softening: &Softening,
+    smoothing_length: Length,
+    neighbours: &[(Length, units::Mass)],
+) -> SofteningCorrection {
+    let distances: Vec<_> = neighbours.iter().map(|(distance, _)| *distance).collect();
+    let (_, derivative) = weighted_num_neighbours(kernel, &distances, smoothing_length);
+    if derivative <= 0.0 {
+        return SofteningCorrection::default();
+    }
+    let softening_length = adaptive_softening_length(smoothing_length);
+    SofteningCorrection(
+        neighbours
+            .iter()
+            .map(|(distance, mass)| {
+                -*mass
+                    * softening.inverse_distance_softening_derivative(*distance, softening_length)
+                    * softening_length
+                    / derivative
+            })
+            .sum(),
+    )
+}
+
+fn compute_softening_corrections_system(
+    mut particles: ActiveParticles<(&Position, &SmoothingLength, &mut SofteningCorrection)>,
+    masses: HydroParticles<&Mass>,
+    tree: Res<QuadTree>,
+    box_: Res<SimulationBox>,
+    parameters: Res<HydrodynamicsParameters>,
+    gravity_parameters: Res<GravityParameters>,
+    performance_parameters: Res<PerformanceParameters>,
+) {
+    particles.par_for_each_mut(
+        performance_parameters.batch_size(),
+        |(pos, smoothing_length, mut correction)| {
+            // Clamped smoothing lengths do not depend on the
+            // positions of the neighbours.
+            if **smoothing_length <= parameters.min_smoothing_length
+                || **smoothing_length >= parameters.max_smoothing_length
+            {
+                *correction = SofteningCorrection::default();
+                return;
+            }
+            let neighbours: Vec<_> = tree
+                .get_particles_in_gather_radius(&box_, pos, smoothing_length)
+                .into_iter()
+                .map(|neighbour| {
+                    (
+                        box_.periodic_distance(pos, &neighbour.pos),
+                        **masses.get(neighbour.entity).unwrap(),
+                    )
+                })
+                .collect();
+            *correction = softening_correction(
+                &parameters.kernel,
+                &gravity_parameters.softening,
+                **smoothing_length,
+                &neighbours,
+            );
+        },
+    );
+}
+
+/// The data of a single particle which enters the correction terms.
+struct CorrectionData {
+    pos: VecLength,
+    mass: units::Mass,
+    smoothing_length: Length,
+    correction: SofteningCorrection,
+}
+
+/// The acceleration of the first particle due to the correction
+/// terms of the pair. The corresponding forces are antisymmetric.
+fn correction_acceleration(
+    kernel: &SphKernel,
+    box_: &SimulationBox,
+    p1: &CorrectionData,
+    p2: &CorrectionData,
+) -> VecAcceleration {
+    let term = |p: &CorrectionData| {
+        *p.correction
+            * kernel_volume(p.smoothing_length)
+            * kernel_gradient(kernel, box_, p1.pos, p2.pos, p.smoothing_length)
+    };
+    0.5 * GRAVITY_CONSTANT * (term(p1) + term(p2) * (p2.mass / p1.mass))
+}
+
+fn softening_correction_forces_system(
+    mut particles1: ActiveParticles<(
+        &mut Acceleration,
+        &mut GravitationalAcceleration,
+        &Position,
+        &Mass,
+        &SmoothingLength,
+        &SofteningCorrection,
+    )>,
+    particles2: HydroParticles<(&Position, &Mass, &SmoothingLength, &SofteningCorrection)>,
+    tree: Res<QuadTree>,
+    box_: Res<SimulationBox>,
+    parameters: Res<HydrodynamicsParameters>,
+    performance_parameters: Res<PerformanceParameters>,
+) {
+    particles1.par_for_each_mut(
+        performance_parameters.batch_size(),
+        |(
+            mut acceleration1,
+            mut gravitational_acceleration1,
+            position1,
+            mass1,
+            smoothing_length1,
+            correction1,
+        )| {
+            let p1 = CorrectionData {
+                pos: **position1,
+                mass: **mass1,
+                smoothing_length: **smoothing_length1,
+                correction: correction1.clone(),
+            };
+            let mut acceleration = VecAcceleration::zero();
+            for particle in tree
+                .get_particles_in_radius(&box_, position1, smoothing_length1)
+                .iter()
+            {
+                let (position2, mass2, smoothing_length2, correction2) =
+                    particles2.get(particle.entity).unwrap();
+                if **position1 == **position2 {
+                    continue;
+                }
+                let p2 = CorrectionData {
+                    pos: **position2,
+                    mass: **mass2,
+                    smoothing_length: **smoothing_length2,
+                    correction: correction2.clone(),
+                };
+                acceleration += correction_acceleration(&parameters.kernel, &box_, &p1, &p2);
+            }
+            **acceleration1 += acceleration;
+            **gravitational_acceleration1 += acceleration;
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::adaptive_softening_length;
+    use super::correction_acceleration;
+    use super::softening_correction;
+    use super::CorrectionData;
+    use super::SelfGravityPlugin;
+    use crate::gravity::Softening;
+    use crate::hydrodynamics::SphKernel;
+    use crate::parameters::SimulationBox;
+    use crate::simulation::Simulation;
+    use crate::units::Length;
+    use crate::units::Mass;
+    use crate::units::VecLength;
+
+    #[test]
+    fn correction_forces_are_antisymmetric_and_attractive() {
+        let kernel = SphKernel::default();
+        let box_ = SimulationBox::cube_from_side_length_centered(Length::meters(100.0));
+        let smoothing_length1 = Length::meters(1.0);
+        let smoothing_length2 = Length::meters(1.5);
+        let mass1 = Mass::kilograms(1.0);
+        let mass2 = Mass::kilograms(3.0);
+        let neighbours = |smoothing_length: Length, mass| {
+            vec![
+                (Length::zero(), mass),
+                (0.3 * smoothing_length, Mass::kilograms(2.0)),
+                (0.6 * smoothing_length, Mass::kilograms(2.0)),
+            ]
+        };
+        let correction = |smoothing_length, mass| {
+            softening_correction(
+                &kernel,
+                &Softening::Spline,
+                smoothing_length,
+                &neighbours(smoothing_length, mass),
+            )
+        };
+        #[cfg(feature = "2d")]
+        let (pos1, pos2) = (VecLength::meters(0.0, 0.0), VecLength::meters(0.5, 0.2));
+        #[cfg(not(feature = "2d"))]
+        let (pos1, pos2) = (
+            VecLength::meters(0.0, 0.0, 0.0),
+            VecLength::meters(0.5, 0.2, -0.1),
+        );
+        let p1 = CorrectionData {
+            pos: pos1,
+            mass: mass1,
+            smoothing_length: smoothing_length1,
+            correction: correction(smoothing_length1, mass1),
+        };
+        let p2 = CorrectionData {
+            pos: pos2,
+            mass: mass2,
+            smoothing_length: smoothing_length2,
+            correction: correction(smoothing_length2, mass2),
+        };
+        assert!(p1.correction.value_unchecked() > 0.0);
+        assert!(p2.correction.value_unchecked() > 0.0);
+        let force1 = correction_acceleration(&kernel, &box_, &p1, &p2) * mass1;
+        let force2 = correction_acceleration(&kernel, &box_, &p2, &p1) * mass2;
+        assert!((force1 + force2).length() <= 1e-10 * force1.length());
+        // The correction pulls the particles towards each other.
+        assert!(force1.dot(pos2 - pos1).value_unchecked() > 0.0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "2d"))]
+    fn forces_conserve_momentum() {
+        use crate::units::Force;
+        use crate::units::VecAcceleration;
+        use crate::units::VecForce;
+        use crate::units::GRAVITY_CONSTANT;
+
+        let particles: Vec<_> = (0..20)
+            .map(|i| {
+                let x = i as f64;
+                (
+                    VecLength::meters(
+                        (1.3 * x).sin(),
+                        (2.1 * x + 0.5).cos(),
+                        0.5 * (0.7 * x).sin(),
+                    ),
+                    Mass::kilograms(1.0 + 0.1 * (i % 3) as f64),
+                    Length::meters(0.6 + 0.05 * (i % 5) as f64),
+                )
+            })
+            .collect();
+        let kernel = SphKernel::default();
+        let softening = Softening::Spline;
+        let box_ = SimulationBox::cube_from_side_length_centered(Length::meters(100.0));
+        let data: Vec<_> = particles
+            .iter()
+            .map(|(pos, mass, smoothing_length)| {
+                let neighbours: Vec<_> = particles
+                    .iter()
+                    .map(|(pos2, mass2, _)| (box_.periodic_distance(pos, pos2), *mass2))
+                    .filter(|(distance, _)| *distance < *smoothing_length)
+                    .collect();
+                CorrectionData {
+                    pos: *pos,
+                    mass: *mass,
+                    smoothing_length: *smoothing_length,
+                    correction: softening_correction(
+                        &kernel,
+                        &softening,
+                        *smoothing_length,
+                        &neighbours,
+                    ),
+                }
+            })
+            .collect();
+        // The symmetrised softened gravity of all other particles and
+        // the correction terms of all pairs within their smoothing
+        // lengths.
+        let accelerations: Vec<_> = data
+            .iter()
+            .enumerate()
+            .map(|(i, p1)| {
+                let mut acceleration = VecAcceleration::zero();
+                for (j, p2) in data.iter().enumerate() {
+                    if i == j {
+                        continue;
+                    }
+                    let distance_vector = box_.periodic_distance_vec(&p1.pos, &p2.pos);
+                    let distance = distance_vector.length();
+                    acceleration -= distance_vector
+                        * GRAVITY_CONSTANT
+                        * p2.mass
+                        * softening.symmetric_inverse_distance_cubed(
+                            distance,
+                            adaptive_softening_length(p1.smoothing_length),
+                            adaptive_softening_length(p2.smoothing_length),
+                        );
+                    if distance < p1.smoothing_length.max(p2.smoothing_length) {
+                        acceleration += correction_acceleration(&kernel, &box_, p1, p2);
+                    }
+                }
+                acceleration
+            })
+            .collect();
+        let forces: Vec<_> = particles
+            .iter()
+            .zip(accelerations.iter())
+            .map(|((_, mass, _), acceleration)| *acceleration * *mass)
+            .collect();
+        let total_force = forces
+            .iter()
+            .fold(VecForce::zero(), |sum, force| sum + *force);
+        let force_scale = forces
+            .iter()
+            .fold(Force::zero(), |sum, force| sum + force.length());
+        assert!(force_scale.value_unchecked() > 0.0);
+        assert!(total_force.length() <= 1e-10 * force_scale);
+    }
+
+    #[test]
+    #[should_panic(expected = "requires spline softening")]
+    fn plugin_requires_spline_softening() {
+        let mut sim = Simulation::default();
+        sim.add_parameter_file_contents("".into());
+        sim.add_plugin(SelfGravityPlugin);
+    }
+
+    #[test]
+    fn softening_follows_smoothing_length() {
+        let smoothing_length = Length::meters(2.8);
+        let softening_length = adaptive_softening_length(smoothing_length);
+        // The spline softening is Newtonian beyond the smoothing length.
+        let softening = Softening::Spline;
+        assert_eq!(
+            softening.inverse_distance(smoothing_length, softening_length),
+            1.0 / smoothing_length
+        );
+        assert_eq!(softening.support_radius(softening_length), smoothing_length);
+    }
+}
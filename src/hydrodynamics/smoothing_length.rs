@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use mpi::traits::Equivalence;
+
+use super::kernel_volume;
+use super::quadtree::QuadTree;
+use super::HydrodynamicsParameters;
+use super::SphKernel;
+use crate::communication::Communicator;
+use crate::communication::Rank;
+use crate::communication::WorldRank;
+use crate::components::Position;
+use crate::components::SmoothingLength;
+use crate::parameters::SimulationBox;
+use crate::performance_parameters::PerformanceParameters;
+use crate::prelude::ActiveParticles;
+use crate::prelude::Float;
+use crate::prelude::Particles;
+use crate::units::Length;
+
+/// The largest smoothing length of the local particles on each rank,
+/// as determined in the last round of the smoothing length iteration.
+/// Particles are sent to another rank as halo particles if they lie
+/// within this radius of its domain, so that every particle can find
+/// all of its neighbours within its own smoothing length.
+#[derive(Resource, Default)]
+pub(super) struct HaloRegion(HashMap<Rank, Length>);
+
+impl HaloRegion {
+    /// The radius around the particle which needs to be checked for
+    /// overlap with the domain of the given rank.
+    pub(super) fn radius(&self, rank: Rank, smoothing_length: Length) -> Length {
+        self.0
+            .get(&rank)
+            .map(|largest_smoothing_length| largest_smoothing_length.max(smoothing_length))
+            .unwrap_or(smoothing_length)
+    }
+}
+
+#[derive(Resource, Default)]
+pub(super) struct SmoothingLengthIterationState {
+    round: usize,
+    counts: SmoothingLengthCounts,
+    done: bool,
+}
+
+/// The number of particles for which the smoothing length
+/// iteration did not end in a converged state.
+#[derive(Equivalence, Clone, Copy, Default)]
+pub(super) struct SmoothingLengthCounts {
+    unconverged: usize,
+    clamped_min: usize,
+    clamped_max: usize,
+}
+
+#[derive(Equivalence, Clone)]
+pub(super) struct SmoothingLengthIterationResult {
+    rank: Rank,
+    largest_smoothing_length: Length,
+    counts: SmoothingLengthCounts,
+}
+
+/// The outcome of the smoothing length iteration of a single particle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SmoothingLengthOutcome {
+    /// The weighted number of neighbours matches the desired number
+    /// of neighbours within the tolerance.
+    Converged,
+    /// The particle has too many neighbours even at the minimum
+    /// smoothing length.
+    ClampedMin,
+    /// The particle has too few neighbours even at the maximum
+    /// smoothing length.
+    ClampedMax,
+    /// The iteration did not converge within the maximum number
+    /// of iterations.
+    NotConverged,
+}
+
+/// Repeats the smoothing length iteration until the halo region
+/// stopped growing on all ranks.
+pub(super) fn smoothing_length_iteration_criterion(
+    mut state: ResMut<SmoothingLengthIterationState>,
+) -> ShouldRun {
+    if state.done {
+        *state = SmoothingLengthIterationState::default();
+        ShouldRun::No
+    } else {
+        ShouldRun::YesAndCheckAgain
+    }
+}
+
+/// The kernel-weighted number of neighbours
+/// N(h) = V(h) sum_j W(r_j, h)
+/// where V(h) is the volume of the kernel support, along with
+/// its logarithmic derivative dN / dln(h).
+pub(super) fn weighted_num_neighbours(
+    kernel: &SphKernel,
+    distances: &[Length],
+    h: Length,
+) -> (Float, Float) {
+    let volume = kernel_volume(h);
+    let mut num_neighbours = 0.0;
+    let mut derivative = 0.0;
+    for distance in distances.iter() {
+        num_neighbours += (volume * kernel.value(*distance, h)).value();
+        derivative -= (volume * *distance * kernel.radial_derivative(*distance, h)).value();
+    }
+    (num_neighbours, derivative)
+}
+
+/// Finds the smoothing length for which the weighted number of
+/// neighbours matches the desired number of neighbours, starting
+/// from the initial guess. `get_distances` returns the distances
+/// to all particles within the given radius. Uses Newton-Raphson
+/// iteration and falls back to bisection whenever a Newton step
+/// would leave the current bracket. Returns the smoothing length
+/// and the outcome of the iteration.
+fn find_smoothing_length(
+    parameters: &HydrodynamicsParameters,
+    initial_guess: Length,
+    get_distances: impl Fn(Length) -> Vec<Length>,
+) -> (Length, SmoothingLengthOutcome) {
+    let target = parameters.num_smoothing_neighbours as Float;
+    let tolerance = parameters.smoothing_length_iteration.tolerance * target;
+    let min = parameters.min_smoothing_length;
+    let max = parameters.max_smoothing_length;
+    let mut lower: Option<Length> = None;
+    let mut upper: Option<Length> = None;
+    let mut h = initial_guess.clamp(min, max);
+    for _ in 0..parameters.smoothing_length_iteration.max_iterations {
+        let (num_neighbours, derivative) =
+            weighted_num_neighbours(&parameters.kernel, &get_distances(h), h);
+        let residual = num_neighbours - target;
+        if residual.abs() <= tolerance {
+            return (h, SmoothingLengthOutcome::Converged);
+        }
+        if residual < 0.0 {
+            if h >= max {
+                return (max, SmoothingLengthOutcome::ClampedMax);
+            }
+            lower = Some(h);
+        } else {
+            if h <= min {
+                return (min, SmoothingLengthOutcome::ClampedMin);
+            }
+            upper = Some(h);
+        }
+        let newton = h * (1.0 - residual / derivative);
+        let in_bracket = |h: Length| {
+            lower.map(|lower| h > lower).unwrap_or(true)
+                && upper.map(|upper| h < upper).unwrap_or(true)
+        };
+        let next = if derivative > 0.0 && in_bracket(newton) {
+            newton
+        } else {
+            match (lower, upper) {
+                (Some(lower), Some(upper)) => 0.5 * (lower + upper),
+                (Some(lower), None) => 2.0 * lower,
+                (None, Some(upper)) => 0.5 * upper,
+                (None, None) => unreachable!(),
+            }
+        };
+        h = next.clamp(min, max);
+    }
+    (h, SmoothingLengthOutcome::NotConverged)
+}
+
+pub(super) fn compute_smoothing_lengths_system(
+    mut particles: ActiveParticles<(&Position, &mut SmoothingLength)>,
+    mut state: ResMut<SmoothingLengthIterationState>,
+    tree: Res<QuadTree>,
+    box_: Res<SimulationBox>,
+    parameters: Res<HydrodynamicsParameters>,
+    performance_parameters: Res<PerformanceParameters>,
+) {
+    let num_unconverged = AtomicUsize::new(0);
+    let num_clamped_min = AtomicUsize::new(0);
+    let num_clamped_max = AtomicUsize::new(0);
+    particles.par_for_each_mut(
+        performance_parameters.batch_size(),
+        |(pos, mut smoothing_length)| {
+            let get_distances = |radius: Length| {
+                tree.get_particles_in_gather_radius(&box_, pos, &radius)
+                    .into_iter()
+                    .map(|particle| box_.periodic_distance(pos, &particle.pos))
+                    .collect::<Vec<_>>()
+            };
+            let (h, outcome) =
+                find_smoothing_length(&parameters, **smoothing_length, get_distances);
+            **smoothing_length = h;
+            let count = match outcome {
+                SmoothingLengthOutcome::Converged => return,
+                SmoothingLengthOutcome::ClampedMin => &num_clamped_min,
+                SmoothingLengthOutcome::ClampedMax => &num_clamped_max,
+                SmoothingLengthOutcome::NotConverged => &num_unconverged,
+            };
+            count.fetch_add(1, Ordering::Relaxed);
+        },
+    );
+    state.counts = SmoothingLengthCounts {
+        unconverged: num_unconverged.into_inner(),
+        clamped_min: num_clamped_min.into_inner(),
+        clamped_max: num_clamped_max.into_inner(),
+    };
+}
+
+pub(super) fn communicate_smoothing_length_iteration_system(
+    particles: Particles<&SmoothingLength>,
+    mut state: ResMut<SmoothingLengthIterationState>,
+    mut halo_region: ResMut<HaloRegion>,
+    mut communicator: Communicator<SmoothingLengthIterationResult>,
+    parameters: Res<HydrodynamicsParameters>,
+    world_rank: Res<WorldRank>,
+) {
+    let largest_smoothing_length = particles
+        .iter()
+        .map(|smoothing_length| **smoothing_length)
+        .fold(Length::zero(), |a, b| a.max(b));
+    let results = communicator.all_gather(&SmoothingLengthIterationResult {
+        rank: **world_rank,
+        largest_smoothing_length,
+        counts: state.counts,
+    });
+    let mut halo_region_grew = false;
+    for result in results.iter() {
+        let previous = halo_region
+            .0
+            .insert(result.rank, result.largest_smoothing_length);
+        halo_region_grew |= previous
+            .map(|previous| result.largest_smoothing_length > previous)
+            .unwrap_or(true);
+    }
+    state.round += 1;
+    let max_rounds = parameters.smoothing_length_iteration.max_rounds;
+    if !halo_region_grew || state.round >= max_rounds {
+        state.done = true;
+        if !world_rank.is_main() {
+            return;
+        }
+        let sum = |count: fn(&SmoothingLengthCounts) -> usize| -> usize {
+            results.iter().map(|result| count(&result.counts)).sum()
+        };
+        let num_unconverged = sum(|counts| counts.unconverged);
+        if num_unconverged > 0 {
+            warn!(
+                "Smoothing length iteration did not converge for {} particles",
+                num_unconverged
+            );
+        }
+        let num_clamped_min = sum(|counts| counts.clamped_min);
+        if num_clamped_min > 0 {
+            warn!(
+                "Smoothing length clamped to the minimum smoothing length for {} particles",
+                num_clamped_min
+            );
+        }
+        let num_clamped_max = sum(|counts| counts.clamped_max);
+        if num_clamped_max > 0 {
+            warn!(
+                "Smoothing length clamped to the maximum smoothing length for {} particles",
+                num_clamped_max
+            );
+        }
+        if halo_region_grew {
+            warn!(
+                "Halo region still growing after {} rounds of smoothing length iteration",
+                max_rounds
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_smoothing_length;
+    use super::weighted_num_neighbours;
+    use super::SmoothingLengthOutcome;
+    use crate::parameters::ArtificialViscosity;
+    use crate::parameters::HydrodynamicsParameters;
+    use crate::parameters::InitialGasEnergy;
+    use crate::parameters::SmoothingLengthIteration;
+    use crate::parameters::SphKernel;
+    use crate::prelude::Float;
+    use crate::quadtree::QuadTreeConfig;
+    use crate::units::Length;
+
+    fn lattice_distances(spacing: Length, num_per_side: i32) -> Vec<Length> {
+        let range = -num_per_side..=num_per_side;
+        #[cfg(feature = "2d")]
+        let distances = range
+            .clone()
+            .flat_map(|x| range.clone().map(move |y| (x * x + y * y) as Float))
+            .map(|dist_squared| spacing * dist_squared.sqrt())
+            .collect();
+        #[cfg(not(feature = "2d"))]
+        let distances = range
+            .clone()
+            .flat_map(|x| range.clone().map(move |y| (x, y)))
+            .flat_map(|(x, y)| range.clone().map(move |z| (x * x + y * y + z * z) as Float))
+            .map(|dist_squared| spacing * dist_squared.sqrt())
+            .collect();
+        distances
+    }
+
+    fn hydro_parameters(min: Length, max: Length) -> HydrodynamicsParameters {
+        HydrodynamicsParameters {
+            num_smoothing_neighbours: 32,
+            min_smoothing_length: min,
+            max_smoothing_length: max,
+            initial_gas_energy: InitialGasEnergy::Explicit,
+            tree: QuadTreeConfig::default(),
+            viscosity: ArtificialViscosity::default(),
+            smoothing_length_iteration: SmoothingLengthIteration::default(),
+            kernel: SphKernel::default(),
+        }
+    }
+
+    #[test]
+    fn smoothing_length_iteration_converges_on_lattice() {
+        let parameters = hydro_parameters(Length::meters(1e-5), Length::meters(1e5));
+        let distances = lattice_distances(Length::meters(1.0), 10);
+        let get_distances = |radius: Length| {
+            distances
+                .iter()
+                .filter(|distance| **distance < radius)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        for initial_guess in [1e-5, 0.1, 1.0, 3.0, 8.0] {
+            let (h, outcome) =
+                find_smoothing_length(&parameters, Length::meters(initial_guess), get_distances);
+            assert_eq!(outcome, SmoothingLengthOutcome::Converged);
+            let (num_neighbours, _) =
+                weighted_num_neighbours(&parameters.kernel, &get_distances(h), h);
+            assert!(
+                (num_neighbours - 32.0).abs()
+                    <= parameters.smoothing_length_iteration.tolerance * 32.0
+            );
+        }
+    }
+
+    #[test]
+    fn clamped_smoothing_lengths_are_reported() {
+        let distances = lattice_distances(Length::meters(1.0), 10);
+        let get_distances = |radius: Length| {
+            distances
+                .iter()
+                .filter(|distance| **distance < radius)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        // The desired number of neighbours is reached at a smoothing
+        // length of a few lattice spacings.
+        let parameters = hydro_parameters(Length::meters(1e-5), Length::meters(1.0));
+        let (h, outcome) = find_smoothing_length(&parameters, Length::meters(0.5), get_distances);
+        assert_eq!(outcome, SmoothingLengthOutcome::ClampedMax);
+        assert_eq!(h, Length::meters(1.0));
+        let parameters = hydro_parameters(Length::meters(5.0), Length::meters(1e5));
+        let (h, outcome) = find_smoothing_length(&parameters, Length::meters(8.0), get_distances);
+        assert_eq!(outcome, SmoothingLengthOutcome::ClampedMin);
+        assert_eq!(h, Length::meters(5.0));
+    }
+}
@@ -0,0 +1,24 @@
+use super::hydro_components::SignalVelocity;
+use super::hydro_components::SmoothingLength;
+use crate::timestep::TimestepCriterion;
+use crate::timestep::TimestepParameters;
+use crate::units::Time;
+
+/// The Courant-Friedrichs-Lewy criterion dt = C h / v_sig for SPH
+/// particles, where v_sig is the largest signal velocity between the
+/// particle and any of its neighbours, as computed during the force
+/// calculation.
+pub struct CourantCriterion;
+
+impl TimestepCriterion for CourantCriterion {
+    type Filter = ();
+
+    type Query = (&'static SmoothingLength, &'static SignalVelocity);
+
+    fn timestep(
+        parameters: &TimestepParameters,
+        (smoothing_length, signal_velocity): (&SmoothingLength, &SignalVelocity),
+    ) -> Time {
+        parameters.courant_factor * **smoothing_length / **signal_velocity
+    }
+}
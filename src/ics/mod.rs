@@ -16,6 +16,7 @@ pub use self::velocity_profile::ConstantVelocity;
 pub use self::velocity_profile::VelocityProfile;
 pub use self::velocity_profile::ZeroVelocity;
 use crate::components;
+use crate::components::Species;
 use crate::parameters::SimulationBox;
 use crate::prelude::LocalParticle;
 use crate::prelude::Named;
@@ -36,10 +37,15 @@ pub struct Sample {
     positions: Vec<VecLength>,
     velocities: Vec<VecVelocity>,
     masses: Vec<Mass>,
+    species: Species,
 }
 
 impl Sample {
-    fn new(pre_sample: PreSample, velocity_profile: &dyn VelocityProfile) -> Self {
+    fn new(
+        pre_sample: PreSample,
+        velocity_profile: &dyn VelocityProfile,
+        species: Species,
+    ) -> Self {
         let velocities = pre_sample
             .positions
             .iter()
@@ -49,6 +55,7 @@ impl Sample {
             positions: pre_sample.positions,
             velocities,
             masses: pre_sample.masses,
+            species,
         }
     }
 
@@ -64,6 +71,7 @@ impl Sample {
                 components::Position(pos),
                 components::Mass(mass),
                 components::Velocity(vel),
+                self.species,
             ));
         }
     }
@@ -83,6 +91,7 @@ pub struct InitialConditionsPlugin {
     density_profile: Box<dyn DensityProfile>,
     velocity_profile: Box<dyn VelocityProfile>,
     sampler: Box<dyn Sampler>,
+    species: Species,
 }
 
 impl Default for InitialConditionsPlugin {
@@ -91,6 +100,7 @@ impl Default for InitialConditionsPlugin {
             density_profile: Box::new(ConstantDensity(Density::zero())),
             velocity_profile: Box::new(ZeroVelocity),
             sampler: Box::new(MonteCarloSampler::num_particles(100)),
+            species: Species::default(),
         }
     }
 }
@@ -110,16 +120,37 @@ impl InitialConditionsPlugin {
         self.sampler = Box::new(sampler);
         self
     }
+
+    /// The species of the sampled particles. In order to set up
+    /// initial conditions with multiple species, the plugin can be
+    /// added once per species.
+    pub fn species(mut self, species: Species) -> Self {
+        self.species = species;
+        self
+    }
 }
 
 impl RaxiomPlugin for InitialConditionsPlugin {
+    fn allow_adding_twice(&self) -> bool {
+        true
+    }
+
+    fn should_build(&self, sim: &crate::simulation::Simulation) -> bool {
+        // The particles are read from the restart files instead.
+        !sim.restart
+    }
+
     fn build_on_main_rank(&self, sim: &mut crate::simulation::Simulation) {
         let box_ = sim.get_parameters::<SimulationBox>();
         let data = SamplingData {
             density_profile: self.density_profile.clone_box(),
             box_: box_.clone(),
         };
-        let mut sample = Sample::new(self.sampler.sample(&data), &*self.velocity_profile);
+        let mut sample = Sample::new(
+            self.sampler.sample(&data),
+            &*self.velocity_profile,
+            self.species,
+        );
         sim.add_startup_system(move |commands: Commands| {
             initial_conditions_system(commands, &mut sample)
         });
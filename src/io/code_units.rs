@@ -0,0 +1,64 @@
+use derive_custom::raxiom_parameters;
+
+use crate::units;
+use crate::units::Dimension;
+use crate::units::Quantity;
+
+/// A system of code units given by a unit of length, mass and
+/// velocity, as used by files in the Gadget layout and by input
+/// files which do not store their units. The unit of time is
+/// given by length / velocity. Temperatures are always given in
+/// kelvin.
+#[raxiom_parameters]
+pub struct CodeUnits {
+    #[serde(default = "default_unit_length")]
+    pub length: units::Length,
+    #[serde(default = "default_unit_mass")]
+    pub mass: units::Mass,
+    #[serde(default = "default_unit_velocity")]
+    pub velocity: units::Velocity,
+}
+
+fn default_unit_length() -> units::Length {
+    units::Length::kiloparsecs(1.0)
+}
+
+fn default_unit_mass() -> units::Mass {
+    units::Mass::solar(1e10)
+}
+
+fn default_unit_velocity() -> units::Velocity {
+    units::Velocity::kilometers_per_second(1.0)
+}
+
+impl Default for CodeUnits {
+    fn default() -> Self {
+        Self {
+            length: default_unit_length(),
+            mass: default_unit_mass(),
+            velocity: default_unit_velocity(),
+        }
+    }
+}
+
+impl CodeUnits {
+    /// The value of one code unit of the given dimension in SI units.
+    pub(super) fn conversion_factor(&self, dimension: Dimension) -> f64 {
+        // The unit of temperature is the SI unit, so the
+        // temperature does not contribute to the factor.
+        let Dimension {
+            length,
+            time,
+            mass,
+            temperature: _,
+        } = dimension;
+        let unit_time = self.length / self.velocity;
+        self.length.value_unchecked().powi(length)
+            * unit_time.value_unchecked().powi(time)
+            * self.mass.value_unchecked().powi(mass)
+    }
+
+    pub(super) fn to_code_units<const D: Dimension>(&self, quantity: Quantity<f64, D>) -> f64 {
+        quantity.value_unchecked() * D.base_conversion_factor() / self.conversion_factor(D)
+    }
+}
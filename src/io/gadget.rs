@@ -0,0 +1,407 @@
+use bevy::prelude::*;
+use derive_custom::raxiom_parameters;
+use hdf5::Dataset;
+use hdf5::File;
+use hdf5::Group;
+use hdf5::H5Type;
+use mpi::traits::Equivalence;
+use ndarray::ArrayView2;
+
+use super::code_units::CodeUnits;
+use super::input::InputChunk;
+use super::output::get_or_create_group;
+use super::output::parameters::OutputParameters;
+use super::output::parameters::SnapshotFiles;
+use super::output::OutputFile;
+use super::to_dataset::ToDataset;
+use crate::communication::Communicator;
+use crate::components::Mass;
+use crate::components::Species;
+use crate::components::NUM_SPECIES;
+use crate::prelude::Particles;
+use crate::prelude::SimulationBox;
+use crate::prelude::WorldSize;
+use crate::simulation_plugin::Time;
+use crate::units;
+
+/// The number of particle types in the Gadget layout.
+pub const NUM_PARTICLE_TYPES: usize = 6;
+
+const HEADER: &str = "Header";
+const PARAMETERS: &str = "Parameters";
+const NUM_PART_THIS_FILE: &str = "NumPart_ThisFile";
+const MASS_TABLE: &str = "MassTable";
+const UNIT_LENGTH: &str = "UnitLength_in_cm";
+const UNIT_MASS: &str = "UnitMass_in_g";
+const UNIT_VELOCITY: &str = "UnitVelocity_in_cm_per_s";
+const HUBBLE_PARAM: &str = "HubbleParam";
+
+/// The layout of snapshot and initial conditions files.
+#[derive(Default)]
+#[raxiom_parameters]
+pub enum FileFormat {
+    /// One group per species (`gas`, `dark_matter`, `star`, `sink`)
+    /// with one dataset per component. Every dataset stores its unit scale factor and its
+    /// dimension as attributes.
+    #[default]
+    Raxiom,
+    /// The HDF5 layout of Gadget and AREPO. The particles are
+    /// split into groups by type (`PartType0` for gas,
+    /// `PartType1` for dark matter, `PartType4` for stars and
+    /// `PartType5` for sink particles)
+    /// with datasets such as `Coordinates`, `Velocities`, `Masses`
+    /// and `InternalEnergy` (per unit mass) in the code units given
+    /// in the `Header`. Lengths and masses are read in units of
+    /// h^-1 times the code units, where h is the `HubbleParam` of
+    /// the `Header` (as in cosmological initial conditions).
+    Gadget,
+}
+
+fn particle_type(species: Species) -> usize {
+    match species {
+        Species::Gas => 0,
+        Species::DarkMatter => 1,
+        Species::Star => 4,
+        Species::Sink => 5,
+    }
+}
+
+/// The species of the particles of the given type. Apart from
+/// gas, stars and sinks, all types (such as the disk and bulge
+/// particles of Gadget) are read as dark matter.
+fn species(particle_type: usize) -> Species {
+    match particle_type {
+        0 => Species::Gas,
+        4 => Species::Star,
+        5 => Species::Sink,
+        _ => Species::DarkMatter,
+    }
+}
+
+fn per_particle_type(nums: &[usize; NUM_SPECIES]) -> [usize; NUM_PARTICLE_TYPES] {
+    let mut result = [0; NUM_PARTICLE_TYPES];
+    for species in Species::ALL {
+        result[particle_type(species)] = nums[species.index()];
+    }
+    result
+}
+
+impl CodeUnits {
+    /// Returns the units stored in the `Header` (or `Parameters`)
+    /// group of a Gadget file, falling back to self for any unit
+    /// that the file does not specify. The units of length and mass
+    /// are divided by the `HubbleParam` h of the file (if present),
+    /// since Gadget files store them in units of h^-1.
+    pub(super) fn from_file(&self, file: &File) -> Self {
+        let read = |name: &str| -> Option<f64> {
+            [HEADER, PARAMETERS]
+                .iter()
+                .find_map(|group| file.group(group).ok()?.attr(name).ok()?.read_scalar().ok())
+        };
+        let hubble_param = read(HUBBLE_PARAM).unwrap_or(1.0);
+        Self {
+            length: read(UNIT_LENGTH)
+                .map(units::Length::centimeters)
+                .unwrap_or(self.length)
+                / hubble_param,
+            mass: read(UNIT_MASS).map(units::Mass::grams).unwrap_or(self.mass) / hubble_param,
+            velocity: read(UNIT_VELOCITY)
+                .map(units::Velocity::centimeters_per_second)
+                .unwrap_or(self.velocity),
+        }
+    }
+}
+
+/// The name of the dataset of a component in the Gadget layout
+/// and whether the quantity is stored per unit mass.
+fn dataset_name<T: ToDataset>() -> (String, bool) {
+    match T::name() {
+        "position" => ("Coordinates".into(), false),
+        "velocity" => ("Velocities".into(), false),
+        "mass" => ("Masses".into(), false),
+        "internal_energy" => ("InternalEnergy".into(), true),
+        "particle_id" => ("ParticleIDs".into(), false),
+        name => (
+            name.split('_')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    chars
+                        .next()
+                        .map(|first| first.to_uppercase().chain(chars).collect())
+                        .unwrap_or_default()
+                })
+                .collect::<String>(),
+            false,
+        ),
+    }
+}
+
+fn group_name(particle_type: usize) -> String {
+    format!("PartType{particle_type}")
+}
+
+fn to_floats<T: ToDataset>(data: &[T], num_floats: usize) -> Vec<f64> {
+    let mut floats = Vec::with_capacity(data.len() * num_floats);
+    for item in data {
+        item.to_floats(&mut floats);
+    }
+    floats
+}
+
+fn from_floats<T: ToDataset>(data: &[f64], num_floats: usize) -> Vec<T> {
+    data.chunks_exact(num_floats).map(T::from_floats).collect()
+}
+
+pub(super) fn write_dataset_system<T: ToDataset + Equivalence>(
+    query: Particles<(&T, &Mass, Option<&Species>)>,
+    parameters: Res<OutputParameters>,
+    mut file: ResMut<OutputFile>,
+    mut comm: Communicator<T>,
+) {
+    let units = &parameters.gadget_units;
+    let (name, per_mass) = dataset_name::<T>();
+    let factor = T::dimension().base_conversion_factor() / units.conversion_factor(T::dimension());
+    let mut data: [Vec<T>; NUM_SPECIES] = Default::default();
+    for (item, mass, species) in query.iter() {
+        let factor = if per_mass {
+            factor / units.to_code_units(**mass)
+        } else {
+            factor
+        };
+        data[species.copied().unwrap_or_default().index()]
+            .push(item.clone().convert_base_units(factor));
+    }
+    let data = match file.gather(data, &mut comm) {
+        Some(data) => data,
+        None => return,
+    };
+    file.write(move |f, slice| {
+        for species in Species::ALL {
+            let data = &data[species.index()];
+            if !data.is_empty() {
+                let group = get_or_create_group(f, &group_name(particle_type(species)));
+                let offset = slice.offset[species.index()];
+                let total = slice.total[species.index()];
+                // Components which do not consist of floats (such as
+                // the particle ids) are written as they are.
+                match T::num_floats() {
+                    Some(num_floats) => write_dataset(
+                        &group,
+                        &name,
+                        &to_floats(data, num_floats),
+                        num_floats,
+                        offset,
+                        total,
+                    ),
+                    None => write_dataset(&group, &name, data, 1, offset, total),
+                }
+            }
+        }
+    });
+}
+
+fn write_dataset<T: H5Type>(
+    group: &Group,
+    name: &str,
+    data: &[T],
+    num_floats: usize,
+    offset: usize,
+    total: usize,
+) {
+    // The first rank to write to the file creates the dataset.
+    let dataset = group.dataset(name).unwrap_or_else(|_| {
+        let builder = group.new_dataset::<T>();
+        if num_floats == 1 {
+            builder.shape(total).create(name)
+        } else {
+            builder.shape((total, num_floats)).create(name)
+        }
+        .expect("Failed to write dataset")
+    });
+    let num_particles = data.len() / num_floats;
+    let result = if num_floats == 1 {
+        dataset.write_slice(data, offset..offset + num_particles)
+    } else {
+        let data = ArrayView2::from_shape((num_particles, num_floats), data).unwrap();
+        dataset.write_slice(data, (offset..offset + num_particles, ..))
+    };
+    result.expect("Failed to write dataset");
+}
+
+fn write_header_values<T: H5Type>(header: &Group, name: &str, values: &[T]) {
+    header
+        .new_attr::<T>()
+        .shape(values.len())
+        .create(name)
+        .unwrap()
+        .write_raw(values)
+        .unwrap();
+}
+
+fn write_header_scalar<T: H5Type>(header: &Group, name: &str, value: T) {
+    header
+        .new_attr::<T>()
+        .shape(())
+        .create(name)
+        .unwrap()
+        .write_scalar(&value)
+        .unwrap();
+}
+
+pub(super) fn write_header_system(
+    file: Res<OutputFile>,
+    parameters: Res<OutputParameters>,
+    time: Res<Time>,
+    box_size: Res<SimulationBox>,
+    world_size: Res<WorldSize>,
+) {
+    let f = match file.attribute_file() {
+        Some(f) => f,
+        None => return,
+    };
+    let units = &parameters.gadget_units;
+    let header = get_or_create_group(f, HEADER);
+    let this_file = per_particle_type(&file.slice.total);
+    let total = per_particle_type(&file.num_particles_total);
+    let low_word = |nums: [usize; NUM_PARTICLE_TYPES]| nums.map(|num| num as u32);
+    let high_word = |nums: [usize; NUM_PARTICLE_TYPES]| nums.map(|num| (num >> 32) as u32);
+    write_header_values(&header, NUM_PART_THIS_FILE, &low_word(this_file));
+    write_header_values(&header, "NumPart_Total", &low_word(total));
+    write_header_values(&header, "NumPart_Total_HighWord", &high_word(total));
+    write_header_values(&header, MASS_TABLE, &[0.0f64; NUM_PARTICLE_TYPES]);
+    let num_files = match parameters.snapshot_files {
+        SnapshotFiles::PerRank => **world_size,
+        SnapshotFiles::Single => 1,
+    };
+    write_header_scalar(&header, "NumFilesPerSnapshot", num_files as i32);
+    write_header_scalar(&header, "Time", units.to_code_units(**time));
+    write_header_scalar(&header, "Redshift", 0.0f64);
+    write_header_scalar(
+        &header,
+        "BoxSize",
+        units.to_code_units(box_size.max_side_length()),
+    );
+    write_header_scalar(&header, "Omega0", 0.0f64);
+    write_header_scalar(&header, "OmegaLambda", 0.0f64);
+    write_header_scalar(&header, HUBBLE_PARAM, 1.0f64);
+    write_header_scalar(&header, "Flag_DoublePrecision", 1i32);
+    write_header_scalar(&header, UNIT_LENGTH, units.length.in_centimeters());
+    write_header_scalar(&header, UNIT_MASS, units.mass.in_grams());
+    write_header_scalar(
+        &header,
+        UNIT_VELOCITY,
+        units.velocity.in_centimeters_per_second(),
+    );
+}
+
+/// Splits an input file into one chunk per particle type which
+/// is present in the file.
+pub(super) fn get_chunks(file: File, units: &CodeUnits) -> Vec<InputChunk> {
+    let units = units.from_file(&file);
+    let num_particles: Vec<u64> = file
+        .group(HEADER)
+        .and_then(|header| header.attr(NUM_PART_THIS_FILE))
+        .and_then(|attr| attr.read_raw())
+        .unwrap_or_else(|e| panic!("Failed to read {NUM_PART_THIS_FILE} from header: {e:?}"));
+    num_particles
+        .into_iter()
+        .enumerate()
+        .filter(|(_, num_particles)| *num_particles > 0)
+        .map(|(particle_type, num_particles)| InputChunk {
+            group: file
+                .group(&group_name(particle_type))
+                .unwrap_or_else(|e| panic!("Failed to open group: {e:?}")),
+            file: file.clone(),
+            particle_type,
+            species: Some(species(particle_type)),
+            num_particles: num_particles as usize,
+            range: 0..num_particles as usize,
+            units: units.clone(),
+        })
+        .collect()
+}
+
+fn check_length(chunk: &InputChunk, dataset: &Dataset, name: &str) {
+    assert_eq!(
+        dataset.shape()[0],
+        chunk.num_particles,
+        "Length of dataset {name} does not match the number of particles in the header."
+    );
+}
+
+fn read_floats(chunk: &InputChunk, name: &str) -> Option<Vec<f64>> {
+    let dataset = chunk.group.dataset(name).ok()?;
+    check_length(chunk, &dataset, name);
+    let data = if dataset.ndim() == 1 {
+        dataset
+            .read_slice_1d::<f64, _>(chunk.range.clone())
+            .map(|data| data.into_raw_vec())
+    } else {
+        dataset
+            .read_slice_2d::<f64, _>((chunk.range.clone(), ..))
+            .map(|data| data.into_raw_vec())
+    };
+    Some(data.unwrap_or_else(|e| panic!("Failed to read dataset: {name}, {e:?}")))
+}
+
+/// The masses of the particles in the chunk in code units, either
+/// from the `Masses` dataset or from the `MassTable` in the header.
+fn read_masses(chunk: &InputChunk) -> Vec<f64> {
+    read_floats(chunk, "Masses").unwrap_or_else(|| {
+        let mass_table: Vec<f64> = chunk
+            .file
+            .group(HEADER)
+            .and_then(|header| header.attr(MASS_TABLE))
+            .and_then(|attr| attr.read_raw())
+            .unwrap_or_else(|e| panic!("Failed to read {MASS_TABLE} from header: {e:?}"));
+        vec![mass_table[chunk.particle_type]; chunk.range.len()]
+    })
+}
+
+/// Reads the component from a chunk in the Gadget layout and
+/// converts it to internal units. Returns None if the file does
+/// not contain the dataset.
+pub(super) fn read_dataset<T: ToDataset>(chunk: &InputChunk) -> Option<Vec<T>> {
+    let (name, per_mass) = dataset_name::<T>();
+    let num_floats = match T::num_floats() {
+        Some(num_floats) => num_floats,
+        None => {
+            let dataset = chunk.group.dataset(&name).ok()?;
+            check_length(chunk, &dataset, &name);
+            return Some(
+                dataset
+                    .read_slice_1d::<T, _>(chunk.range.clone())
+                    .unwrap_or_else(|e| panic!("Failed to read dataset: {name}, {e:?}"))
+                    .into_raw_vec(),
+            );
+        }
+    };
+    let data = if name == "Masses" {
+        read_masses(chunk)
+    } else {
+        read_floats(chunk, &name)?
+    };
+    let factor =
+        chunk.units.conversion_factor(T::dimension()) / T::dimension().base_conversion_factor();
+    assert_eq!(
+        data.len(),
+        chunk.range.len() * num_floats,
+        "Dataset {name} does not have {num_floats} columns."
+    );
+    let data = from_floats::<T>(&data, num_floats);
+    if per_mass {
+        let masses = read_masses(chunk);
+        Some(
+            data.into_iter()
+                .zip(masses)
+                .map(|(item, mass)| item.convert_base_units(factor * mass))
+                .collect(),
+        )
+    } else {
+        Some(
+            data.into_iter()
+                .map(|item| item.convert_base_units(factor))
+                .collect(),
+        )
+    }
+}
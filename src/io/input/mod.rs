@@ -1,14 +1,21 @@
 #[cfg(test)]
 mod tests;
 
+use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::ops::Range;
+use std::path::Path;
 use std::path::PathBuf;
 
 use bevy::prelude::*;
 use derive_custom::raxiom_parameters;
 use hdf5::Dataset;
 use hdf5::File;
+use hdf5::Group;
 
+use super::code_units::CodeUnits;
+use super::gadget;
+use super::gadget::FileFormat;
 use super::to_dataset::ToDataset;
 use super::to_dataset::LENGTH_IDENTIFIER;
 use super::to_dataset::MASS_IDENTIFIER;
@@ -16,12 +23,14 @@ use super::to_dataset::TEMPERATURE_IDENTIFIER;
 use super::to_dataset::TIME_IDENTIFIER;
 use crate::communication::WorldRank;
 use crate::communication::WorldSize;
+use crate::components::Species;
 use crate::io::to_dataset::SCALE_FACTOR_IDENTIFIER;
 use crate::named::Named;
 use crate::prelude::LocalParticle;
 use crate::simulation::RaxiomPlugin;
 use crate::simulation::Simulation;
 use crate::units::Dimension;
+use crate::units::NONE;
 
 /// Determines how a component is input into the simulation.
 pub enum ComponentInput {
@@ -30,10 +39,30 @@ pub enum ComponentInput {
     /// The component does not need to be present and will be inserted
     /// by a startup system.
     Derived,
+    /// The component is read from the initial conditions if they
+    /// contain it. Otherwise, it will be inserted by a startup system.
+    Optional,
+}
+
+/// A group of particles within an input file together with the
+/// range of particles within it that this rank reads. Every
+/// species (in the Gadget layout: every particle type) within a
+/// file is a separate chunk.
+pub(super) struct InputChunk {
+    pub file: File,
+    pub group: Group,
+    pub particle_type: usize,
+    /// The species of the particles in the chunk. None for files
+    /// which store all particles at the root of the file.
+    pub species: Option<Species>,
+    pub num_particles: usize,
+    pub range: Range<usize>,
+    /// The code units of the file. Only used in the Gadget layout.
+    pub units: CodeUnits,
 }
 
 #[derive(Default, Deref, DerefMut, Resource)]
-struct InputFiles(Vec<File>);
+struct InputChunks(Vec<InputChunk>);
 
 /// Parameters describing how the initial conditions
 /// should be read. Only required if should_read_initial_conditions
@@ -43,6 +72,48 @@ struct InputFiles(Vec<File>);
 pub struct InputParameters {
     /// The files containing the initial conditions
     pub paths: Vec<PathBuf>,
+    /// The layout of the files.
+    #[serde(default)]
+    pub format: FileFormat,
+    /// The code units of files in the Gadget layout which do not
+    /// specify their units in the header.
+    #[serde(default)]
+    pub gadget_units: CodeUnits,
+    /// Describes files in the raxiom layout which were not written
+    /// by raxiom.
+    #[serde(default)]
+    pub schema: InputSchema,
+}
+
+/// Describes how the datasets of input files in the raxiom layout
+/// map to components and which units they are in.
+#[derive(Default)]
+#[raxiom_parameters]
+pub struct InputSchema {
+    /// The names of the datasets containing the components, by
+    /// component name. Components which are not listed are read
+    /// from the dataset with the name of the component.
+    /// Example: {position: Coordinates, velocity: Velocities}
+    #[serde(default)]
+    pub datasets: HashMap<String, String>,
+    /// The units of datasets which do not store their units as
+    /// attributes, given by a unit of length, mass and velocity.
+    /// Example: {length: 1 kpc, velocity: 1 km/s, mass: 1e10 Msol}
+    /// Datasets with unit attributes are always read in the units
+    /// given by their attributes. Base dimensions without a
+    /// `scaling_*` attribute are read in these units.
+    #[serde(default)]
+    pub units: Option<CodeUnits>,
+}
+
+impl InputSchema {
+    /// The name of the dataset containing the component.
+    pub fn dataset_name<'a>(&'a self, component: &'a str) -> &'a str {
+        self.datasets
+            .get(component)
+            .map(|name| name.as_str())
+            .unwrap_or(component)
+    }
 }
 
 #[derive(Default, Deref, DerefMut, Resource)]
@@ -50,20 +121,34 @@ struct SpawnedEntities(Vec<Entity>);
 
 #[derive(Named)]
 pub struct DatasetInputPlugin<T> {
+    required: bool,
     _marker: PhantomData<T>,
 }
 
 impl<T> Default for DatasetInputPlugin<T> {
     fn default() -> Self {
         Self {
+            required: true,
             _marker: PhantomData::default(),
         }
     }
 }
 
+impl<T> DatasetInputPlugin<T> {
+    /// Reads the component only if the input files contain it.
+    pub fn optional() -> Self {
+        Self {
+            required: false,
+            ..default()
+        }
+    }
+}
+
 #[derive(SystemLabel)]
 struct ReadDatasetLabel;
 
+/// The names of the datasets which are required to be present in
+/// the input files.
 #[derive(Default, Deref, DerefMut, Resource)]
 pub struct RegisteredDatasets(Vec<&'static str>);
 
@@ -77,8 +162,12 @@ impl<T: ToDataset + Component + Sync + Send + 'static> RaxiomPlugin for DatasetI
     }
 
     fn build_once_everywhere(&self, sim: &mut Simulation) {
-        sim.add_parameter_type::<InputParameters>()
-            .insert_resource(InputFiles::default())
+        sim.add_parameter_type::<InputParameters>();
+        // The particles are read from the restart files instead.
+        if sim.restart {
+            return;
+        }
+        sim.insert_resource(InputChunks::default())
             .insert_resource(SpawnedEntities::default())
             .add_startup_system(open_file_system)
             .add_startup_system(
@@ -90,8 +179,13 @@ impl<T: ToDataset + Component + Sync + Send + 'static> RaxiomPlugin for DatasetI
     }
 
     fn build_everywhere(&self, sim: &mut Simulation) {
+        if sim.restart {
+            return;
+        }
         let mut registered_datasets = sim.get_resource_or_insert_with(RegisteredDatasets::default);
-        registered_datasets.push(T::name());
+        if self.required {
+            registered_datasets.push(T::name());
+        }
         sim.add_startup_system(
             read_dataset_system::<T>
                 .after(open_file_system)
@@ -104,129 +198,242 @@ impl<T: ToDataset + Component + Sync + Send + 'static> RaxiomPlugin for DatasetI
 }
 
 fn open_file_system(
-    mut files: ResMut<InputFiles>,
+    mut chunks: ResMut<InputChunks>,
     parameters: Res<InputParameters>,
+    datasets: Res<RegisteredDatasets>,
     rank: Res<WorldRank>,
     size: Res<WorldSize>,
 ) {
-    let files_this_rank_should_open: Vec<_> = parameters
-        .paths
-        .iter()
-        .enumerate()
-        .filter(|(i, _)| i.rem_euclid(**size) == **rank as usize)
-        .map(|(_, file)| file)
-        .collect();
-    assert!(files.is_empty());
-    for path in files_this_rank_should_open.iter() {
-        info!(
-            "Reading initial conditions file: {}",
+    assert!(chunks.is_empty());
+    if parameters.paths.len() >= **size {
+        // Every rank reads entire files.
+        for (_, path) in parameters
+            .paths
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i.rem_euclid(**size) == **rank as usize)
+        {
+            chunks.extend(get_chunks(open_file(path), &parameters, &datasets));
+        }
+    } else {
+        // There are fewer files than ranks (for example a single
+        // snapshot file), so every rank reads its share of the
+        // particles in all the files.
+        let all_chunks: Vec<_> = parameters
+            .paths
+            .iter()
+            .flat_map(|path| get_chunks(open_file(path), &parameters, &datasets))
+            .collect();
+        let total: usize = all_chunks.iter().map(|chunk| chunk.num_particles).sum();
+        let start = total * **rank as usize / **size;
+        let end = total * (**rank as usize + 1) / **size;
+        let mut offset = 0;
+        for mut chunk in all_chunks {
+            let num_particles = chunk.num_particles;
+            chunk.range = start.max(offset) - offset..end.min(offset + num_particles) - offset;
+            offset += num_particles;
+            if !chunk.range.is_empty() {
+                chunks.push(chunk);
+            }
+        }
+    }
+}
+
+fn open_file(path: &Path) -> File {
+    info!(
+        "Reading initial conditions file: {}",
+        path.to_str().unwrap()
+    );
+    File::open(path).unwrap_or_else(|_| {
+        panic!(
+            "Failed to open initial conditions file: {}",
             path.to_str().unwrap()
-        );
-        files.push(File::open(path).unwrap_or_else(|_| {
-            panic!(
-                "Failed to open initial conditions file: {}",
-                path.to_str().unwrap()
-            )
-        }));
+        )
+    })
+}
+
+fn get_chunks(
+    file: File,
+    parameters: &InputParameters,
+    datasets: &RegisteredDatasets,
+) -> Vec<InputChunk> {
+    match parameters.format {
+        FileFormat::Raxiom => {
+            let chunk = |group: Group, species: Option<Species>| {
+                let num_particles = get_num_particles(&group, datasets, &parameters.schema);
+                InputChunk {
+                    group,
+                    file: file.clone(),
+                    particle_type: 0,
+                    species,
+                    num_particles,
+                    range: 0..num_particles,
+                    units: parameters.gadget_units.clone(),
+                }
+            };
+            let chunks: Vec<_> = Species::ALL
+                .into_iter()
+                .filter_map(|species| {
+                    let group = file.group(species.group_name()).ok()?;
+                    Some(chunk(group, Some(species)))
+                })
+                .collect();
+            // Files without species groups contain all
+            // particles at their root.
+            if chunks.is_empty() {
+                vec![chunk((*file).clone(), None)]
+            } else {
+                chunks
+            }
+        }
+        FileFormat::Gadget => gadget::get_chunks(file, &parameters.gadget_units),
     }
 }
 
-fn close_file_system(mut files: ResMut<InputFiles>) {
-    files.0.clear();
+fn get_num_particles(group: &Group, datasets: &RegisteredDatasets, schema: &InputSchema) -> usize {
+    datasets
+        .first()
+        .map(|name| {
+            let name = schema.dataset_name(name);
+            group
+                .dataset(name)
+                .unwrap_or_else(|e| panic!("Failed to open dataset: {name}, {e:?}"))
+                .shape()[0]
+        })
+        .unwrap_or(0)
+}
+
+fn close_file_system(mut chunks: ResMut<InputChunks>) {
+    chunks.0.clear();
 }
 
 fn spawn_entities_system(
     mut commands: Commands,
     mut spawned_entities: ResMut<SpawnedEntities>,
-    datasets: Res<RegisteredDatasets>,
-    files: Res<InputFiles>,
+    chunks: Res<InputChunks>,
 ) {
-    if datasets.len() == 0 {
-        return;
-    }
-    let example_dataset = datasets[0];
-    let get_num_entities = |dataset_name: &str| {
-        files
-            .iter()
-            .map(|f| f.dataset(dataset_name).unwrap().shape()[0])
-            .sum::<usize>()
-    };
-    let num_entities = get_num_entities(example_dataset);
-    for dataset in datasets.iter() {
-        let num_entities_this_dataset = get_num_entities(dataset);
-        if num_entities_this_dataset != num_entities {
-            panic!(
-                "Different lengths of datasets: {example_dataset} ({num_entities}) and {dataset} ({num_entities_this_dataset})",
-            );
-        }
-    }
     assert_eq!(spawned_entities.len(), 0);
-    spawned_entities.0 = (0..num_entities)
-        .map(|_| commands.spawn((LocalParticle,)).id())
-        .collect();
+    for chunk in chunks.iter() {
+        spawned_entities.extend(chunk.range.clone().map(|_| {
+            let mut entity = commands.spawn((LocalParticle,));
+            if let Some(species) = chunk.species {
+                entity.insert(species);
+            }
+            entity.id()
+        }));
+    }
 }
 
 fn read_dataset_system<T: ToDataset + Component>(
     mut commands: Commands,
-    files: Res<InputFiles>,
+    chunks: Res<InputChunks>,
+    parameters: Res<InputParameters>,
+    datasets: Res<RegisteredDatasets>,
     spawned_entities: Res<SpawnedEntities>,
 ) {
     let name = T::name();
-    let data = files.iter().map(|file| {
-        let set = file
-            .dataset(name)
-            .unwrap_or_else(|e| panic!("Failed to open dataset: {name}, {e:?}"));
-        let data = set
-            .read_1d::<T>()
-            .unwrap_or_else(|e| panic!("Failed to read dataset: {name}, {e:?}"));
-        let conversion_factor: f64 = set
-            .attr(SCALE_FACTOR_IDENTIFIER)
-            .expect("No scale factor in dataset")
-            .read_scalar()
-            .unwrap();
-        assert_eq!(
-            read_dimension(&set),
-            T::dimension(),
-            "Mismatch in dimension while reading dataset {name}.",
-        );
-        (data, conversion_factor)
-    });
-    for ((item, factor_written), entity) in data
-        .flat_map(|(set, factor_written)| set.into_iter().map(move |item| (item, factor_written)))
-        .zip(spawned_entities.iter())
-    {
-        let factor_read = T::dimension().base_conversion_factor();
-        commands
-            .entity(*entity)
-            .insert(item.convert_base_units(factor_written / factor_read));
+    let required = datasets.contains(&name);
+    let mut offset = 0;
+    for chunk in chunks.iter() {
+        let entities = &spawned_entities[offset..offset + chunk.range.len()];
+        offset += chunk.range.len();
+        let data = match parameters.format {
+            FileFormat::Raxiom => read_dataset::<T>(chunk, &parameters.schema),
+            FileFormat::Gadget => gadget::read_dataset::<T>(chunk),
+        };
+        match data {
+            Some(data) => {
+                for (item, entity) in data.into_iter().zip(entities.iter()) {
+                    commands.entity(*entity).insert(item);
+                }
+            }
+            None => {
+                if required {
+                    panic!("Failed to open dataset: {name}");
+                }
+            }
+        }
     }
 }
 
-fn read_dimension(dataset: &Dataset) -> Dimension {
-    let length: i32 = dataset
-        .attr(LENGTH_IDENTIFIER)
-        .expect("No length scale factor in dataset")
-        .read_scalar()
-        .unwrap();
-    let mass: i32 = dataset
-        .attr(MASS_IDENTIFIER)
-        .expect("No mass scale factor in dataset")
-        .read_scalar()
-        .unwrap();
-    let time: i32 = dataset
-        .attr(TIME_IDENTIFIER)
-        .expect("No time scale factor in dataset")
-        .read_scalar()
-        .unwrap();
-    let temperature: i32 = dataset
-        .attr(TEMPERATURE_IDENTIFIER)
-        .expect("No temperature scale factor in dataset")
-        .read_scalar()
-        .unwrap();
-    Dimension {
-        length,
-        mass,
-        time,
-        temperature,
+/// Reads the component from a chunk in the raxiom layout and
+/// converts it to internal units. Returns None if the file does
+/// not contain the dataset.
+fn read_dataset<T: ToDataset>(chunk: &InputChunk, schema: &InputSchema) -> Option<Vec<T>> {
+    let name = schema.dataset_name(T::name());
+    let set = chunk.group.dataset(name).ok()?;
+    let num_particles_this_dataset = set.shape()[0];
+    if num_particles_this_dataset != chunk.num_particles {
+        panic!(
+            "Different lengths of datasets: {name} ({num_particles_this_dataset}) and other datasets ({})",
+            chunk.num_particles
+        );
     }
+    let data = set
+        .read_slice_1d::<T, _>(chunk.range.clone())
+        .unwrap_or_else(|e| panic!("Failed to read dataset: {name}, {e:?}"));
+    let schema_units = |missing: &str| {
+        schema.units.as_ref().unwrap_or_else(|| {
+            panic!("No {missing} in dataset {name} and no units given in the input schema.")
+        })
+    };
+    let factor_written: f64 = match set.attr(SCALE_FACTOR_IDENTIFIER) {
+        Ok(attr) => {
+            let (dimension, missing) = read_dimension(&set, T::dimension());
+            assert_eq!(
+                dimension,
+                T::dimension(),
+                "Mismatch in dimension while reading dataset {name}.",
+            );
+            let factor: f64 = attr.read_scalar().unwrap();
+            if missing == NONE {
+                factor
+            } else {
+                factor * schema_units("dimension attributes").conversion_factor(missing)
+            }
+        }
+        // Dimensionless datasets (such as the particle ids) do not
+        // need any units.
+        Err(_) if T::dimension() == NONE => 1.0,
+        Err(_) => schema_units("scale factor").conversion_factor(T::dimension()),
+    };
+    let factor_read = T::dimension().base_conversion_factor();
+    Some(
+        data.into_iter()
+            .map(|item| item.convert_base_units(factor_written / factor_read))
+            .collect(),
+    )
+}
+
+/// Reads the dimension stored in the unit attributes of the
+/// dataset. Base dimensions without an attribute are assumed to
+/// have their expected exponent and to be given in the units of
+/// the input schema (the scale factor of the dataset only covers
+/// the base dimensions with an attribute). Returns the dimension
+/// of the dataset and the dimension of the part without attributes.
+fn read_dimension(dataset: &Dataset, expected: Dimension) -> (Dimension, Dimension) {
+    let read = |identifier: &str, expected: i32| -> (i32, i32) {
+        match dataset.attr(identifier) {
+            Ok(attr) => (attr.read_scalar().unwrap(), 0),
+            Err(_) => (expected, expected),
+        }
+    };
+    let (length, missing_length) = read(LENGTH_IDENTIFIER, expected.length);
+    let (mass, missing_mass) = read(MASS_IDENTIFIER, expected.mass);
+    let (time, missing_time) = read(TIME_IDENTIFIER, expected.time);
+    let (temperature, missing_temperature) = read(TEMPERATURE_IDENTIFIER, expected.temperature);
+    (
+        Dimension {
+            length,
+            mass,
+            time,
+            temperature,
+        },
+        Dimension {
+            length: missing_length,
+            mass: missing_mass,
+            time: missing_time,
+            temperature: missing_temperature,
+        },
+    )
 }
@@ -7,11 +7,26 @@ use bevy::prelude::World;
 use super::close_file_system;
 use super::open_file_system;
 use super::read_dataset_system;
-use super::InputFiles;
+use super::spawn_entities_system;
+use super::InputChunks;
 use super::InputParameters;
+use super::InputSchema;
+use super::RegisteredDatasets;
 use super::SpawnedEntities;
+use crate::components::InternalEnergy;
 use crate::components::Mass;
+use crate::components::ParticleId;
+use crate::components::Position;
+use crate::components::Species;
+use crate::config::NUM_DIMENSIONS;
+use crate::io::code_units::CodeUnits;
+use crate::io::gadget::FileFormat;
 use crate::io::to_dataset::ToDataset;
+use crate::io::to_dataset::LENGTH_IDENTIFIER;
+use crate::io::to_dataset::SCALE_FACTOR_IDENTIFIER;
+use crate::io::to_dataset::TEMPERATURE_IDENTIFIER;
+use crate::io::to_dataset::TIME_IDENTIFIER;
+use crate::named::Named;
 use crate::prelude::WorldRank;
 use crate::prelude::WorldSize;
 use crate::test_utils::assert_is_close;
@@ -50,15 +65,314 @@ fn panic_on_dimension_mismatch() {
 }
 
 fn read_dataset_from_file<T: ToDataset + Component>(world: &mut World, file: &Path) {
-    let entity = world.spawn_empty().id();
-    world.insert_resource(SpawnedEntities(vec![entity]));
-    world.insert_resource(InputFiles(vec![]));
-    world.insert_resource(WorldRank(0));
-    world.insert_resource(WorldSize(1));
+    read_dataset_from_file_on_rank::<T>(world, file, 0, 1)
+}
+
+fn read_dataset_from_file_on_rank<T: ToDataset + Component>(
+    world: &mut World,
+    file: &Path,
+    rank: i32,
+    size: usize,
+) {
+    world.insert_resource(SpawnedEntities::default());
+    world.insert_resource(InputChunks(vec![]));
+    world.insert_resource(RegisteredDatasets(vec![T::name()]));
+    world.insert_resource(WorldRank(rank));
+    world.insert_resource(WorldSize(size));
     world.insert_resource(InputParameters {
         paths: vec![file.into()],
+        ..Default::default()
     });
     run_system_on_world(world, open_file_system);
+    run_system_on_world(world, spawn_entities_system);
     run_system_on_world(world, read_dataset_system::<T>);
     run_system_on_world(world, close_file_system);
 }
+
+fn read_file_without_units(path: &Path, units: Option<CodeUnits>) -> Vec<units::Mass> {
+    let f = hdf5::File::create(path).unwrap();
+    f.new_dataset_builder()
+        .with_data(&[3.0, 4.0])
+        .create("Masses")
+        .unwrap();
+    drop(f);
+    read_masses(path, units)
+}
+
+fn read_masses(path: &Path, units: Option<CodeUnits>) -> Vec<units::Mass> {
+    let mut world = World::new();
+    world.insert_resource(SpawnedEntities::default());
+    world.insert_resource(InputChunks(vec![]));
+    world.insert_resource(RegisteredDatasets(vec![Mass::name()]));
+    world.insert_resource(WorldRank(0));
+    world.insert_resource(WorldSize(1));
+    world.insert_resource(InputParameters {
+        paths: vec![path.into()],
+        schema: InputSchema {
+            datasets: [("mass".into(), "Masses".into())].into_iter().collect(),
+            units,
+        },
+        ..Default::default()
+    });
+    run_system_on_world(&mut world, open_file_system);
+    run_system_on_world(&mut world, spawn_entities_system);
+    run_system_on_world(&mut world, read_dataset_system::<Mass>);
+    run_system_on_world(&mut world, close_file_system);
+    let mut masses: Vec<_> = world.query::<&Mass>().iter(&world).map(|m| **m).collect();
+    masses.sort_by(|m1, m2| m1.partial_cmp(m2).unwrap());
+    masses
+}
+
+#[test]
+fn read_file_with_declared_units() {
+    let path = std::env::temp_dir().join("read_file_with_declared_units.hdf5");
+    let units = CodeUnits {
+        mass: units::Mass::kilograms(2.0),
+        ..Default::default()
+    };
+    let masses = read_file_without_units(&path, Some(units));
+    assert_eq!(masses.len(), 2);
+    assert_is_close(masses[0], units::Mass::kilograms(6.0));
+    assert_is_close(masses[1], units::Mass::kilograms(8.0));
+}
+
+#[test]
+#[should_panic(expected = "No scale factor in dataset Masses")]
+fn panic_on_missing_units() {
+    let path = std::env::temp_dir().join("panic_on_missing_units.hdf5");
+    read_file_without_units(&path, None);
+}
+
+fn read_file_with_partial_units(path: &Path, units: Option<CodeUnits>) -> Vec<units::Mass> {
+    let f = hdf5::File::create(path).unwrap();
+    let dataset = f
+        .new_dataset_builder()
+        .with_data(&[3.0, 4.0])
+        .create("Masses")
+        .unwrap();
+    // The scale factor is given, but the mass dimension is not.
+    let write_attr = |name, value: f64| {
+        dataset
+            .new_attr::<f64>()
+            .shape(())
+            .create(name)
+            .unwrap()
+            .write_scalar(&value)
+            .unwrap();
+    };
+    write_attr(SCALE_FACTOR_IDENTIFIER, 5.0);
+    for name in [LENGTH_IDENTIFIER, TIME_IDENTIFIER, TEMPERATURE_IDENTIFIER] {
+        dataset
+            .new_attr::<i32>()
+            .shape(())
+            .create(name)
+            .unwrap()
+            .write_scalar(&0)
+            .unwrap();
+    }
+    drop(f);
+    read_masses(path, units)
+}
+
+#[test]
+fn missing_dimension_attributes_fall_back_to_schema_units() {
+    let path = std::env::temp_dir().join("missing_dimension_attributes_fall_back.hdf5");
+    let units = CodeUnits {
+        mass: units::Mass::kilograms(2.0),
+        ..Default::default()
+    };
+    let masses = read_file_with_partial_units(&path, Some(units));
+    assert_eq!(masses.len(), 2);
+    assert_is_close(masses[0], units::Mass::kilograms(30.0));
+    assert_is_close(masses[1], units::Mass::kilograms(40.0));
+}
+
+#[test]
+#[should_panic(expected = "No dimension attributes in dataset Masses")]
+fn panic_on_missing_dimension_attributes() {
+    let path = std::env::temp_dir().join("panic_on_missing_dimension_attributes.hdf5");
+    read_file_with_partial_units(&path, None);
+}
+
+#[test]
+fn read_single_file_on_multiple_ranks() {
+    let num_particles_on_rank = |rank| {
+        let mut world = World::new();
+        read_dataset_from_file_on_rank::<Mass>(
+            &mut world,
+            &tests_path().join("input/respect_scale_factor.hdf5"),
+            rank,
+            2,
+        );
+        world.query::<&Mass>().iter(&world).count()
+    };
+    assert_eq!(num_particles_on_rank(0) + num_particles_on_rank(1), 1);
+}
+
+fn write_gadget_file(path: &Path, hubble_param: Option<f64>) {
+    let f = hdf5::File::create(path).unwrap();
+    let header = f.create_group("Header").unwrap();
+    header
+        .new_attr::<u32>()
+        .shape(6)
+        .create("NumPart_ThisFile")
+        .unwrap()
+        .write_raw(&[1, 2, 0, 0, 0, 0])
+        .unwrap();
+    header
+        .new_attr::<f64>()
+        .shape(6)
+        .create("MassTable")
+        .unwrap()
+        .write_raw(&[0.0, 3.0, 0.0, 0.0, 0.0, 0.0])
+        .unwrap();
+    // Code units of 1 m, 1 kg and 1 m/s
+    let write_unit = |name, value: f64| {
+        header
+            .new_attr::<f64>()
+            .shape(())
+            .create(name)
+            .unwrap()
+            .write_scalar(&value)
+            .unwrap();
+    };
+    write_unit("UnitLength_in_cm", 1e2);
+    write_unit("UnitMass_in_g", 1e3);
+    write_unit("UnitVelocity_in_cm_per_s", 1e2);
+    if let Some(hubble_param) = hubble_param {
+        write_unit("HubbleParam", hubble_param);
+    }
+    let write_dataset = |group: &hdf5::Group, name, values: Vec<f64>, num_floats| {
+        let data = ndarray::Array2::from_shape_vec((values.len() / num_floats, num_floats), values)
+            .unwrap();
+        group
+            .new_dataset_builder()
+            .with_data(&data)
+            .create(name)
+            .unwrap();
+    };
+    let gas = f.create_group("PartType0").unwrap();
+    write_dataset(
+        &gas,
+        "Coordinates",
+        vec![1.0; NUM_DIMENSIONS],
+        NUM_DIMENSIONS,
+    );
+    write_dataset(&gas, "Masses", vec![2.0], 1);
+    write_dataset(&gas, "InternalEnergy", vec![5.0], 1);
+    // Gadget files usually store the ids as 32 bit integers.
+    let write_ids = |group: &hdf5::Group, ids: &[u32]| {
+        group
+            .new_dataset_builder()
+            .with_data(ids)
+            .create("ParticleIDs")
+            .unwrap();
+    };
+    write_ids(&gas, &[7]);
+    let dark_matter = f.create_group("PartType1").unwrap();
+    write_dataset(
+        &dark_matter,
+        "Coordinates",
+        vec![2.0; 2 * NUM_DIMENSIONS],
+        NUM_DIMENSIONS,
+    );
+    write_ids(&dark_matter, &[3, 5]);
+}
+
+fn read_gadget_file_into_world(path: &Path) -> World {
+    let mut world = World::new();
+    world.insert_resource(SpawnedEntities::default());
+    world.insert_resource(InputChunks(vec![]));
+    world.insert_resource(RegisteredDatasets(vec![Position::name(), Mass::name()]));
+    world.insert_resource(WorldRank(0));
+    world.insert_resource(WorldSize(1));
+    world.insert_resource(InputParameters {
+        paths: vec![path.to_owned()],
+        format: FileFormat::Gadget,
+        ..Default::default()
+    });
+    run_system_on_world(&mut world, open_file_system);
+    run_system_on_world(&mut world, spawn_entities_system);
+    run_system_on_world(&mut world, read_dataset_system::<Position>);
+    run_system_on_world(&mut world, read_dataset_system::<Mass>);
+    run_system_on_world(&mut world, read_dataset_system::<InternalEnergy>);
+    run_system_on_world(&mut world, read_dataset_system::<ParticleId>);
+    run_system_on_world(&mut world, close_file_system);
+    world
+}
+
+/// The positions, masses, internal energies and species of all
+/// particles, sorted by mass.
+fn gadget_particles(
+    world: &mut World,
+) -> Vec<(
+    units::VecLength,
+    units::Mass,
+    Option<units::Energy>,
+    Species,
+)> {
+    let mut particles: Vec<_> = world
+        .query::<(&Position, &Mass, Option<&InternalEnergy>, &Species)>()
+        .iter(world)
+        .map(|(pos, mass, energy, species)| {
+            (**pos, **mass, energy.map(|energy| **energy), *species)
+        })
+        .collect();
+    particles.sort_by(|(_, m1, _, _), (_, m2, _, _)| m1.partial_cmp(m2).unwrap());
+    particles
+}
+
+#[test]
+fn read_gadget_file() {
+    let path = std::env::temp_dir().join("read_gadget_file.hdf5");
+    write_gadget_file(&path, None);
+    let mut world = read_gadget_file_into_world(&path);
+    let mut ids: Vec<_> = world
+        .query::<(&ParticleId, &Species)>()
+        .iter(&world)
+        .map(|(id, species)| (*id, *species))
+        .collect();
+    ids.sort_by_key(|(id, _)| *id);
+    assert_eq!(
+        ids,
+        [
+            (ParticleId(3), Species::DarkMatter),
+            (ParticleId(5), Species::DarkMatter),
+            (ParticleId(7), Species::Gas)
+        ]
+    );
+    let particles = gadget_particles(&mut world);
+    assert_eq!(particles.len(), 3);
+    let (pos, mass, energy, species) = particles[0];
+    assert_eq!(species, Species::Gas);
+    assert_is_close(pos.x(), units::Length::meters(1.0));
+    assert_is_close(mass, units::Mass::kilograms(2.0));
+    // The internal energy is stored per unit mass.
+    assert_is_close(energy.unwrap(), units::Energy::joules(10.0));
+    for (pos, mass, energy, species) in &particles[1..] {
+        assert_eq!(*species, Species::DarkMatter);
+        assert_is_close(pos.x(), units::Length::meters(2.0));
+        // The mass is taken from the mass table.
+        assert_is_close(*mass, units::Mass::kilograms(3.0));
+        assert!(energy.is_none());
+    }
+}
+
+#[test]
+fn read_gadget_file_in_h_units() {
+    let path = std::env::temp_dir().join("read_gadget_file_in_h_units.hdf5");
+    write_gadget_file(&path, Some(0.5));
+    let mut world = read_gadget_file_into_world(&path);
+    let particles = gadget_particles(&mut world);
+    assert_eq!(particles.len(), 3);
+    // Lengths and masses are given in units of 1 / h.
+    let (pos, mass, energy, _) = particles[0];
+    assert_is_close(pos.x(), units::Length::meters(2.0));
+    assert_is_close(mass, units::Mass::kilograms(4.0));
+    // The internal energy per unit mass does not depend on h.
+    assert_is_close(energy.unwrap(), units::Energy::joules(20.0));
+    for (pos, mass, _, _) in &particles[1..] {
+        assert_is_close(pos.x(), units::Length::meters(4.0));
+        assert_is_close(*mass, units::Mass::kilograms(6.0));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod code_units;
+pub mod gadget;
 pub mod input;
 pub mod output;
+pub mod restart;
 pub mod to_dataset;
@@ -1,6 +1,8 @@
 use std::marker::PhantomData;
 
 use bevy::ecs::schedule::SystemDescriptor;
+use bevy::ecs::schedule::SystemLabelId;
+use bevy::prelude::AsSystemLabel;
 use bevy::prelude::IntoSystemDescriptor;
 use bevy::prelude::Res;
 use bevy::prelude::ResMut;
@@ -9,6 +11,7 @@ use hdf5::H5Type;
 
 use super::plugin::IntoOutputSystem;
 use super::OutputFile;
+use crate::io::gadget::FileFormat;
 use crate::named::Named;
 
 pub trait ToAttribute: Named + Resource {
@@ -27,13 +30,20 @@ impl<T: Named> Named for Attribute<T> {
 }
 
 impl<T: ToAttribute> IntoOutputSystem for Attribute<T> {
-    fn system() -> SystemDescriptor {
+    fn system(_: &FileFormat) -> SystemDescriptor {
         write_attribute::<T>.into_descriptor()
     }
+
+    fn label(_: &FileFormat) -> SystemLabelId {
+        write_attribute::<T>.as_system_label()
+    }
 }
 
 fn write_attribute<T: ToAttribute>(res: Res<T>, file: ResMut<OutputFile>) {
-    let f = file.f.as_ref().unwrap();
+    let f = match file.attribute_file() {
+        Some(f) => f,
+        None => return,
+    };
     let attr = f
         .new_attr::<T::Output>()
         .shape(())
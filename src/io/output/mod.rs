@@ -4,7 +4,9 @@ pub(super) mod plugin;
 mod timer;
 
 use std::fs;
+use std::ops::Range;
 use std::path::Path;
+use std::path::PathBuf;
 
 use bevy::prelude::info;
 use bevy::prelude::Res;
@@ -12,14 +14,22 @@ use bevy::prelude::ResMut;
 use bevy::prelude::Resource;
 use bevy::prelude::StageLabel;
 use hdf5::File;
+use hdf5::Group;
+use mpi::traits::Equivalence;
 
 pub use self::attribute::Attribute;
 pub use self::attribute::ToAttribute;
 use self::parameters::OutputParameters;
+use self::parameters::SnapshotFiles;
 pub use self::plugin::OutputPlugin;
 use self::timer::Timer;
+use crate::communication::Communicator;
+use crate::communication::Rank;
 use crate::communication::WorldRank;
+use crate::components::Species;
+use crate::components::NUM_SPECIES;
 use crate::parameter_plugin::ParameterFileContents;
+use crate::prelude::Particles;
 use crate::prelude::WorldSize;
 
 #[derive(StageLabel)]
@@ -27,11 +37,111 @@ pub enum OutputStages {
     Output,
 }
 
+/// The part of the datasets of the snapshot file that contains the
+/// particles of this rank, for each species.
+#[derive(Default)]
+pub(super) struct FileSlice {
+    pub offset: [usize; NUM_SPECIES],
+    pub total: [usize; NUM_SPECIES],
+}
+
+type PendingWrite = Box<dyn FnOnce(&File, &FileSlice) + Send + Sync>;
+
+/// A snapshot file which all ranks write to.
+struct SingleFile {
+    path: PathBuf,
+    rank: Rank,
+    /// The ranks whose particles are written by the same writer
+    /// as those of this rank. The writer is the first rank of
+    /// the group.
+    group: Range<Rank>,
+    /// The writes to the file which are deferred until it is the
+    /// turn of this rank to write.
+    pending: Vec<PendingWrite>,
+}
+
+impl SingleFile {
+    fn is_writer(&self) -> bool {
+        self.rank == self.group.start
+    }
+}
+
+/// The group of consecutive ranks whose particles are written
+/// to a single snapshot file by the same writer.
+fn writer_group(rank: Rank, world_size: usize, num_writers: usize) -> Range<Rank> {
+    let num_writers = num_writers.clamp(1, world_size);
+    let group_size = ((world_size + num_writers - 1) / num_writers) as Rank;
+    let start = rank - rank % group_size;
+    start..(start + group_size).min(world_size as Rank)
+}
+
 #[derive(Default, Resource)]
 pub(super) struct OutputFile {
     pub f: Option<File>,
+    pub(super) slice: FileSlice,
+    /// The number of particles of each species in all files
+    /// of the snapshot.
+    pub(super) num_particles_total: [usize; NUM_SPECIES],
+    single_file: Option<SingleFile>,
 }
 
+impl OutputFile {
+    /// Writes to the snapshot file of this rank. If all ranks
+    /// write to a single file, the write is deferred until all
+    /// output systems have run.
+    pub(super) fn write(&mut self, write: impl FnOnce(&File, &FileSlice) + Send + Sync + 'static) {
+        match self.single_file {
+            Some(ref mut single_file) => {
+                assert!(single_file.is_writer());
+                single_file.pending.push(Box::new(write))
+            }
+            None => write(self.f.as_ref().unwrap(), &self.slice),
+        }
+    }
+
+    /// The file to which this rank writes the attributes of the
+    /// snapshot. If all ranks write to a single file, only the main
+    /// rank writes them and None is returned on all other ranks.
+    pub(super) fn attribute_file(&self) -> Option<&File> {
+        match self.single_file {
+            Some(ref single_file) if single_file.rank != WorldRank::main() => None,
+            _ => Some(self.f.as_ref().expect("Output file is not open")),
+        }
+    }
+
+    /// Collects the particle data (per species) which this rank
+    /// writes to the snapshot. If all ranks write to a single file,
+    /// the data of every rank is sent to the writer of its group,
+    /// which concatenates it in the order of the ranks. Returns
+    /// None on all ranks which do not write.
+    pub(super) fn gather<T: Equivalence>(
+        &self,
+        data: [Vec<T>; NUM_SPECIES],
+        comm: &mut Communicator<T>,
+    ) -> Option<[Vec<T>; NUM_SPECIES]> {
+        let single_file = match self.single_file {
+            Some(ref single_file) => single_file,
+            None => return Some(data),
+        };
+        if !single_file.is_writer() {
+            for data in data.iter() {
+                comm.blocking_send_vec(single_file.group.start, data);
+            }
+            return None;
+        }
+        let mut data = data;
+        for rank in single_file.group.clone().skip(1) {
+            for data in data.iter_mut() {
+                data.extend(comm.receive_vec(rank));
+            }
+        }
+        Some(data)
+    }
+}
+
+#[derive(Clone, Equivalence)]
+pub(super) struct NumParticles([usize; NUM_SPECIES]);
+
 fn write_used_parameters_system(
     parameter_file_contents: Res<ParameterFileContents>,
     parameters: Res<OutputParameters>,
@@ -51,7 +161,7 @@ fn write_used_parameters_system(
     });
 }
 
-fn make_output_dirs_system(parameters: Res<OutputParameters>) {
+fn handle_existing_output_system(parameters: Res<OutputParameters>) {
     if parameters.output_dir.exists() {
         match parameters.handle_existing_output {
             parameters::HandleExistingOutput::Panic => panic!(
@@ -65,6 +175,9 @@ fn make_output_dirs_system(parameters: Res<OutputParameters>) {
             parameters::HandleExistingOutput::Overwrite => {}
         }
     }
+}
+
+fn make_output_dirs_system(parameters: Res<OutputParameters>) {
     fs::create_dir_all(&parameters.output_dir)
         .unwrap_or_else(|_| panic!("Failed to create output dir: {:?}", parameters.output_dir));
     fs::create_dir_all(parameters.snapshot_dir()).unwrap_or_else(|_| {
@@ -86,25 +199,136 @@ fn open_file_system(
     world_size: Res<WorldSize>,
     parameters: Res<OutputParameters>,
     output_timer: Res<Timer>,
+    particles: Particles<Option<&Species>>,
+    mut comm: Communicator<NumParticles>,
 ) {
     assert!(file.f.is_none());
-    let rank_padding = ((**world_size as f64).log10().floor() as usize) + 1;
     let snapshot_name = format!(
         "{:0snap_padding$}",
         output_timer.snapshot_num(),
         snap_padding = parameters.snapshot_padding
     );
-    let snapshot_dir = parameters.snapshot_dir().join(&snapshot_name);
-    make_snapshot_dir(&snapshot_dir);
-    let filename = &format!(
-        "{:0rank_padding$}.hdf5",
-        rank.0,
-        rank_padding = rank_padding
-    );
     info!("Writing snapshot: {}", &snapshot_name);
-    file.f = Some(File::create(snapshot_dir.join(filename)).expect("Failed to open output file"));
+    let mut num_particles = [0; NUM_SPECIES];
+    for species in particles.iter() {
+        num_particles[species.copied().unwrap_or_default().index()] += 1;
+    }
+    let nums: Vec<_> = comm
+        .all_gather(&NumParticles(num_particles))
+        .into_iter()
+        .map(|num| num.0)
+        .collect();
+    let sum = |nums: &[[usize; NUM_SPECIES]]| {
+        let mut total = [0; NUM_SPECIES];
+        for num in nums {
+            for (total, num) in total.iter_mut().zip(num) {
+                *total += num;
+            }
+        }
+        total
+    };
+    file.num_particles_total = sum(&nums);
+    match parameters.snapshot_files {
+        SnapshotFiles::PerRank => {
+            let snapshot_dir = parameters.snapshot_dir().join(&snapshot_name);
+            make_snapshot_dir(&snapshot_dir);
+            let filename = rank_file_name(**rank, **world_size);
+            file.f = Some(
+                File::create(snapshot_dir.join(filename)).expect("Failed to open output file"),
+            );
+            file.slice = FileSlice {
+                offset: [0; NUM_SPECIES],
+                total: num_particles,
+            };
+        }
+        SnapshotFiles::Single => {
+            let group = writer_group(**rank, **world_size, parameters.num_writers);
+            // The writer writes the particles of its entire group.
+            file.slice = FileSlice {
+                offset: sum(&nums[..group.start as usize]),
+                total: file.num_particles_total,
+            };
+            let path = parameters
+                .snapshot_dir()
+                .join(format!("{snapshot_name}.hdf5"));
+            // Only the main rank writes the attributes, the datasets
+            // are written once all ranks are done.
+            if rank.is_main() {
+                file.f = Some(File::create(&path).expect("Failed to open output file"));
+            }
+            file.single_file = Some(SingleFile {
+                path,
+                rank: **rank,
+                group,
+                pending: vec![],
+            });
+        }
+    }
+}
+
+pub(super) fn get_or_create_group(f: &File, name: &str) -> Group {
+    f.group(name)
+        .unwrap_or_else(|_| f.create_group(name).expect("Failed to create group"))
 }
 
-fn close_file_system(mut file: ResMut<OutputFile>) {
+/// The name of the file that the given rank writes to within a
+/// snapshot (or restart) directory, zero-padded according to the
+/// number of ranks.
+pub(super) fn rank_file_name(rank: Rank, world_size: usize) -> String {
+    let rank_padding = ((world_size as f64).log10().floor() as usize) + 1;
+    format!("{rank:0rank_padding$}.hdf5")
+}
+
+fn close_file_system(
+    mut file: ResMut<OutputFile>,
+    rank: Res<WorldRank>,
+    world_size: Res<WorldSize>,
+    parameters: Res<OutputParameters>,
+    mut comm: Communicator<NumParticles>,
+) {
     file.f = None;
+    if let Some(single_file) = file.single_file.take() {
+        // The writers take turns writing to the file, starting with
+        // the main rank, which creates the datasets.
+        if single_file.is_writer() {
+            if !rank.is_main() {
+                let previous_group = writer_group(**rank - 1, **world_size, parameters.num_writers);
+                comm.receive_vec(previous_group.start);
+            }
+            let f = File::open_rw(&single_file.path).expect("Failed to open output file");
+            for write in single_file.pending.into_iter() {
+                write(&f, &file.slice);
+            }
+            drop(f);
+            if (single_file.group.end as usize) < **world_size {
+                comm.blocking_send_vec(single_file.group.end, &[]);
+            }
+        }
+        // Make sure the file is complete before continuing.
+        comm.all_gather(&NumParticles([0; NUM_SPECIES]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::writer_group;
+
+    #[test]
+    fn writer_groups_cover_all_ranks() {
+        for world_size in 1..10 {
+            for num_writers in 0..12 {
+                let groups: Vec<_> = (0..world_size as i32)
+                    .map(|rank| writer_group(rank, world_size, num_writers))
+                    .collect();
+                for (rank, group) in groups.iter().enumerate() {
+                    assert!(group.contains(&(rank as i32)));
+                    assert_eq!(group, &groups[group.start as usize]);
+                }
+                let mut writers: Vec<_> = groups.iter().map(|group| group.start).collect();
+                writers.dedup();
+                assert!(writers.len() <= num_writers.max(1));
+                assert_eq!(writers[0], 0);
+            }
+        }
+    }
 }
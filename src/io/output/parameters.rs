@@ -2,6 +2,9 @@ use std::path::PathBuf;
 
 use derive_custom::raxiom_parameters;
 
+use crate::components::ParticleId;
+use crate::io::code_units::CodeUnits;
+use crate::io::gadget::FileFormat;
 use crate::named::Named;
 use crate::simulation::Simulation;
 use crate::units::Time;
@@ -22,6 +25,23 @@ pub enum HandleExistingOutput {
     Delete,
 }
 
+/// How the data of the individual ranks is distributed among
+/// the files of a snapshot.
+#[derive(Default)]
+#[raxiom_parameters]
+pub enum SnapshotFiles {
+    /// Every rank writes its own file within the directory of
+    /// the snapshot.
+    #[default]
+    PerRank,
+    /// All ranks write to a single file per snapshot. The particles
+    /// of all ranks are gathered on a small number of writer ranks
+    /// (see `num_writers`), which take turns to write them into the
+    /// respective part of each dataset. The number of files is then
+    /// independent of the number of ranks.
+    Single,
+}
+
 /// Parameters for the output of the simulation.
 /// Only required if write_output
 /// is set in the [SimulationBuilder](crate::prelude::SimulationBuilder)
@@ -58,12 +78,34 @@ pub struct OutputParameters {
     /// What to do when the output folder already exists.
     #[serde(default)]
     pub handle_existing_output: HandleExistingOutput,
+    /// Whether every rank writes its own file or all ranks write
+    /// to a single file per snapshot.
+    #[serde(default)]
+    pub snapshot_files: SnapshotFiles,
+    /// The number of ranks which write to the file if all ranks
+    /// write to a single file per snapshot. Every writer collects
+    /// the particles of an equally sized group of consecutive
+    /// ranks. Fewer writers need more memory, since each of them
+    /// holds the particles of more ranks, while more writers have
+    /// to take more turns.
+    #[serde(default = "default_num_writers")]
+    pub num_writers: usize,
+    /// The layout of the snapshot files.
+    #[serde(default)]
+    pub format: FileFormat,
+    /// The code units of snapshots in the Gadget layout.
+    #[serde(default)]
+    pub gadget_units: CodeUnits,
 }
 
 fn default_snapshot_padding() -> usize {
     3
 }
 
+fn default_num_writers() -> usize {
+    1
+}
+
 fn default_output_dir() -> PathBuf {
     "output".into()
 }
@@ -83,11 +125,16 @@ fn default_used_parameters_filename() -> String {
 }
 
 impl OutputParameters {
+    /// Whether the field should be written to snapshots. The
+    /// particle ids are always written, so that particles can be
+    /// identified across snapshots.
     pub fn is_desired_field<T: Named>(sim: &Simulation) -> bool {
-        sim.unwrap_resource::<Self>()
-            .fields
-            .iter()
-            .any(|field| field == T::name())
+        T::name() == ParticleId::name()
+            || sim
+                .unwrap_resource::<Self>()
+                .fields
+                .iter()
+                .any(|field| field == T::name())
     }
 
     pub fn snapshot_dir(&self) -> PathBuf {
@@ -1,27 +1,41 @@
 use std::marker::PhantomData;
 
 use bevy::ecs::schedule::SystemDescriptor;
+use bevy::ecs::schedule::SystemLabelId;
 use bevy::prelude::*;
 
 use super::close_file_system;
+use super::handle_existing_output_system;
 use super::make_output_dirs_system;
 use super::open_file_system;
 use super::parameters::OutputParameters;
 use super::timer::Timer;
 use super::write_used_parameters_system;
+use super::NumParticles;
 use super::OutputFile;
 use super::OutputStages;
+use crate::communication::CommunicationPlugin;
+use crate::io::gadget;
+use crate::io::gadget::FileFormat;
+use crate::io::restart::RestartAttribute;
+use crate::io::restart::RestartPlugin;
 use crate::named::Named;
 use crate::prelude::Simulation;
 use crate::simulation::RaxiomPlugin;
 
 pub(crate) trait IntoOutputSystem {
-    fn system() -> SystemDescriptor;
+    fn system(format: &FileFormat) -> SystemDescriptor;
+    fn label(format: &FileFormat) -> SystemLabelId;
+    /// Adds anything else that the output system requires.
+    fn build(_sim: &mut Simulation) {}
 }
 
 #[derive(SystemLabel)]
 struct OutputSystemLabel;
 
+#[derive(Named)]
+struct OutputSystemOrder;
+
 #[derive(Named)]
 pub struct OutputPlugin<T> {
     _marker: PhantomData<T>,
@@ -48,6 +62,11 @@ where
     }
 
     fn build_once_on_main_rank(&self, sim: &mut Simulation) {
+        // When resuming from restart files, the output directory
+        // is expected to already exist.
+        if !sim.restart {
+            sim.add_startup_system(handle_existing_output_system.before(make_output_dirs_system));
+        }
         sim.add_startup_system(make_output_dirs_system)
             .add_startup_system(write_used_parameters_system.after(make_output_dirs_system));
     }
@@ -55,7 +74,9 @@ where
     fn build_once_everywhere(&self, sim: &mut Simulation) {
         sim.add_parameter_type::<OutputParameters>()
             .insert_resource(OutputFile::default())
+            .add_plugin(CommunicationPlugin::<NumParticles>::default())
             .add_startup_system(Timer::initialize_system)
+            .add_plugin(RestartPlugin::<RestartAttribute<Timer>>::default())
             .add_system_to_stage(
                 OutputStages::Output,
                 open_file_system.with_run_criteria(Timer::run_criterion),
@@ -72,18 +93,35 @@ where
                     .after(close_file_system)
                     .with_run_criteria(Timer::run_criterion),
             );
+        if let FileFormat::Gadget = sim.unwrap_resource::<OutputParameters>().format {
+            sim.add_system_to_stage(
+                OutputStages::Output,
+                gadget::write_header_system
+                    .after(open_file_system)
+                    .before(close_file_system)
+                    .with_run_criteria(Timer::run_criterion)
+                    .label(OutputSystemLabel)
+                    .ambiguous_with(OutputSystemLabel),
+            );
+        }
     }
 
     fn build_everywhere(&self, sim: &mut Simulation) {
         if OutputParameters::is_desired_field::<T>(sim) {
-            sim.add_system_to_stage(
+            let format = sim.unwrap_resource::<OutputParameters>().format.clone();
+            T::build(sim);
+            // If all ranks write to a single file, the output systems
+            // communicate, so they need to run in the same order on
+            // every rank.
+            sim.add_well_ordered_system_to_stage::<_, OutputSystemOrder>(
                 OutputStages::Output,
-                T::system()
+                T::system(&format)
                     .after(open_file_system)
                     .before(close_file_system)
                     .with_run_criteria(Timer::run_criterion)
                     .label(OutputSystemLabel)
                     .ambiguous_with(OutputSystemLabel),
+                T::label(&format),
             );
         }
     }
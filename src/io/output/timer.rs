@@ -4,13 +4,18 @@ use bevy::prelude::EventReader;
 use bevy::prelude::Res;
 use bevy::prelude::ResMut;
 use bevy::prelude::Resource;
+use hdf5::H5Type;
 
 use super::parameters::OutputParameters;
+use crate::io::restart::ToRestartAttribute;
+use crate::named::Named;
 use crate::simulation_plugin::StopSimulationEvent;
 use crate::simulation_plugin::Time;
 use crate::units;
 
-#[derive(Resource)]
+#[derive(Clone, H5Type, Named, Resource)]
+#[name = "output_timer"]
+#[repr(C)]
 pub(super) struct Timer {
     next_output_time: units::Time,
     snapshot_num: usize,
@@ -26,6 +31,9 @@ impl Timer {
         });
     }
 
+    /// Snapshots are written on schedule and once more when the
+    /// simulation stops, including early stops (see
+    /// [Interrupted](crate::simulation_plugin::Interrupted)).
     pub fn run_criterion(
         time: Res<Time>,
         timer: Res<Self>,
@@ -39,12 +47,34 @@ impl Timer {
         }
     }
 
-    pub fn update_system(mut output_timer: ResMut<Self>, parameters: Res<OutputParameters>) {
+    /// A final snapshot which is not on schedule does not move the
+    /// time of the next snapshot, since the timer is part of the
+    /// restart files and a resumed simulation should write its
+    /// scheduled snapshots at the same times as an uninterrupted one.
+    pub fn update_system(
+        time: Res<Time>,
+        mut output_timer: ResMut<Self>,
+        parameters: Res<OutputParameters>,
+    ) {
         output_timer.snapshot_num += 1;
-        output_timer.next_output_time += parameters.time_between_snapshots;
+        if time.0 >= output_timer.next_output_time {
+            output_timer.next_output_time += parameters.time_between_snapshots;
+        }
     }
 
     pub fn snapshot_num(&self) -> usize {
         self.snapshot_num
     }
 }
+
+impl ToRestartAttribute for Timer {
+    type Output = Self;
+
+    fn to_restart_value(&self) -> Self {
+        self.clone()
+    }
+
+    fn from_restart_value(value: Self) -> Self {
+        value
+    }
+}
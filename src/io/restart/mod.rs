@@ -0,0 +1,321 @@
+#[cfg(test)]
+mod tests;
+
+mod parameters;
+mod plugin;
+
+use std::fs;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bevy::ecs::schedule::ShouldRun;
+use bevy::ecs::schedule::SystemDescriptor;
+use bevy::prelude::*;
+use hdf5::File;
+use hdf5::H5Type;
+
+pub use self::parameters::RestartParameters;
+pub use self::plugin::RestartPlugin;
+use super::output::parameters::OutputParameters;
+use super::output::rank_file_name;
+use crate::communication::WorldRank;
+use crate::communication::WorldSize;
+use crate::named::Named;
+use crate::prelude::LocalParticle;
+use crate::prelude::Particles;
+use crate::simulation_plugin::Interrupted;
+use crate::simulation_plugin::StopSimulationEvent;
+use crate::simulation_plugin::Time;
+use crate::units;
+
+const NUM_RANKS_IDENTIFIER: &str = "num_ranks";
+const NUM_PARTICLES_IDENTIFIER: &str = "num_particles";
+const INDICES_SUFFIX: &str = "_indices";
+
+/// A resource which is part of the state of the simulation and
+/// needs to be written to restart files in order to resume the
+/// simulation.
+pub trait ToRestartAttribute: Named + Resource {
+    type Output: H5Type;
+    fn to_restart_value(&self) -> Self::Output;
+    fn from_restart_value(value: Self::Output) -> Self;
+}
+
+pub struct RestartAttribute<T> {
+    _marker: PhantomData<T>,
+}
+
+impl<T: Named> Named for RestartAttribute<T> {
+    fn name() -> &'static str {
+        T::name()
+    }
+}
+
+pub(crate) trait IntoRestartSystems {
+    fn write_system() -> SystemDescriptor;
+    fn read_system() -> SystemDescriptor;
+}
+
+impl<T: Clone + Component + H5Type + Named> IntoRestartSystems for T {
+    fn write_system() -> SystemDescriptor {
+        write_dataset_system::<T>.into_descriptor()
+    }
+
+    fn read_system() -> SystemDescriptor {
+        read_dataset_system::<T>.into_descriptor()
+    }
+}
+
+impl<T: ToRestartAttribute> IntoRestartSystems for RestartAttribute<T> {
+    fn write_system() -> SystemDescriptor {
+        write_attribute_system::<T>.into_descriptor()
+    }
+
+    fn read_system() -> SystemDescriptor {
+        read_attribute_system::<T>.into_descriptor()
+    }
+}
+
+#[derive(Default, Resource)]
+struct RestartFile {
+    f: Option<File>,
+}
+
+/// The entities of the particles in the restart file, in the
+/// order in which they appear in the datasets.
+#[derive(Default, Deref, DerefMut, Resource)]
+struct RestartEntities(Vec<Entity>);
+
+#[derive(Resource)]
+struct RestartTimer {
+    next_restart_time: Option<units::Time>,
+}
+
+impl RestartTimer {
+    fn initialize_system(
+        mut commands: Commands,
+        parameters: Res<RestartParameters>,
+        time: Res<Time>,
+    ) {
+        commands.insert_resource(RestartTimer {
+            next_restart_time: parameters.time_between_restarts.map(|dt| **time + dt),
+        });
+    }
+
+    /// Restart files are written periodically and once more
+    /// when the simulation stops, so that it can be continued.
+    /// If the simulation is stopped early, they are written even
+    /// if no periodic restart files were requested, since the
+    /// simulation is then expected to be resumed.
+    fn run_criterion(
+        time: Res<Time>,
+        timer: Res<Self>,
+        events: EventReader<StopSimulationEvent>,
+        interrupted: Res<Interrupted>,
+    ) -> ShouldRun {
+        let simulation_finished = !events.is_empty();
+        if simulation_finished && interrupted.0 {
+            return ShouldRun::Yes;
+        }
+        match timer.next_restart_time {
+            Some(next_restart_time) if simulation_finished || **time >= next_restart_time => {
+                ShouldRun::Yes
+            }
+            _ => ShouldRun::No,
+        }
+    }
+
+    fn update_system(mut timer: ResMut<Self>, parameters: Res<RestartParameters>) {
+        if let Some(ref mut next_restart_time) = timer.next_restart_time {
+            *next_restart_time += parameters.time_between_restarts.unwrap();
+        }
+    }
+}
+
+fn restart_file_path(
+    rank: &WorldRank,
+    world_size: &WorldSize,
+    output_parameters: &OutputParameters,
+    parameters: &RestartParameters,
+) -> PathBuf {
+    parameters
+        .restart_dir(output_parameters)
+        .join(rank_file_name(**rank, **world_size))
+}
+
+/// Restart files are first written to a temporary file, which
+/// replaces the previous restart file once it is complete, so that
+/// an interruption while writing does not leave us without a
+/// valid restart file.
+fn temporary_path(path: &Path) -> PathBuf {
+    path.with_extension("hdf5.tmp")
+}
+
+fn open_file_for_writing_system(
+    mut file: ResMut<RestartFile>,
+    rank: Res<WorldRank>,
+    world_size: Res<WorldSize>,
+    output_parameters: Res<OutputParameters>,
+    parameters: Res<RestartParameters>,
+    particles: Particles<Entity>,
+    mut entities: ResMut<RestartEntities>,
+) {
+    assert!(file.f.is_none());
+    let restart_dir = parameters.restart_dir(&output_parameters);
+    fs::create_dir_all(&restart_dir)
+        .unwrap_or_else(|_| panic!("Failed to create restart dir: {restart_dir:?}"));
+    let path = temporary_path(&restart_file_path(
+        &rank,
+        &world_size,
+        &output_parameters,
+        &parameters,
+    ));
+    info!("Writing restart files");
+    let f = File::create(path).expect("Failed to open restart file");
+    write_scalar(&f, NUM_RANKS_IDENTIFIER, **world_size);
+    entities.0 = particles.iter().collect();
+    write_scalar(&f, NUM_PARTICLES_IDENTIFIER, entities.len());
+    file.f = Some(f);
+}
+
+fn close_file_for_writing_system(
+    mut file: ResMut<RestartFile>,
+    rank: Res<WorldRank>,
+    world_size: Res<WorldSize>,
+    output_parameters: Res<OutputParameters>,
+    parameters: Res<RestartParameters>,
+) {
+    file.f = None;
+    let path = restart_file_path(&rank, &world_size, &output_parameters, &parameters);
+    fs::rename(temporary_path(&path), &path)
+        .unwrap_or_else(|e| panic!("Failed to move restart file to {path:?}: {e}"));
+}
+
+fn open_file_for_reading_system(
+    mut file: ResMut<RestartFile>,
+    rank: Res<WorldRank>,
+    world_size: Res<WorldSize>,
+    output_parameters: Res<OutputParameters>,
+    parameters: Res<RestartParameters>,
+) {
+    assert!(file.f.is_none());
+    let path = restart_file_path(&rank, &world_size, &output_parameters, &parameters);
+    info!("Reading restart file: {}", path.to_str().unwrap());
+    let f = File::open(&path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to open restart file: {}: {e}",
+            path.to_str().unwrap()
+        )
+    });
+    let num_ranks: usize = read_scalar(&f, NUM_RANKS_IDENTIFIER);
+    assert_eq!(
+        num_ranks, **world_size,
+        "Restart files were written by a simulation running on {num_ranks} ranks, but the simulation is running on {} ranks.",
+        **world_size,
+    );
+    file.f = Some(f);
+}
+
+fn close_file_for_reading_system(mut file: ResMut<RestartFile>) {
+    file.f = None;
+}
+
+fn spawn_entities_system(
+    mut commands: Commands,
+    mut spawned_entities: ResMut<RestartEntities>,
+    file: Res<RestartFile>,
+) {
+    let num_particles: usize = read_scalar(file.f.as_ref().unwrap(), NUM_PARTICLES_IDENTIFIER);
+    assert_eq!(spawned_entities.len(), 0);
+    spawned_entities.0 = (0..num_particles)
+        .map(|_| commands.spawn((LocalParticle,)).id())
+        .collect();
+}
+
+fn write_dataset_system<T: Clone + Component + H5Type + Named>(
+    query: Particles<&T>,
+    entities: Res<RestartEntities>,
+    file: ResMut<RestartFile>,
+) {
+    let f = file.f.as_ref().unwrap();
+    let (indices, data): (Vec<u64>, Vec<T>) = entities
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entity)| {
+            query
+                .get(*entity)
+                .ok()
+                .map(|item| (index as u64, item.clone()))
+        })
+        .unzip();
+    f.new_dataset_builder()
+        .with_data(&data)
+        .create(T::name())
+        .expect("Failed to write dataset to restart file");
+    // Not every particle carries every component (for example, only
+    // gas particles have hydrodynamical quantities), so the datasets
+    // can differ in length. We always store the index of the particle
+    // (in the order of the RestartEntities) that every entry belongs
+    // to, so that the datasets never have to rely on being ordered
+    // consistently.
+    f.new_dataset_builder()
+        .with_data(&indices)
+        .create(format!("{}{INDICES_SUFFIX}", T::name()).as_str())
+        .expect("Failed to write dataset to restart file");
+}
+
+fn read_dataset_system<T: Clone + Component + H5Type + Named>(
+    mut commands: Commands,
+    file: ResMut<RestartFile>,
+    spawned_entities: Res<RestartEntities>,
+) {
+    let name = T::name();
+    let f = file.f.as_ref().unwrap();
+    let data = f
+        .dataset(name)
+        .unwrap_or_else(|e| panic!("Failed to open dataset in restart file: {name}, {e:?}"))
+        .read_1d::<T>()
+        .unwrap_or_else(|e| panic!("Failed to read dataset in restart file: {name}, {e:?}"));
+    let indices: Vec<usize> = f
+        .dataset(&format!("{name}{INDICES_SUFFIX}"))
+        .unwrap_or_else(|e| {
+            panic!("Failed to open indices of dataset in restart file: {name}, {e:?}")
+        })
+        .read_1d::<u64>()
+        .unwrap_or_else(|e| {
+            panic!("Failed to read indices of dataset in restart file: {name}, {e:?}")
+        })
+        .into_iter()
+        .map(|index| index as usize)
+        .collect();
+    assert_eq!(
+        data.len(),
+        indices.len(),
+        "Mismatch between length of dataset {name} and its indices in restart file."
+    );
+    for (item, index) in data.into_iter().zip(indices) {
+        commands.entity(spawned_entities[index]).insert(item);
+    }
+}
+
+fn write_attribute_system<T: ToRestartAttribute>(res: Res<T>, file: ResMut<RestartFile>) {
+    write_scalar(file.f.as_ref().unwrap(), T::name(), res.to_restart_value());
+}
+
+fn read_attribute_system<T: ToRestartAttribute>(mut commands: Commands, file: ResMut<RestartFile>) {
+    let value = read_scalar(file.f.as_ref().unwrap(), T::name());
+    commands.insert_resource(T::from_restart_value(value));
+}
+
+fn write_scalar<T: H5Type>(f: &File, name: &str, value: T) {
+    let attr = f.new_attr::<T>().shape(()).create(name).unwrap();
+    attr.write_scalar(&value).unwrap();
+}
+
+fn read_scalar<T: H5Type>(f: &File, name: &str) -> T {
+    f.attr(name)
+        .unwrap_or_else(|e| panic!("Failed to open attribute in restart file: {name}, {e:?}"))
+        .read_scalar()
+        .unwrap_or_else(|e| panic!("Failed to read attribute in restart file: {name}, {e:?}"))
+}
@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+
+use derive_custom::raxiom_parameters;
+
+use crate::parameters::OutputParameters;
+use crate::units::Time;
+
+/// Parameters for the restart files, from which a simulation
+/// can be resumed by passing `--restart` on the command line.
+#[raxiom_parameters("restart")]
+pub struct RestartParameters {
+    /// The time between two subsequent restart files. If None, restart
+    /// files are only written when the simulation is stopped early
+    /// (see [SimulationParameters](crate::parameters::SimulationParameters)).
+    /// If set to zero, restart files will be written at every timestep.
+    #[serde(default)]
+    pub time_between_restarts: Option<Time>,
+    /// The name of the sub-directory of the output directory
+    /// to which the restart files are written and from which
+    /// they are read.
+    #[serde(default = "default_restart_dir")]
+    pub restart_dir: PathBuf,
+}
+
+fn default_restart_dir() -> PathBuf {
+    "restart".into()
+}
+
+impl RestartParameters {
+    pub fn restart_dir(&self, output_parameters: &OutputParameters) -> PathBuf {
+        output_parameters.output_dir.join(&self.restart_dir)
+    }
+}
@@ -0,0 +1,115 @@
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+
+use super::close_file_for_reading_system;
+use super::close_file_for_writing_system;
+use super::open_file_for_reading_system;
+use super::open_file_for_writing_system;
+use super::parameters::RestartParameters;
+use super::spawn_entities_system;
+use super::IntoRestartSystems;
+use super::RestartEntities;
+use super::RestartFile;
+use super::RestartTimer;
+use crate::named::Named;
+use crate::parameters::OutputParameters;
+use crate::prelude::Simulation;
+use crate::simulation::RaxiomPlugin;
+use crate::simulation_plugin::SimulationStartupStages;
+
+#[derive(SystemLabel)]
+struct RestartSystemLabel;
+
+/// Writes the component (or, for a [`RestartAttribute`](super::RestartAttribute),
+/// the resource) T to the restart files and reads it back in when
+/// the simulation is resumed from them. In contrast to snapshots,
+/// restart files store the data exactly as it is represented in
+/// memory, so that the resumed simulation continues bit-for-bit
+/// identically. They can only be read by a simulation running on
+/// the same number of ranks.
+#[derive(Named)]
+pub struct RestartPlugin<T> {
+    _marker: PhantomData<T>,
+}
+
+impl<T> Default for RestartPlugin<T> {
+    fn default() -> Self {
+        Self {
+            _marker: PhantomData::default(),
+        }
+    }
+}
+
+impl<T> RaxiomPlugin for RestartPlugin<T>
+where
+    T: IntoRestartSystems + Named,
+{
+    fn allow_adding_twice(&self) -> bool {
+        true
+    }
+
+    fn should_build(&self, sim: &Simulation) -> bool {
+        sim.write_output || sim.restart
+    }
+
+    fn build_once_everywhere(&self, sim: &mut Simulation) {
+        sim.add_parameter_type::<OutputParameters>()
+            .add_parameter_type::<RestartParameters>()
+            .insert_resource(RestartFile::default())
+            .insert_resource(RestartEntities::default());
+        if sim.write_output {
+            sim.add_startup_system_to_stage(
+                StartupStage::PostStartup,
+                RestartTimer::initialize_system,
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                open_file_for_writing_system.with_run_criteria(RestartTimer::run_criterion),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                close_file_for_writing_system
+                    .after(open_file_for_writing_system)
+                    .with_run_criteria(RestartTimer::run_criterion),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                RestartTimer::update_system
+                    .after(close_file_for_writing_system)
+                    .with_run_criteria(RestartTimer::run_criterion),
+            );
+        }
+        if sim.restart {
+            sim.add_startup_system(open_file_for_reading_system)
+                .add_startup_system(spawn_entities_system.after(open_file_for_reading_system))
+                .add_startup_system_to_stage(
+                    SimulationStartupStages::InsertComponents,
+                    close_file_for_reading_system,
+                );
+        }
+    }
+
+    fn build_everywhere(&self, sim: &mut Simulation) {
+        if sim.write_output {
+            sim.add_system_to_stage(
+                CoreStage::Last,
+                T::write_system()
+                    .after(open_file_for_writing_system)
+                    .before(close_file_for_writing_system)
+                    .with_run_criteria(RestartTimer::run_criterion)
+                    .label(RestartSystemLabel)
+                    .ambiguous_with(RestartSystemLabel),
+            );
+        }
+        if sim.restart {
+            sim.add_startup_system_to_stage(
+                SimulationStartupStages::InsertComponents,
+                T::read_system()
+                    .before(close_file_for_reading_system)
+                    .label(RestartSystemLabel)
+                    .ambiguous_with(RestartSystemLabel),
+            );
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use bevy::prelude::World;
+
+use super::close_file_for_reading_system;
+use super::close_file_for_writing_system;
+use super::open_file_for_reading_system;
+use super::open_file_for_writing_system;
+use super::read_attribute_system;
+use super::read_dataset_system;
+use super::spawn_entities_system;
+use super::write_attribute_system;
+use super::write_dataset_system;
+use super::RestartEntities;
+use super::RestartFile;
+use super::RestartParameters;
+use crate::components::Mass;
+use crate::components::Work;
+use crate::parameters::OutputParameters;
+use crate::prelude::LocalParticle;
+use crate::prelude::WorldRank;
+use crate::prelude::WorldSize;
+use crate::simulation_plugin::Time;
+use crate::test_utils::run_system_on_world;
+use crate::units;
+
+fn insert_resources(world: &mut World, name: &str) {
+    let output_dir = std::env::temp_dir().join(name);
+    let output_parameters: OutputParameters =
+        serde_yaml::from_str(&format!("output_dir: {}", output_dir.to_str().unwrap())).unwrap();
+    let parameters: RestartParameters = serde_yaml::from_str("restart_dir: restart").unwrap();
+    world.insert_resource(output_parameters);
+    world.insert_resource(parameters);
+    world.insert_resource(RestartFile::default());
+    world.insert_resource(RestartEntities::default());
+    world.insert_resource(WorldRank(0));
+    world.insert_resource(WorldSize(1));
+}
+
+#[test]
+fn restart_files_preserve_state_exactly() {
+    // Values which cannot be represented exactly in decimal,
+    // to make sure nothing is lost on the way.
+    let masses = [1.0 / 3.0, 2.0f64.sqrt(), 1e-300];
+    let time = units::Time::seconds(0.1 + 0.2);
+    let mut world = World::new();
+    insert_resources(&mut world, "raxiom_restart_files_preserve_state_exactly");
+    for mass in masses.iter() {
+        world.spawn((Mass(units::Mass::kilograms(*mass)), LocalParticle));
+    }
+    world.insert_resource(Time(time));
+    run_system_on_world(&mut world, open_file_for_writing_system);
+    run_system_on_world(&mut world, write_dataset_system::<Mass>);
+    run_system_on_world(&mut world, write_attribute_system::<Time>);
+    run_system_on_world(&mut world, close_file_for_writing_system);
+
+    let mut world = World::new();
+    insert_resources(&mut world, "raxiom_restart_files_preserve_state_exactly");
+    run_system_on_world(&mut world, open_file_for_reading_system);
+    run_system_on_world(&mut world, spawn_entities_system);
+    run_system_on_world(&mut world, read_dataset_system::<Mass>);
+    run_system_on_world(&mut world, read_attribute_system::<Time>);
+    run_system_on_world(&mut world, close_file_for_reading_system);
+    assert_eq!(**world.resource::<Time>(), time);
+    let mut query = world.query::<&Mass>();
+    let read_masses: Vec<_> = query.iter(&world).map(|mass| mass.in_kilograms()).collect();
+    assert_eq!(read_masses, masses);
+}
+
+#[test]
+fn restart_files_preserve_components_of_some_particles() {
+    let mut world = World::new();
+    insert_resources(
+        &mut world,
+        "raxiom_restart_files_preserve_components_of_some_particles",
+    );
+    world.spawn((Mass(units::Mass::kilograms(1.0)), LocalParticle));
+    world.spawn((Mass(units::Mass::kilograms(2.0)), Work(2.0), LocalParticle));
+    world.spawn((Mass(units::Mass::kilograms(3.0)), LocalParticle));
+    run_system_on_world(&mut world, open_file_for_writing_system);
+    run_system_on_world(&mut world, write_dataset_system::<Mass>);
+    run_system_on_world(&mut world, write_dataset_system::<Work>);
+    run_system_on_world(&mut world, close_file_for_writing_system);
+
+    let mut world = World::new();
+    insert_resources(
+        &mut world,
+        "raxiom_restart_files_preserve_components_of_some_particles",
+    );
+    run_system_on_world(&mut world, open_file_for_reading_system);
+    run_system_on_world(&mut world, spawn_entities_system);
+    run_system_on_world(&mut world, read_dataset_system::<Mass>);
+    run_system_on_world(&mut world, read_dataset_system::<Work>);
+    run_system_on_world(&mut world, close_file_for_reading_system);
+    let mut query = world.query::<(&Mass, Option<&Work>)>();
+    assert_eq!(query.iter(&world).count(), 3);
+    for (mass, work) in query.iter(&world) {
+        assert_eq!(
+            work.map(|work| **work),
+            (mass.in_kilograms() == 2.0).then_some(2.0)
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "Restart files were written by a simulation running on 2 ranks")]
+fn panic_on_rank_number_mismatch() {
+    let mut world = World::new();
+    insert_resources(&mut world, "raxiom_panic_on_rank_number_mismatch");
+    world.insert_resource(WorldSize(2));
+    run_system_on_world(&mut world, open_file_for_writing_system);
+    run_system_on_world(&mut world, close_file_for_writing_system);
+    world.insert_resource(WorldSize(1));
+    run_system_on_world(&mut world, open_file_for_reading_system);
+}
+
+#[cfg(not(feature = "mpi"))]
+mod resume {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use bevy::prelude::Commands;
+    use bevy::prelude::Res;
+    use bevy::MinimalPlugins;
+
+    use crate::communication::local_sim_building::build_local_communication_sim_with_custom_logic;
+    use crate::components;
+    use crate::components::Acceleration;
+    use crate::components::ParticleId;
+    use crate::components::Position;
+    use crate::components::Timestep;
+    use crate::components::Velocity;
+    use crate::domain::DomainDecompositionPlugin;
+    use crate::gravity::plugin::GravityPlugin;
+    use crate::gravity::tests::get_particles;
+    use crate::gravity::GravityParameters;
+    use crate::gravity::LeafData;
+    use crate::gravity::MultipoleOrder;
+    use crate::gravity::OpeningCriterion;
+    use crate::gravity::Periodicity;
+    use crate::gravity::Softening;
+    use crate::parameters::SimulationBox;
+    use crate::parameters::SimulationParameters;
+    use crate::parameters::TimestepParameters;
+    use crate::prelude::Extent;
+    use crate::prelude::LocalParticle;
+    use crate::prelude::Particles;
+    use crate::simulation::Simulation;
+    use crate::simulation_plugin::SimulationPlugin;
+    use crate::simulation_plugin::Time;
+    use crate::stages::SimulationStagesPlugin;
+    use crate::test_utils::run_system_on_sim;
+    use crate::timestep::TimestepState;
+    use crate::units;
+    use crate::units::Dimensionless;
+    use crate::units::Length;
+    use crate::units::VecAcceleration;
+    use crate::units::VecLength;
+    use crate::units::VecVelocity;
+
+    const NUM_PARTICLES_ONE_DIMENSION: i32 = 5;
+    const TIME_RESTART: f64 = 3.0;
+    const FINAL_TIME: f64 = 8.0;
+
+    /// Everything that determines the future evolution of
+    /// the simulation.
+    #[derive(Debug, PartialEq)]
+    struct State {
+        particles: Vec<(
+            u64,
+            VecLength,
+            VecVelocity,
+            VecAcceleration,
+            units::Mass,
+            units::Time,
+        )>,
+        time: units::Time,
+        timestep_state: TimestepState,
+    }
+
+    static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+    fn get_particles_this_test() -> Vec<LeafData> {
+        get_particles(NUM_PARTICLES_ONE_DIMENSION, NUM_PARTICLES_ONE_DIMENSION)
+    }
+
+    fn spawn_particles_system(mut commands: Commands) {
+        commands.spawn_batch(get_particles_this_test().into_iter().map(
+            |LeafData { pos, mass, .. }| {
+                (
+                    Position(pos),
+                    components::Mass(mass),
+                    Velocity(VecVelocity::zero()),
+                    LocalParticle,
+                )
+            },
+        ))
+    }
+
+    fn store_state_system(
+        particles: Particles<(
+            &ParticleId,
+            &Position,
+            &Velocity,
+            &Acceleration,
+            &components::Mass,
+            &Timestep,
+        )>,
+        time: Res<Time>,
+        timestep_state: Res<TimestepState>,
+    ) {
+        let mut particles: Vec<_> = particles
+            .iter()
+            .map(|(id, pos, vel, acc, mass, timestep)| {
+                (**id, **pos, **vel, **acc, **mass, **timestep)
+            })
+            .collect();
+        particles.sort_by_key(|(id, ..)| *id);
+        *STATE.lock().unwrap() = Some(State {
+            particles,
+            time: **time,
+            timestep_state: *timestep_state,
+        });
+    }
+
+    fn build_sim(sim: &mut Simulation, name: &str, final_time: f64, restart: bool) {
+        let output_dir = std::env::temp_dir().join(name);
+        sim.add_parameter_file_contents(format!(
+            "
+output:
+  output_dir: {}
+  time_between_snapshots: 2 s
+  handle_existing_output: delete
+restart:
+  time_between_restarts: 1000 s
+",
+            output_dir.to_str().unwrap()
+        ))
+        .add_parameters_explicitly(TimestepParameters {
+            max_timestep: units::Time::seconds(1.0),
+            num_levels: 3,
+            courant_factor: 0.15,
+            acceleration_factor: 0.025,
+        })
+        .add_parameters_explicitly(GravityParameters {
+            opening_angle: Dimensionless::dimensionless(0.5),
+            opening_criterion: OpeningCriterion::Geometric,
+            relative_tolerance: Dimensionless::dimensionless(0.0025),
+            softening_length: Length::meters(0.1),
+            softening: Softening::Plummer,
+            species_softening_lengths: HashMap::default(),
+            multipole_order: MultipoleOrder::Quadrupole,
+            periodicity: Periodicity::NearestImage,
+        })
+        .add_parameters_explicitly(SimulationBox::from(
+            Extent::from_positions(get_particles_this_test().iter().map(|x| &x.pos))
+                .unwrap()
+                .pad(),
+        ))
+        .add_parameters_explicitly(SimulationParameters {
+            final_time: Some(units::Time::seconds(final_time)),
+            max_wall_clock_time: None,
+        })
+        .write_output(true)
+        .restart(restart)
+        .add_bevy_plugins(MinimalPlugins)
+        .add_plugin(SimulationStagesPlugin)
+        .add_plugin(DomainDecompositionPlugin)
+        .add_plugin(SimulationPlugin)
+        .add_plugin(GravityPlugin);
+        if !restart {
+            sim.add_startup_system(spawn_particles_system);
+        }
+    }
+
+    fn run_and_get_state(build: fn(&mut Simulation)) -> State {
+        let run = |mut sim: Simulation| {
+            sim.run_without_finalize();
+            run_system_on_sim(&mut sim, store_state_system);
+        };
+        build_local_communication_sim_with_custom_logic(build, run, 1);
+        STATE.lock().unwrap().take().unwrap()
+    }
+
+    #[test]
+    fn resumed_simulation_is_identical_to_uninterrupted_one() {
+        let uninterrupted = run_and_get_state(|sim| {
+            build_sim(sim, "raxiom_resume_uninterrupted", FINAL_TIME, false)
+        });
+        let first_part = run_and_get_state(|sim| {
+            build_sim(sim, "raxiom_resume_interrupted", TIME_RESTART, false)
+        });
+        assert_eq!(first_part.time, units::Time::seconds(TIME_RESTART));
+        let resumed =
+            run_and_get_state(|sim| build_sim(sim, "raxiom_resume_interrupted", FINAL_TIME, true));
+        assert_eq!(
+            uninterrupted.particles.len(),
+            (NUM_PARTICLES_ONE_DIMENSION * NUM_PARTICLES_ONE_DIMENSION) as usize
+        );
+        assert_eq!(uninterrupted, resumed);
+    }
+}
@@ -1,14 +1,29 @@
 use std::ops::Deref;
 
 use bevy::ecs::schedule::SystemDescriptor;
+use bevy::ecs::schedule::SystemLabelId;
 use bevy::prelude::*;
 use hdf5::Dataset;
+use hdf5::Group;
 use hdf5::H5Type;
+use mpi::traits::Equivalence;
+use mpi::traits::MatchesRaw;
 
+use super::gadget;
+use super::gadget::FileFormat;
+use super::output::get_or_create_group;
 use super::output::plugin::IntoOutputSystem;
+use super::output::FileSlice;
 use super::output::OutputFile;
+use crate::communication::CommunicationPlugin;
+use crate::communication::Communicator;
+use crate::components::Species;
+use crate::components::NUM_SPECIES;
+use crate::config::NUM_DIMENSIONS;
 use crate::named::Named;
+use crate::prelude::MVec;
 use crate::prelude::Particles;
+use crate::simulation::Simulation;
 use crate::units::Dimension;
 use crate::units::Quantity;
 
@@ -24,17 +39,69 @@ struct DatasetSystemAmbiguityLabel;
 pub trait ToDataset: Clone + Component + H5Type + Named + Sync + Send + 'static {
     fn dimension() -> Dimension;
     fn convert_base_units(self, factor: f64) -> Self;
+
+    /// The number of floats that make up a single value of the
+    /// component, for example 3 for a vector in 3D. None for
+    /// components which do not consist of floats (such as the
+    /// particle ids).
+    fn num_floats() -> Option<usize> {
+        None
+    }
+
+    /// Appends the floats that make up the value to `floats`. Only
+    /// called for components which consist of floats.
+    fn to_floats(&self, _floats: &mut Vec<f64>) {
+        panic!("Component {} does not consist of floats", Self::name())
+    }
+
+    /// Constructs a value from its floats. Only called for
+    /// components which consist of floats.
+    fn from_floats(_floats: &[f64]) -> Self {
+        panic!("Component {} does not consist of floats", Self::name())
+    }
+}
+
+/// Quantities which consist of a fixed number of floats.
+pub trait Floats: Sized {
+    const NUM_FLOATS: usize;
+    fn to_floats(&self, floats: &mut Vec<f64>);
+    fn from_floats(floats: &[f64]) -> Self;
+}
+
+impl Floats for f64 {
+    const NUM_FLOATS: usize = 1;
+
+    fn to_floats(&self, floats: &mut Vec<f64>) {
+        floats.push(*self);
+    }
+
+    fn from_floats(floats: &[f64]) -> Self {
+        floats[0]
+    }
+}
+
+impl Floats for MVec {
+    const NUM_FLOATS: usize = NUM_DIMENSIONS;
+
+    fn to_floats(&self, floats: &mut Vec<f64>) {
+        floats.extend(self.to_array());
+    }
+
+    fn from_floats(floats: &[f64]) -> Self {
+        MVec::from_slice(floats)
+    }
 }
 
 impl<const D: Dimension, S, T> ToDataset for T
 where
-    S: Clone + 'static + std::ops::Mul<f64, Output = S>,
+    S: Clone + Floats + 'static + std::ops::Mul<f64, Output = S>,
     T: Clone
         + Component
         + Named
         + H5Type
         + Deref<Target = Quantity<S, D>>
-        + From<<Quantity<S, D> as std::ops::Mul<f64>>::Output>,
+        + From<<Quantity<S, D> as std::ops::Mul<f64>>::Output>
+        + From<Quantity<S, D>>,
     Quantity<S, D>: std::ops::Mul<f64>,
 {
     fn dimension() -> Dimension {
@@ -44,23 +111,86 @@ where
     fn convert_base_units(self, factor: f64) -> T {
         (T::deref(&self).clone() * factor).into()
     }
+
+    fn num_floats() -> Option<usize> {
+        Some(S::NUM_FLOATS)
+    }
+
+    fn to_floats(&self, floats: &mut Vec<f64>) {
+        T::deref(self).value_unchecked().to_floats(floats)
+    }
+
+    fn from_floats(floats: &[f64]) -> Self {
+        Quantity::<S, D>(S::from_floats(floats)).into()
+    }
 }
 
-impl<T: ToDataset> IntoOutputSystem for T {
-    fn system() -> SystemDescriptor {
-        write_dataset::<T>
-            .into_descriptor()
-            .label(DatasetSystemAmbiguityLabel)
-            .ambiguous_with(DatasetSystemAmbiguityLabel)
+impl<T> IntoOutputSystem for T
+where
+    T: ToDataset + Equivalence,
+    <T as Equivalence>::Out: MatchesRaw,
+{
+    fn system(format: &FileFormat) -> SystemDescriptor {
+        match format {
+            FileFormat::Raxiom => write_dataset_system::<T>.into_descriptor(),
+            FileFormat::Gadget => gadget::write_dataset_system::<T>.into_descriptor(),
+        }
+        .label(DatasetSystemAmbiguityLabel)
+        .ambiguous_with(DatasetSystemAmbiguityLabel)
     }
+
+    fn label(format: &FileFormat) -> SystemLabelId {
+        match format {
+            FileFormat::Raxiom => write_dataset_system::<T>.as_system_label(),
+            FileFormat::Gadget => gadget::write_dataset_system::<T>.as_system_label(),
+        }
+    }
+
+    fn build(sim: &mut Simulation) {
+        // Used to gather the data on the writing ranks.
+        sim.add_plugin(CommunicationPlugin::<T>::default());
+    }
+}
+
+fn write_dataset_system<T: ToDataset + Equivalence>(
+    query: Particles<(&T, Option<&Species>)>,
+    mut file: ResMut<OutputFile>,
+    mut comm: Communicator<T>,
+) {
+    let mut data: [Vec<T>; NUM_SPECIES] = Default::default();
+    for (item, species) in query.iter() {
+        data[species.copied().unwrap_or_default().index()].push(item.clone());
+    }
+    let data = match file.gather(data, &mut comm) {
+        Some(data) => data,
+        None => return,
+    };
+    file.write(move |f, slice| {
+        for species in Species::ALL {
+            let data = &data[species.index()];
+            if !data.is_empty() {
+                let group = get_or_create_group(f, species.group_name());
+                write_dataset(&group, data, species, slice);
+            }
+        }
+    });
+}
+
+fn write_dataset<T: ToDataset>(group: &Group, data: &[T], species: Species, slice: &FileSlice) {
+    let offset = slice.offset[species.index()];
+    // The first rank to write to the file creates the dataset.
+    let dataset = group
+        .dataset(T::name())
+        .unwrap_or_else(|_| create_dataset::<T>(group, slice.total[species.index()]));
+    dataset
+        .write_slice(data, offset..offset + data.len())
+        .expect("Failed to write dataset");
 }
 
-fn write_dataset<T: ToDataset>(query: Particles<&T>, file: ResMut<OutputFile>) {
-    let f = file.f.as_ref().unwrap();
-    let data: Vec<T> = query.iter().cloned().collect();
-    let dataset = f
-        .new_dataset_builder()
-        .with_data(&data)
+fn create_dataset<T: ToDataset>(group: &Group, size: usize) -> Dataset {
+    let dataset = group
+        .new_dataset::<T>()
+        .shape(size)
         .create(T::name())
         .expect("Failed to write dataset");
     let attr = dataset
@@ -84,6 +214,7 @@ fn write_dataset<T: ToDataset>(query: Particles<&T>, file: ResMut<OutputFile>) {
     write_dimension(&dataset, TIME_IDENTIFIER, time);
     write_dimension(&dataset, MASS_IDENTIFIER, mass);
     write_dimension(&dataset, TEMPERATURE_IDENTIFIER, temperature);
+    dataset
 }
 
 fn write_dimension(dataset: &Dataset, identifier: &str, dimension: i32) {
@@ -16,6 +16,8 @@ pub mod communication;
 pub(crate) mod communication;
 pub mod components;
 pub(crate) mod config;
+#[cfg(not(feature = "2d"))]
+pub(crate) mod cooling;
 pub(crate) mod domain;
 pub(crate) mod gravity;
 pub mod hydrodynamics;
@@ -31,7 +33,10 @@ pub(crate) mod simulation;
 pub(crate) mod simulation_box;
 pub(crate) mod simulation_builder;
 pub mod simulation_plugin;
+pub(crate) mod sink_particles;
 pub(crate) mod stages;
+#[cfg(not(feature = "2d"))]
+pub(crate) mod star_formation;
 pub(crate) mod timestep;
 pub(crate) mod visualization;
 
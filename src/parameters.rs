@@ -1,13 +1,35 @@
+#[cfg(not(feature = "2d"))]
+pub use crate::cooling::Chemistry;
+#[cfg(not(feature = "2d"))]
+pub use crate::cooling::CoolingParameters;
+#[cfg(not(feature = "2d"))]
+pub use crate::cooling::MetalCoolingParameters;
 pub use crate::domain::DomainParameters;
 pub use crate::gravity::GravityParameters;
+pub use crate::gravity::MultipoleOrder;
+pub use crate::gravity::OpeningCriterion;
+pub use crate::gravity::Periodicity;
+pub use crate::gravity::Softening;
+pub use crate::hydrodynamics::ArtificialViscosity;
 pub use crate::hydrodynamics::HydrodynamicsParameters;
 pub use crate::hydrodynamics::InitialGasEnergy;
+pub use crate::hydrodynamics::SmoothingLengthIteration;
+pub use crate::hydrodynamics::SphKernel;
+pub use crate::io::code_units::CodeUnits;
+pub use crate::io::gadget::FileFormat;
 pub use crate::io::input::InputParameters;
+pub use crate::io::input::InputSchema;
 pub use crate::io::output::parameters::*;
+pub use crate::io::restart::RestartParameters;
 pub use crate::memory::MemoryUsageParameters;
 pub use crate::performance_parameters::PerformanceParameters;
 pub use crate::prelude::SimulationBox;
 pub use crate::quadtree::QuadTreeConfig;
 pub use crate::simulation_plugin::SimulationParameters;
+pub use crate::sink_particles::SinkParticleParameters;
+#[cfg(not(feature = "2d"))]
+pub use crate::star_formation::StarFormationParameters;
+#[cfg(not(feature = "2d"))]
+pub use crate::star_formation::SupernovaFeedback;
 pub use crate::timestep::TimestepParameters;
 pub use crate::visualization::VisualizationParameters;
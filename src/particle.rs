@@ -2,16 +2,33 @@ use bevy::ecs::archetype::Archetypes;
 use bevy::ecs::component::Components;
 use bevy::prelude::debug;
 use bevy::prelude::Bundle;
+use bevy::prelude::Commands;
 use bevy::prelude::Component;
+use bevy::prelude::Entity;
 use bevy::prelude::Query;
+use bevy::prelude::Res;
 use bevy::prelude::StartupStage;
 use bevy::prelude::With;
+use bevy::prelude::Without;
+use mpi::traits::Equivalence;
 
+use crate::communication::CommunicationPlugin;
+use crate::communication::Communicator;
+use crate::communication::WorldRank;
+use crate::components::DarkMatter;
+use crate::components::Gas;
 use crate::components::Mass;
+use crate::components::ParticleId;
 use crate::components::Position;
+use crate::components::Sink;
+use crate::components::Species;
+use crate::components::Star;
 use crate::components::Velocity;
+use crate::domain::DomainDecompositionStages;
+use crate::io::input::ComponentInput;
 use crate::named::Named;
 use crate::prelude::Simulation;
+use crate::prelude::SimulationStartupStages;
 use crate::simulation::RaxiomPlugin;
 
 #[derive(Component)]
@@ -43,7 +60,85 @@ pub struct ParticlePlugin;
 
 impl RaxiomPlugin for ParticlePlugin {
     fn build_everywhere(&self, sim: &mut Simulation) {
-        sim.add_startup_system_to_stage(StartupStage::PostStartup, count_types_system);
+        sim.add_component_no_io::<Species>()
+            .add_component::<ParticleId>(ComponentInput::Optional)
+            .add_plugin(CommunicationPlugin::<NewParticleIds>::default())
+            .add_startup_system_to_stage(
+                SimulationStartupStages::InsertDerivedComponents,
+                insert_particle_ids_system,
+            )
+            // The species of particles from restart files is only
+            // known after InsertComponents. Startup systems in the
+            // same stage need to query for the Species instead of
+            // the markers.
+            .add_startup_system_to_stage(
+                SimulationStartupStages::InsertDerivedComponents,
+                insert_species_markers_system,
+            )
+            .add_system_to_stage(
+                DomainDecompositionStages::AfterExchange,
+                insert_species_markers_system,
+            )
+            .add_startup_system_to_stage(StartupStage::PostStartup, count_types_system);
+    }
+}
+
+/// Gives every particle without a species marker the marker of its
+/// species. Particles without a species become gas particles.
+fn insert_species_markers_system(
+    mut commands: Commands,
+    particles: Particles<
+        (Entity, Option<&Species>),
+        (
+            Without<Gas>,
+            Without<DarkMatter>,
+            Without<Star>,
+            Without<Sink>,
+        ),
+    >,
+) {
+    for (entity, species) in particles.iter() {
+        let species = species.copied().unwrap_or_default();
+        let mut entity = commands.entity(entity);
+        entity.insert(species);
+        match species {
+            Species::Gas => entity.insert(Gas),
+            Species::DarkMatter => entity.insert(DarkMatter),
+            Species::Star => entity.insert(Star),
+            Species::Sink => entity.insert(Sink),
+        };
+    }
+}
+
+#[derive(Clone, Equivalence)]
+struct NewParticleIds {
+    num_new_ids: u64,
+    next_free_id: u64,
+}
+
+/// Gives every particle without an id (such as particles sampled
+/// by the initial conditions or read from files which do not
+/// contain ids) a new id which is unique among all ranks.
+fn insert_particle_ids_system(
+    mut commands: Commands,
+    particles: Particles<Entity, Without<ParticleId>>,
+    ids: Particles<&ParticleId>,
+    rank: Res<WorldRank>,
+    mut comm: Communicator<NewParticleIds>,
+) {
+    let next_free_id = ids.iter().map(|id| **id + 1).max().unwrap_or(0);
+    let new_ids = comm.all_gather(&NewParticleIds {
+        num_new_ids: particles.iter().count() as u64,
+        next_free_id,
+    });
+    let next_free_id = new_ids.iter().map(|ids| ids.next_free_id).max().unwrap();
+    let first_id = next_free_id
+        + new_ids[..**rank as usize]
+            .iter()
+            .map(|ids| ids.num_new_ids)
+            .sum::<u64>();
+    for (id, entity) in (first_id..).zip(particles.iter()) {
+        commands.entity(entity).insert(ParticleId(id));
     }
 }
 
@@ -109,4 +204,49 @@ mod tests {
         }
         run_system_on_world(&mut world, system);
     }
+
+    #[cfg(not(feature = "mpi"))]
+    fn check_particle_ids(mut sim: crate::simulation::Simulation) {
+        use crate::communication::WorldRank;
+        use crate::components::ParticleId;
+        use crate::test_utils::run_system_on_sim;
+
+        let is_main = sim.unwrap_resource::<WorldRank>().is_main();
+        if is_main {
+            sim.world().spawn((LocalParticle, ParticleId(10)));
+            sim.world()
+                .spawn_batch([(LocalParticle,), (LocalParticle,)]);
+        } else {
+            sim.world()
+                .spawn_batch([(LocalParticle,), (LocalParticle,), (LocalParticle,)]);
+        }
+        run_system_on_sim(&mut sim, super::insert_particle_ids_system);
+        let mut ids: Vec<_> = sim
+            .world()
+            .query::<&ParticleId>()
+            .iter(sim.world())
+            .map(|id| **id)
+            .collect();
+        ids.sort();
+        if is_main {
+            assert_eq!(ids, [10, 11, 12]);
+        } else {
+            assert_eq!(ids, [13, 14, 15]);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "mpi"))]
+    fn new_particle_ids_are_unique_among_ranks() {
+        use crate::communication::local_sim_building::build_local_communication_sim_with_custom_logic;
+        use crate::communication::CommunicationPlugin;
+
+        build_local_communication_sim_with_custom_logic(
+            |sim| {
+                sim.add_plugin(CommunicationPlugin::<super::NewParticleIds>::default());
+            },
+            check_particle_ids,
+            2,
+        );
+    }
 }
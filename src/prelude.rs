@@ -2,12 +2,15 @@ pub use derive_custom::raxiom_parameters;
 
 pub use crate::communication::WorldRank;
 pub use crate::communication::WorldSize;
+#[cfg(not(feature = "2d"))]
+pub use crate::cooling::CoolingPlugin;
 pub use crate::domain::Extent;
 pub use crate::domain::GlobalExtent;
 pub use crate::gravity::GravityPlugin;
 pub use crate::hydrodynamics::HaloParticles;
 pub use crate::hydrodynamics::HydroParticles;
 pub use crate::hydrodynamics::HydrodynamicsPlugin;
+pub use crate::hydrodynamics::SelfGravityPlugin;
 pub use crate::named::*;
 pub use crate::particle::LocalParticle;
 pub use crate::particle::Particles;
@@ -19,6 +22,9 @@ pub use crate::simulation_builder::SimulationBuilder;
 pub use crate::simulation_plugin::SimulationStages;
 pub use crate::simulation_plugin::SimulationStartupStages;
 pub use crate::simulation_plugin::StopSimulationEvent;
+pub use crate::sink_particles::SinkParticlePlugin;
+#[cfg(not(feature = "2d"))]
+pub use crate::star_formation::StarFormationPlugin;
 pub use crate::timestep::ActiveParticles;
 pub use crate::units;
 pub use crate::units::helpers::Float;
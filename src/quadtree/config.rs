@@ -3,6 +3,9 @@ use derive_custom::raxiom_parameters;
 /// Parameters controlling the construction of a tree.
 #[raxiom_parameters]
 pub struct QuadTreeConfig {
+    /// The depth to which the tree is subdivided regardless of
+    /// the particles in it. The top-level nodes of the domain
+    /// decomposition are refined adaptively beyond this depth.
     pub min_depth: usize,
     /// The maximum depth of the tree. Should be high enough to ensure
     /// that the tree can keep an approximately constant number of
@@ -4,8 +4,11 @@ use std::ops::IndexMut;
 use mpi::traits::Equivalence;
 
 use super::node_index::NodeIndex;
+use super::LeafDataType;
 use super::Node;
+use super::NodeDataType;
 use super::QuadTree;
+use super::QuadTreeConfig;
 use super::MAX_DEPTH;
 use crate::config::TWO_TO_NUM_DIMENSIONS;
 
@@ -50,6 +53,23 @@ impl QuadTreeIndex {
         Self::internal_iter_all_at_depth(depth, QuadTreeIndex::default(), 0)
     }
 
+    /// The depth of the node this index points to.
+    pub fn depth(&self) -> usize {
+        self.0
+            .iter()
+            .position(|num| NodeIndex::from(*num) == NodeIndex::ThisNode)
+            .expect("Invalid quad tree index which does not terminate before MAX_DEPTH")
+    }
+
+    /// The index of the given child of the node this index points to.
+    pub fn child(&self, num_child: usize) -> Self {
+        let mut index = *self;
+        let depth = self.depth();
+        index.0[depth] = NodeIndex::Child(num_child as u8).into();
+        index.0[depth + 1] = NodeIndex::ThisNode.into();
+        index
+    }
+
     // I implemented this thinking I'd need it immediately but didn't,
     // however this will definitely become useful at some point
     #[allow(dead_code)]
@@ -88,6 +108,32 @@ impl<N, L> IndexMut<&QuadTreeIndex> for QuadTree<N, L> {
     }
 }
 
+impl<N: NodeDataType<L>, L: LeafDataType> QuadTree<N, L> {
+    /// Subdivides all leaves on the path to the node at the given
+    /// index, so that the node exists in the tree even if the
+    /// particles in the tree alone would not have caused the
+    /// subdivision.
+    pub fn subdivide_to_index(&mut self, config: &QuadTreeConfig, idx: &QuadTreeIndex) {
+        self.subdivide_to_index_at_depth(config, idx, 0)
+    }
+
+    fn subdivide_to_index_at_depth(
+        &mut self,
+        config: &QuadTreeConfig,
+        idx: &QuadTreeIndex,
+        depth: usize,
+    ) {
+        if let NodeIndex::Child(num) = idx.0[depth].into() {
+            if let Node::Leaf(_) = self.node {
+                self.subdivide(config, depth);
+            }
+            if let Node::Tree(ref mut children) = self.node {
+                children[num as usize].subdivide_to_index_at_depth(config, idx, depth + 1);
+            }
+        }
+    }
+}
+
 impl<N, L> QuadTree<N, L> {
     fn index_into_depth(&self, idx: &QuadTreeIndex, depth: usize) -> &Self {
         match idx.0[depth].into() {
@@ -128,6 +174,7 @@ mod tests {
     use crate::quadtree::Node;
     use crate::quadtree::QuadTree;
     use crate::quadtree::QuadTreeConfig;
+    use crate::units::Length;
     use crate::units::Mass;
 
     #[test]
@@ -146,6 +193,8 @@ mod tests {
                 pos,
                 mass: Mass::zero(),
                 entity: Entity::from_raw(0),
+                softening_length: Length::zero(),
+                work: 0.0,
             };
             tree.insert_new(&config, data, 0);
         }
@@ -180,4 +229,15 @@ mod tests {
         assert!(!index1.belongs_to(&index3));
         assert!(!index3.belongs_to(&index1));
     }
+
+    #[test]
+    fn subdivide_to_index_creates_node() {
+        let config = QuadTreeConfig::default();
+        let mut tree: QuadTree<(), LeafData> = get_min_depth_quadtree(0);
+        let index = QuadTreeIndex::default().child(1).child(0).child(3);
+        assert_eq!(index.depth(), 3);
+        tree.subdivide_to_index(&config, &index);
+        assert!(matches!(tree[&index].node, Node::Leaf(_)));
+        assert!(index.belongs_to(&QuadTreeIndex::default().child(1)));
+    }
 }
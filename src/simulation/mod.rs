@@ -21,6 +21,7 @@ use bevy::prelude::StageLabel;
 use bevy::prelude::SystemSet;
 use bevy::prelude::World;
 use derive_traits::RaxiomParameters;
+use hdf5::H5Type;
 use mpi::traits::Equivalence;
 use mpi::traits::MatchesRaw;
 pub use raxiom_plugin::RaxiomPlugin;
@@ -30,11 +31,15 @@ use crate::domain::ExchangeDataPlugin;
 use crate::io::input::ComponentInput;
 use crate::io::input::DatasetInputPlugin;
 use crate::io::output::OutputPlugin;
+use crate::io::restart::RestartPlugin;
 use crate::io::to_dataset::ToDataset;
 use crate::memory::ComponentMemoryUsagePlugin;
 use crate::named::Named;
 use crate::parameter_plugin::ParameterFileContents;
 use crate::parameter_plugin::ParameterPlugin;
+use crate::simulation_plugin::Interrupted;
+use crate::simulation_plugin::INTERRUPTED_EXIT_CODE;
+use crate::simulation_plugin::INTERRUPTED_WITHOUT_RESTART_EXIT_CODE;
 use crate::timestep::TimestepState;
 
 #[derive(Default)]
@@ -46,6 +51,8 @@ pub struct Simulation {
     current_communication_tag: i32,
     pub read_initial_conditions: bool,
     pub write_output: bool,
+    pub restart: bool,
+    pub handle_stop_signals: bool,
 }
 
 impl Simulation {
@@ -73,6 +80,19 @@ impl Simulation {
         self
     }
 
+    pub fn restart(&mut self, restart: bool) -> &mut Self {
+        self.restart = restart;
+        self
+    }
+
+    /// Whether to stop the simulation early (and write restart
+    /// files) when receiving a SIGTERM or SIGUSR1 signal. The
+    /// signal handlers are registered once the simulation starts.
+    pub fn handle_stop_signals(&mut self, handle_stop_signals: bool) -> &mut Self {
+        self.handle_stop_signals = handle_stop_signals;
+        self
+    }
+
     pub fn already_added<P: Named>(&mut self) -> bool {
         !self.labels.insert(P::name())
     }
@@ -245,9 +265,24 @@ impl Simulation {
         self
     }
 
+    /// Runs the simulation and calls MPI_FINALIZE afterwards. If the
+    /// simulation was interrupted, exits the program with
+    /// [`INTERRUPTED_EXIT_CODE`].
     pub fn run(&mut self) {
         self.run_without_finalize();
         Simulation::finalize();
+        if self
+            .get_resource::<Interrupted>()
+            .map(|interrupted| interrupted.0)
+            .unwrap_or(false)
+        {
+            // Restart files are only written along with the output.
+            if self.write_output {
+                std::process::exit(INTERRUPTED_EXIT_CODE);
+            } else {
+                std::process::exit(INTERRUPTED_WITHOUT_RESTART_EXIT_CODE);
+            }
+        }
     }
 
     pub fn finalize() {
@@ -375,6 +410,9 @@ impl Simulation {
             ComponentInput::Required => {
                 self.add_plugin(DatasetInputPlugin::<T>::default());
             }
+            ComponentInput::Optional => {
+                self.add_plugin(DatasetInputPlugin::<T>::optional());
+            }
             ComponentInput::Derived => {}
         }
         self
@@ -398,13 +436,14 @@ impl Simulation {
 
     pub fn add_component_no_io<T>(&mut self) -> &mut Self
     where
-        T: Clone + Named + Equivalence + Component,
+        T: Clone + Named + Equivalence + Component + H5Type,
         <T as Equivalence>::Out: MatchesRaw,
     {
         if self.has_world_rank() {
             self.add_plugin(ExchangeDataPlugin::<T>::default());
         }
-        self.add_plugin(ComponentMemoryUsagePlugin::<T>::default());
+        self.add_plugin(ComponentMemoryUsagePlugin::<T>::default())
+            .add_plugin(RestartPlugin::<T>::default());
         self
     }
 
@@ -30,6 +30,8 @@ pub struct SimulationBuilder {
     pub verbosity: usize,
     pub read_initial_conditions: bool,
     pub write_output: bool,
+    pub restart: bool,
+    pub handle_stop_signals: bool,
     pub log: bool,
     pub parameter_overrides: Vec<Override>,
     base_communication: Option<BaseCommunicationPlugin>,
@@ -44,6 +46,8 @@ impl Default for SimulationBuilder {
             verbosity: 0,
             read_initial_conditions: true,
             write_output: true,
+            restart: false,
+            handle_stop_signals: false,
             log: true,
             base_communication: None,
             parameter_overrides: vec![],
@@ -109,6 +113,10 @@ impl SimulationBuilder {
         if let Some(ref path) = opts.parameter_file_path {
             self.parameter_file_path(path);
         }
+        if opts.restart {
+            self.restart(true);
+        }
+        self.handle_stop_signals(true);
         self.verbosity(opts.verbosity);
         self.parameter_overrides = opts.parameter_overrides.clone();
         self
@@ -144,6 +152,21 @@ impl SimulationBuilder {
         self
     }
 
+    /// Resume the simulation from the restart files in the output
+    /// directory instead of reading or creating initial conditions.
+    pub fn restart(&mut self, restart: bool) -> &mut Self {
+        self.restart = restart;
+        self
+    }
+
+    /// Stop the simulation early when receiving a SIGTERM or
+    /// SIGUSR1 signal. Disabled by default and enabled for
+    /// simulations built from the command line.
+    pub fn handle_stop_signals(&mut self, handle_stop_signals: bool) -> &mut Self {
+        self.handle_stop_signals = handle_stop_signals;
+        self
+    }
+
     pub fn log(&mut self, log: bool) -> &mut Self {
         self.log = log;
         self
@@ -160,6 +183,8 @@ impl SimulationBuilder {
             .insert_resource(self.winit_settings())
             .read_initial_conditions(self.read_initial_conditions)
             .write_output(self.write_output)
+            .restart(self.restart)
+            .handle_stop_signals(self.handle_stop_signals)
             .maybe_add_plugin(self.base_communication.clone());
         if sim.on_main_rank() && self.log {
             sim.add_bevy_plugin(self.log_plugin());
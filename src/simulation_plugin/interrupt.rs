@@ -0,0 +1,115 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use bevy::prelude::*;
+use signal_hook::consts::SIGTERM;
+use signal_hook::consts::SIGUSR1;
+
+use super::ShouldExit;
+use super::SimulationParameters;
+use super::StopSimulationEvent;
+use crate::communication::Communicator;
+use crate::units;
+
+/// The exit code of the program if the simulation was stopped
+/// early, either because it reached the maximum wall clock time or
+/// because it received a SIGTERM or SIGUSR1 signal. Corresponds to
+/// EX_TEMPFAIL, signaling to job scripts that the simulation can be
+/// resumed from its restart files, which are always written when
+/// the simulation is stopped early.
+pub const INTERRUPTED_EXIT_CODE: i32 = 75;
+
+/// The exit code of the program if the simulation was stopped
+/// early but no restart files were written, because the output
+/// is disabled, so that it cannot be resumed. Differs from the
+/// generic failure code 1, so that job scripts can tell it apart
+/// from an error.
+pub const INTERRUPTED_WITHOUT_RESTART_EXIT_CODE: i32 = 3;
+
+/// Whether all ranks agreed to stop the simulation early.
+#[derive(Default, Resource)]
+pub struct Interrupted(pub bool);
+
+/// Set by the signal handlers once a SIGTERM or SIGUSR1 has been
+/// received. Never set if the handlers are not registered.
+#[derive(Default, Resource)]
+pub(super) struct StopSignalReceived(Arc<AtomicBool>);
+
+/// Registers the signal handlers once the simulation starts. Only
+/// added if [`Simulation::handle_stop_signals`](crate::simulation::Simulation::handle_stop_signals)
+/// is set, so that simulations in tests (or those embedded in other
+/// programs) keep the default behaviour of the signals.
+pub(super) fn register_signal_handlers_system(signal: Res<StopSignalReceived>) {
+    for signal_id in [SIGTERM, SIGUSR1] {
+        signal_hook::flag::register(signal_id, signal.0.clone())
+            .unwrap_or_else(|e| panic!("Failed to register signal handler: {e}"));
+    }
+}
+
+#[derive(Resource)]
+pub(super) struct WallClockTimer {
+    start: Instant,
+    last_step: Instant,
+    longest_step: Duration,
+}
+
+impl Default for WallClockTimer {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last_step: now,
+            longest_step: Duration::ZERO,
+        }
+    }
+}
+
+impl WallClockTimer {
+    fn update(&mut self) {
+        let now = Instant::now();
+        self.longest_step = self.longest_step.max(now - self.last_step);
+        self.last_step = now;
+    }
+
+    /// Whether there might not be enough time left for another
+    /// step and the final output, which we (conservatively) assume
+    /// to take at most twice as long as the longest step so far.
+    fn close_to(&self, max_wall_clock_time: units::Time) -> bool {
+        let projected = (self.last_step - self.start) + 2 * self.longest_step;
+        units::Time::seconds(projected.as_secs_f64()) >= max_wall_clock_time
+    }
+}
+
+/// Stops the simulation on all ranks if any rank is close to the
+/// maximum wall clock time or has received a stop signal. Runs at
+/// the end of the step, so that the final snapshot and the restart
+/// files are written in the same step.
+pub(super) fn interrupt_system(
+    parameters: Res<SimulationParameters>,
+    signal: Res<StopSignalReceived>,
+    mut timer: ResMut<WallClockTimer>,
+    mut interrupted: ResMut<Interrupted>,
+    mut comm: Communicator<ShouldExit>,
+    mut stop_sim: EventWriter<StopSimulationEvent>,
+) {
+    timer.update();
+    let signal_received = signal.0.load(Ordering::Relaxed);
+    if signal_received {
+        info!("Received stop signal.");
+    }
+    let wall_clock_time_reached = parameters
+        .max_wall_clock_time
+        .map(|max_wall_clock_time| timer.close_to(max_wall_clock_time))
+        .unwrap_or(false);
+    if wall_clock_time_reached {
+        info!("Reached maximum wall clock time.");
+    }
+    let result = comm.all_gather(&ShouldExit(signal_received || wall_clock_time_reached));
+    if result.into_iter().any(|x| x.0) {
+        interrupted.0 = true;
+        stop_sim.send(StopSimulationEvent);
+    }
+}
@@ -1,3 +1,4 @@
+mod interrupt;
 mod parameters;
 mod time;
 
@@ -9,16 +10,27 @@ use bevy::prelude::*;
 use bevy::window::exit_on_all_closed;
 use mpi::traits::Equivalence;
 
+use self::interrupt::interrupt_system;
+use self::interrupt::register_signal_handlers_system;
+pub use self::interrupt::Interrupted;
+use self::interrupt::StopSignalReceived;
+use self::interrupt::WallClockTimer;
+pub use self::interrupt::INTERRUPTED_EXIT_CODE;
+pub use self::interrupt::INTERRUPTED_WITHOUT_RESTART_EXIT_CODE;
 pub use self::parameters::SimulationParameters;
 pub use self::time::Time;
 use crate::communication::CommunicationPlugin;
 use crate::communication::Communicator;
+use crate::components::Acceleration;
 use crate::components::Mass;
 use crate::components::Position;
 use crate::components::Timestep;
 use crate::components::Velocity;
+use crate::components::Work;
 use crate::io::output::Attribute;
 use crate::io::output::OutputPlugin;
+use crate::io::restart::RestartAttribute;
+use crate::io::restart::RestartPlugin;
 use crate::named::Named;
 use crate::parameters::SimulationBox;
 use crate::parameters::TimestepParameters;
@@ -30,6 +42,7 @@ use crate::timestep::ConstantTimestep;
 use crate::timestep::TimestepPlugin;
 use crate::timestep::TimestepState;
 use crate::units;
+use crate::units::VecAcceleration;
 
 #[derive(Named)]
 pub struct SimulationPlugin;
@@ -59,13 +72,45 @@ impl RaxiomPlugin for SimulationPlugin {
             .add_required_component::<Position>()
             .add_required_component::<Mass>()
             .add_required_component::<Velocity>()
+            .add_derived_component::<Acceleration>()
+            .add_component_no_io::<Work>()
             .add_plugin(ParticlePlugin)
             .add_plugin(OutputPlugin::<Attribute<Time>>::default())
+            .add_plugin(RestartPlugin::<RestartAttribute<Time>>::default())
             .add_plugin(CommunicationPlugin::<ShouldExit>::default())
             .add_event::<StopSimulationEvent>()
             .insert_resource(Time(units::Time::seconds(0.00)))
+            .insert_resource(WallClockTimer::default())
+            .insert_resource(StopSignalReceived::default())
+            .insert_resource(Interrupted::default())
             .add_plugin(TimestepPlugin::<ConstantTimestep>::default())
-            .add_system_to_stage(SimulationStages::Integration, integrate_motion_system)
+            .add_startup_system_to_stage(
+                SimulationStartupStages::InsertDerivedComponents,
+                insert_acceleration_system,
+            )
+            .add_startup_system_to_stage(
+                SimulationStartupStages::InsertDerivedComponents,
+                insert_work_system,
+            )
+            .add_system_to_stage(
+                SimulationStages::ForceCalculation,
+                reset_accelerations_system,
+            )
+            .add_system_to_stage(SimulationStages::ForceCalculation, reset_work_system)
+            .add_system_to_stage(
+                SimulationStages::Integration,
+                closing_kick_system.label("closing_kick"),
+            )
+            .add_system_to_stage(
+                SimulationStages::Integration,
+                opening_kick_system
+                    .label("opening_kick")
+                    .after("closing_kick"),
+            )
+            .add_system_to_stage(
+                SimulationStages::Integration,
+                integrate_motion_system.after("opening_kick"),
+            )
             .add_system_to_stage(
                 SimulationStages::Integration,
                 show_time_system.before(time_system),
@@ -74,6 +119,10 @@ impl RaxiomPlugin for SimulationPlugin {
                 SimulationStages::Integration,
                 time_system.after(integrate_motion_system),
             )
+            .add_system_to_stage(
+                SimulationStages::Integration,
+                interrupt_system.after(time_system),
+            )
             .add_system_to_stage(CoreStage::PostUpdate, stop_simulation_system)
             .add_system_to_stage(
                 CoreStage::PostUpdate,
@@ -81,6 +130,9 @@ impl RaxiomPlugin for SimulationPlugin {
                     .after(stop_simulation_system)
                     .before(exit_on_all_closed),
             );
+        if sim.handle_stop_signals {
+            sim.add_startup_system(register_signal_handlers_system);
+        }
     }
 }
 
@@ -112,12 +164,94 @@ fn handle_app_exit_system(
     }
 }
 
+fn insert_acceleration_system(
+    mut commands: Commands,
+    particles: Particles<Entity, Without<Acceleration>>,
+) {
+    for entity in particles.iter() {
+        commands
+            .entity(entity)
+            .insert(Acceleration(VecAcceleration::zero()));
+    }
+}
+
+fn insert_work_system(mut commands: Commands, particles: Particles<Entity, Without<Work>>) {
+    for entity in particles.iter() {
+        commands.entity(entity).insert(Work(0.0));
+    }
+}
+
+/// Resets the work of all active particles, so that the
+/// force calculation systems can add their interaction counts.
+pub fn reset_work_system(
+    mut particles: Particles<(&mut Work, &Timestep)>,
+    parameters: Res<TimestepParameters>,
+    timestep_state: Res<TimestepState>,
+) {
+    for (mut work, timestep) in particles.iter_mut() {
+        if timestep_state.is_active(&parameters, **timestep) {
+            **work = 0.0;
+        }
+    }
+}
+
+/// Resets the accelerations of all active particles, so that the
+/// force calculation systems can add their contributions.
+pub fn reset_accelerations_system(
+    mut particles: Particles<(&mut Acceleration, &Timestep)>,
+    parameters: Res<TimestepParameters>,
+    timestep_state: Res<TimestepState>,
+) {
+    for (mut acceleration, timestep) in particles.iter_mut() {
+        if timestep_state.is_active(&parameters, **timestep) {
+            **acceleration = VecAcceleration::zero();
+        }
+    }
+}
+
+fn kick(
+    mut particles: Particles<(&mut Velocity, &Acceleration, &Timestep)>,
+    parameters: Res<TimestepParameters>,
+    timestep_state: Res<TimestepState>,
+) {
+    for (mut velocity, acceleration, timestep) in particles.iter_mut() {
+        if timestep_state.is_active(&parameters, **timestep) {
+            **velocity += **acceleration * 0.5 * **timestep;
+        }
+    }
+}
+
+/// The half-kick at the end of the timestep of all active particles,
+/// using the accelerations at the end of the timestep. Runs before
+/// the new timesteps are determined.
+pub fn closing_kick_system(
+    particles: Particles<(&mut Velocity, &Acceleration, &Timestep)>,
+    parameters: Res<TimestepParameters>,
+    timestep_state: Res<TimestepState>,
+) {
+    kick(particles, parameters, timestep_state);
+}
+
+/// The half-kick at the beginning of the new timestep of all active
+/// particles. Runs after the new timesteps have been determined.
+pub fn opening_kick_system(
+    particles: Particles<(&mut Velocity, &Acceleration, &Timestep)>,
+    parameters: Res<TimestepParameters>,
+    timestep_state: Res<TimestepState>,
+) {
+    kick(particles, parameters, timestep_state);
+}
+
+/// Drifts all particles by the smallest timestep, since every step
+/// advances the simulation by the timestep of the highest time bin.
 pub fn integrate_motion_system(
-    mut query: Particles<(&mut Position, &Velocity, &Timestep)>,
+    mut query: Particles<(&mut Position, &Velocity)>,
     box_: Res<SimulationBox>,
+    parameters: Res<TimestepParameters>,
 ) {
-    for (mut pos, velocity, timestep) in query.iter_mut() {
-        **pos += **velocity * **timestep;
+    let timestep = parameters.smallest_timestep();
+    for (mut pos, velocity) in query.iter_mut() {
+        **pos += **velocity * timestep;
         **pos = box_.periodic_wrap(**pos);
     }
 }
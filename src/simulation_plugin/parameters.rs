@@ -10,4 +10,16 @@ pub struct SimulationParameters {
     /// run indefinitely.
     #[serde(default)]
     pub final_time: Option<Time>,
+    /// If set to some value, the simulation will stop once the
+    /// elapsed wall clock time comes close to this value, leaving
+    /// enough time to write the final snapshot and the restart
+    /// files.
+    /// The program then exits with
+    /// [`INTERRUPTED_EXIT_CODE`](crate::simulation_plugin::INTERRUPTED_EXIT_CODE)
+    /// (or with
+    /// [`INTERRUPTED_WITHOUT_RESTART_EXIT_CODE`](crate::simulation_plugin::INTERRUPTED_WITHOUT_RESTART_EXIT_CODE)
+    /// if the output is disabled).
+    /// If None, there is no limit on the wall clock time.
+    #[serde(default)]
+    pub max_wall_clock_time: Option<Time>,
 }
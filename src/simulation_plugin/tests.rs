@@ -1,10 +1,11 @@
 use bevy::prelude::Commands;
-use bevy::prelude::Res;
+use bevy::prelude::IntoSystemDescriptor;
 use bevy::prelude::ResMut;
 use bevy::prelude::Resource;
 use bevy::MinimalPlugins;
 
 use crate::components;
+use crate::components::Acceleration;
 use crate::components::Position;
 use crate::components::Velocity;
 use crate::parameters::SimulationBox;
@@ -14,6 +15,7 @@ use crate::prelude::LocalParticle;
 use crate::prelude::Particles;
 use crate::prelude::SimulationStages;
 use crate::simulation::Simulation;
+use crate::simulation_plugin::reset_accelerations_system;
 use crate::simulation_plugin::SimulationPlugin;
 use crate::units::Energy;
 use crate::units::Length;
@@ -58,13 +60,12 @@ fn spawn_particles_system(mut commands: Commands) {
 struct TotalEnergy(Option<Energy>);
 
 fn force_system(
-    mut particles: Particles<(&mut Velocity, &Position, &components::Mass)>,
-    parameters: Res<TimestepParameters>,
+    mut particles: Particles<(&mut Acceleration, &Velocity, &Position, &components::Mass)>,
     mut initial_energy: ResMut<TotalEnergy>,
 ) {
     let mut iter = particles.iter_mut();
-    let (mut vel1, pos1, mass1) = iter.next().unwrap();
-    let (mut vel2, pos2, mass2) = iter.next().unwrap();
+    let (mut acc1, vel1, pos1, mass1) = iter.next().unwrap();
+    let (mut acc2, vel2, pos2, mass2) = iter.next().unwrap();
 
     let kinetic_energy = |vel: VecVelocity, mass| vel.length().squared() * mass;
     let total_energy = kinetic_energy(**vel1, **mass1)
@@ -72,10 +73,9 @@ fn force_system(
         + potential_energy(**pos1, **pos2, **mass1, **mass2)
         + potential_energy(**pos2, **pos1, **mass2, **mass1);
 
-    let timestep = parameters.max_timestep;
     let force = gravity_force(**pos1, **pos2, **mass1, **mass2);
-    **vel1 += force / **mass1 * timestep;
-    **vel2 -= force / **mass2 * timestep;
+    **acc1 = force / **mass1;
+    **acc2 = -force / **mass2;
     if let Some(initial_energy) = initial_energy.0 {
         let diff =
             (initial_energy - total_energy).abs() / (initial_energy.abs() + total_energy.abs());
@@ -93,12 +93,15 @@ fn build_integration_sim(sim: &mut Simulation) {
         .add_parameters_explicitly(TimestepParameters {
             max_timestep: Time::years(1e-3),
             num_levels: 1,
+            courant_factor: 0.15,
+            acceleration_factor: 0.025,
         })
         .add_parameters_explicitly(SimulationBox::cube_from_side_length_centered(
             Length::astronomical_units(100.0),
         ))
         .add_parameters_explicitly(SimulationParameters {
             final_time: Some(Time::years(1.0)),
+            max_wall_clock_time: None,
         })
         .write_output(false)
         .insert_resource(TotalEnergy(None))
@@ -106,7 +109,10 @@ fn build_integration_sim(sim: &mut Simulation) {
         .add_plugin(SimulationStagesPlugin)
         .add_plugin(SimulationPlugin)
         .add_startup_system(spawn_particles_system)
-        .add_system_to_stage(SimulationStages::ForceCalculation, force_system);
+        .add_system_to_stage(
+            SimulationStages::ForceCalculation,
+            force_system.after(reset_accelerations_system),
+        );
 }
 
 #[test]
@@ -115,3 +121,18 @@ fn integration() {
     build_integration_sim(&mut sim);
     sim.run();
 }
+
+#[test]
+fn stop_at_max_wall_clock_time() {
+    use crate::simulation_plugin::Interrupted;
+    use crate::simulation_plugin::Time;
+    use crate::units;
+
+    let mut sim = Simulation::test();
+    build_integration_sim(&mut sim);
+    sim.unwrap_resource_mut::<SimulationParameters>()
+        .max_wall_clock_time = Some(units::Time::seconds(0.0));
+    sim.run_without_finalize();
+    assert!(sim.unwrap_resource::<Interrupted>().0);
+    assert!(**sim.unwrap_resource::<Time>() < units::Time::years(1.0));
+}
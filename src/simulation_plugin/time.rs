@@ -3,6 +3,7 @@ use bevy::prelude::DerefMut;
 use bevy::prelude::Resource;
 
 use crate::io::output::ToAttribute;
+use crate::io::restart::ToRestartAttribute;
 use crate::named::Named;
 
 #[derive(Clone, Deref, DerefMut, Named, Resource)]
@@ -16,3 +17,15 @@ impl ToAttribute for Time {
         self.0
     }
 }
+
+impl ToRestartAttribute for Time {
+    type Output = crate::units::Time;
+
+    fn to_restart_value(&self) -> Self::Output {
+        self.0
+    }
+
+    fn from_restart_value(value: Self::Output) -> Self {
+        Self(value)
+    }
+}
@@ -0,0 +1,631 @@
+mod parameters;
+
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+
+use bevy::prelude::*;
+use mpi::traits::Equivalence;
+
+pub use self::parameters::SinkParticleParameters;
+use crate::communication::CommunicationPlugin;
+use crate::communication::DataByRank;
+use crate::communication::ExchangeCommunicator;
+use crate::communication::Identified;
+use crate::communication::Rank;
+use crate::communication::SizedCommunicator;
+use crate::components;
+use crate::components::Gas;
+use crate::components::GasComponents;
+use crate::components::InternalEnergy;
+use crate::components::Mass;
+use crate::components::ParticleId;
+use crate::components::Position;
+use crate::components::Sink;
+use crate::components::Species;
+use crate::components::Velocity;
+use crate::hydrodynamics::QuadTree;
+use crate::named::Named;
+use crate::prelude::ActiveParticles;
+use crate::prelude::Float;
+use crate::prelude::Particles;
+use crate::prelude::SimulationBox;
+use crate::prelude::WorldRank;
+use crate::simulation::RaxiomPlugin;
+use crate::simulation::Simulation;
+use crate::simulation_plugin::SimulationStages;
+use crate::units;
+use crate::units::helpers::VecQuantity;
+use crate::units::Density;
+use crate::units::Dimension;
+use crate::units::Energy;
+use crate::units::EnergyPerMass;
+use crate::units::Length;
+use crate::units::Quantity;
+use crate::units::VecLength;
+use crate::units::VecLengthMass;
+use crate::units::VecVelocity;
+use crate::units::GRAVITY_CONSTANT;
+use crate::units::NONE;
+
+type Momentum = Quantity<
+    Float,
+    {
+        Dimension {
+            mass: 1,
+            length: 1,
+            time: -1,
+            ..NONE
+        }
+    },
+>;
+
+type VecMomentum = VecQuantity<
+    {
+        Dimension {
+            mass: 1,
+            length: 1,
+            time: -1,
+            ..NONE
+        }
+    },
+>;
+
+/// Replaces collapsing regions of gas by sink particles, which
+/// accrete the surrounding gas and otherwise only interact
+/// gravitationally. An active gas particle above the
+/// [density threshold](crate::parameters::SinkParticleParameters::density_threshold)
+/// becomes a sink particle if
+/// 1. it is not within the accretion radius of an existing sink,
+/// 2. it is the densest gas particle within the accretion radius,
+/// 3. the gas within the accretion radius is converging,
+/// 4. its thermal energy is less than half of its gravitational
+///    energy and
+/// 5. its total energy is negative.
+///
+/// Every gas particle within the accretion radius of a sink which is
+/// gravitationally bound to it is accreted by the sink, conserving
+/// mass, momentum and the center of mass. The gas particles are
+/// accreted on their own rank, independently of where the sink lives.
+/// Requires the
+/// [HydrodynamicsPlugin](crate::prelude::HydrodynamicsPlugin).
+#[derive(Named)]
+pub struct SinkParticlePlugin;
+
+impl RaxiomPlugin for SinkParticlePlugin {
+    fn build_everywhere(&self, sim: &mut Simulation) {
+        sim.add_parameter_type::<SinkParticleParameters>()
+            .add_plugin(CommunicationPlugin::<Identified<SinkData>>::exchange())
+            .add_plugin(CommunicationPlugin::<Identified<Accretion>>::exchange())
+            .add_plugin(CommunicationPlugin::<SinkCandidate>::exchange())
+            .add_plugin(CommunicationPlugin::<SinkEnvironment>::exchange())
+            .insert_resource(Sinks::default())
+            // The hydrodynamical quantities of the gas (and its
+            // tree) are final only after the force calculation.
+            // Running before the kicks also ensures that
+            // gas particles which were turned into stars during
+            // the force calculation are not accreted.
+            .add_system_to_stage(
+                SimulationStages::Integration,
+                gather_sinks_system.before("closing_kick"),
+            )
+            .add_system_to_stage(
+                SimulationStages::Integration,
+                accretion_system
+                    .after(gather_sinks_system)
+                    .before("closing_kick"),
+            )
+            .add_system_to_stage(
+                SimulationStages::Integration,
+                form_sinks_system
+                    .after(accretion_system)
+                    .before("closing_kick"),
+            );
+    }
+}
+
+#[derive(Clone, Equivalence)]
+struct SinkData {
+    position: VecLength,
+    velocity: VecVelocity,
+    mass: units::Mass,
+}
+
+/// All sink particles on all ranks, together with the
+/// rank they live on.
+#[derive(Resource, Default, Deref, DerefMut)]
+struct Sinks(Vec<(Rank, Identified<SinkData>)>);
+
+/// The mass, momentum and mass-weighted offset (relative to the
+/// sink) of the gas accreted by a sink particle.
+#[derive(Clone, Default, Equivalence)]
+struct Accretion {
+    mass: units::Mass,
+    momentum: VecMomentum,
+    weighted_offset: VecLengthMass,
+}
+
+impl AddAssign for Accretion {
+    fn add_assign(&mut self, rhs: Self) {
+        self.mass += rhs.mass;
+        self.momentum += rhs.momentum;
+        self.weighted_offset += rhs.weighted_offset;
+    }
+}
+
+impl Accretion {
+    fn add_particle(
+        &mut self,
+        box_: &SimulationBox,
+        sink_position: &VecLength,
+        position: &VecLength,
+        velocity: VecVelocity,
+        mass: units::Mass,
+    ) {
+        self.mass += mass;
+        self.momentum += velocity * mass;
+        self.weighted_offset += box_.periodic_distance_vec(position, sink_position) * mass;
+    }
+
+    /// Adds the accreted gas to the sink particle, such that the sink
+    /// moves to the common center of mass with the common velocity.
+    fn apply(
+        &self,
+        box_: &SimulationBox,
+        position: &mut VecLength,
+        velocity: &mut VecVelocity,
+        mass: &mut units::Mass,
+    ) {
+        let total_mass = *mass + self.mass;
+        *velocity = (*velocity * *mass + self.momentum) / total_mass;
+        *position = box_.periodic_wrap(*position + self.weighted_offset / total_mass);
+        *mass = total_mass;
+    }
+}
+
+/// A gas particle which might form a sink particle, pending the
+/// checks on the gas within its accretion radius.
+#[derive(Clone, Equivalence)]
+struct SinkCandidate {
+    id: ParticleId,
+    position: VecLength,
+    velocity: VecVelocity,
+    density: Density,
+}
+
+/// Sums over the gas within the accretion radius of a sink candidate.
+/// Velocities are relative to the candidate. The sums are
+/// computed on every rank for its own particles and then added up
+/// on the rank of the candidate.
+#[derive(Clone, Default, Equivalence)]
+struct SinkEnvironment {
+    mass: units::Mass,
+    momentum: VecMomentum,
+    twice_kinetic_energy: Energy,
+    thermal_energy: Energy,
+    /// The momentum in radial direction away from the candidate,
+    /// which is negative for converging flows.
+    radial_momentum: Momentum,
+    /// The number of particles that are denser than the candidate.
+    num_denser: usize,
+}
+
+impl AddAssign for SinkEnvironment {
+    fn add_assign(&mut self, rhs: Self) {
+        self.mass += rhs.mass;
+        self.momentum += rhs.momentum;
+        self.twice_kinetic_energy += rhs.twice_kinetic_energy;
+        self.thermal_energy += rhs.thermal_energy;
+        self.radial_momentum += rhs.radial_momentum;
+        self.num_denser += rhs.num_denser;
+    }
+}
+
+impl SinkEnvironment {
+    fn add_particle(
+        &mut self,
+        box_: &SimulationBox,
+        candidate: &SinkCandidate,
+        id: ParticleId,
+        position: &VecLength,
+        velocity: VecVelocity,
+        mass: units::Mass,
+        thermal_energy: Energy,
+        density: Density,
+    ) {
+        let relative_velocity = velocity - candidate.velocity;
+        self.mass += mass;
+        self.momentum += relative_velocity * mass;
+        self.twice_kinetic_energy += mass * relative_velocity.dot(relative_velocity);
+        self.thermal_energy += thermal_energy;
+        if id == candidate.id {
+            return;
+        }
+        let offset = box_.periodic_distance_vec(position, &candidate.position);
+        if offset.length() > Length::zero() {
+            self.radial_momentum += mass * relative_velocity.dot(offset) / offset.length();
+        }
+        // Ties are broken by the id, so that at most one
+        // of the particles within the accretion radius forms a sink.
+        if (density, id) > (candidate.density, candidate.id) {
+            self.num_denser += 1;
+        }
+    }
+
+    /// Whether the gas is collapsing and gravitationally bound. The
+    /// gravitational energy is approximated by that of a uniform
+    /// sphere with the accretion radius, and the kinetic energy
+    /// is measured in the center of mass frame.
+    fn allows_sink_formation(&self, accretion_radius: Length) -> bool {
+        let gravitational_energy = 0.6 * GRAVITY_CONSTANT * self.mass.squared() / accretion_radius;
+        let kinetic_energy =
+            0.5 * (self.twice_kinetic_energy - self.momentum.length().squared() / self.mass);
+        self.num_denser == 0
+            && self.radial_momentum < Momentum::zero()
+            && self.thermal_energy < 0.5 * gravitational_energy
+            && self.thermal_energy + kinetic_energy < gravitational_energy
+    }
+}
+
+/// The specific energy of a gas particle relative to a sink particle,
+/// which is negative if the particle is bound to the sink.
+fn specific_energy(
+    box_: &SimulationBox,
+    sink: &SinkData,
+    position: &VecLength,
+    velocity: VecVelocity,
+    mass: units::Mass,
+) -> EnergyPerMass {
+    let distance = box_.periodic_distance(position, &sink.position);
+    let relative_velocity = velocity - sink.velocity;
+    0.5 * relative_velocity.dot(relative_velocity)
+        - GRAVITY_CONSTANT * (sink.mass + mass) / distance
+}
+
+fn gather_sinks_system(
+    particles: Particles<(Entity, &Position, &Velocity, &Mass), With<Sink>>,
+    mut sinks: ResMut<Sinks>,
+    mut communicator: ExchangeCommunicator<Identified<SinkData>>,
+    world_rank: Res<WorldRank>,
+) {
+    let local_sinks = || {
+        particles.iter().map(|(entity, position, velocity, mass)| {
+            Identified::new(
+                entity,
+                SinkData {
+                    position: **position,
+                    velocity: **velocity,
+                    mass: **mass,
+                },
+            )
+        })
+    };
+    let mut outgoing = DataByRank::from_communicator(&*communicator);
+    for rank in communicator.other_ranks() {
+        outgoing.insert(rank, local_sinks().collect());
+    }
+    let incoming = communicator.exchange_all(outgoing);
+    **sinks = local_sinks()
+        .map(|sink| (**world_rank, sink))
+        .chain(
+            incoming
+                .into_iter()
+                .flat_map(|(rank, received)| received.into_iter().map(move |sink| (rank, sink))),
+        )
+        .collect();
+}
+
+/// Accretes all local gas particles which are bound to a sink
+/// particle and sends the accreted mass and momentum to the rank
+/// of the sink.
+fn accretion_system(
+    mut commands: Commands,
+    gas: Particles<(&Position, &Velocity, &Mass), (With<Gas>, Without<Sink>)>,
+    mut sink_particles: Particles<(&mut Position, &mut Velocity, &mut Mass), With<Sink>>,
+    sinks: Res<Sinks>,
+    tree: Res<QuadTree>,
+    box_: Res<SimulationBox>,
+    parameters: Res<SinkParticleParameters>,
+    world_rank: Res<WorldRank>,
+    mut communicator: ExchangeCommunicator<Identified<Accretion>>,
+) {
+    // Every particle is accreted by the sink it is most
+    // strongly bound to.
+    let mut accreted: BTreeMap<Entity, (usize, EnergyPerMass)> = BTreeMap::new();
+    for (index, (_, sink)) in sinks.iter().enumerate() {
+        for neighbour in tree.get_particles_in_gather_radius(
+            &box_,
+            &sink.data.position,
+            &parameters.accretion_radius,
+        ) {
+            // Halo particles are accreted on their own rank.
+            let (position, velocity, mass) = match gas.get(neighbour.entity) {
+                Ok(particle) => particle,
+                Err(_) => continue,
+            };
+            let energy = specific_energy(&box_, &sink.data, position, **velocity, **mass);
+            if energy >= EnergyPerMass::zero() {
+                continue;
+            }
+            let best = accreted.entry(neighbour.entity).or_insert((index, energy));
+            if energy < best.1 {
+                *best = (index, energy);
+            }
+        }
+    }
+    let mut accretions: BTreeMap<usize, Accretion> = BTreeMap::new();
+    for (entity, (index, _)) in accreted {
+        let (position, velocity, mass) = gas.get(entity).unwrap();
+        accretions.entry(index).or_default().add_particle(
+            &box_,
+            &sinks[index].1.data.position,
+            position,
+            **velocity,
+            **mass,
+        );
+        commands.entity(entity).despawn();
+    }
+    let mut outgoing = DataByRank::from_communicator(&*communicator);
+    let mut local_accretions: BTreeMap<Entity, Accretion> = BTreeMap::new();
+    for (index, accretion) in accretions {
+        let (rank, sink) = &sinks[index];
+        if *rank == **world_rank {
+            *local_accretions.entry(sink.entity()).or_default() += accretion;
+        } else {
+            outgoing.push(
+                *rank,
+                Identified {
+                    key: sink.key,
+                    data: accretion,
+                },
+            );
+        }
+    }
+    for (_, accretions) in communicator.exchange_all(outgoing) {
+        for accretion in accretions {
+            *local_accretions.entry(accretion.entity()).or_default() += accretion.data;
+        }
+    }
+    for (entity, accretion) in local_accretions {
+        let (mut position, mut velocity, mut mass) = sink_particles.get_mut(entity).unwrap();
+        accretion.apply(&box_, &mut position, &mut velocity, &mut mass);
+    }
+}
+
+/// The sums over the local gas particles within the accretion
+/// radius of the candidate.
+fn local_environment(
+    candidate: &SinkCandidate,
+    gas: &Particles<
+        (
+            &ParticleId,
+            &Position,
+            &Velocity,
+            &Mass,
+            &InternalEnergy,
+            &components::Density,
+        ),
+        With<Gas>,
+    >,
+    tree: &QuadTree,
+    box_: &SimulationBox,
+    accretion_radius: Length,
+) -> SinkEnvironment {
+    let mut environment = SinkEnvironment::default();
+    for neighbour in
+        tree.get_particles_in_gather_radius(box_, &candidate.position, &accretion_radius)
+    {
+        // Halo particles contribute on their own rank.
+        let (id, position, velocity, mass, energy, density) = match gas.get(neighbour.entity) {
+            Ok(particle) => particle,
+            Err(_) => continue,
+        };
+        environment.add_particle(
+            box_, candidate, *id, position, **velocity, **mass, **energy, **density,
+        );
+    }
+    environment
+}
+
+fn form_sinks_system(
+    mut commands: Commands,
+    mut particles: ActiveParticles<
+        (
+            Entity,
+            &ParticleId,
+            &Position,
+            &Velocity,
+            &components::Density,
+        ),
+        With<Gas>,
+    >,
+    gas: Particles<
+        (
+            &ParticleId,
+            &Position,
+            &Velocity,
+            &Mass,
+            &InternalEnergy,
+            &components::Density,
+        ),
+        With<Gas>,
+    >,
+    sinks: Res<Sinks>,
+    tree: Res<QuadTree>,
+    box_: Res<SimulationBox>,
+    parameters: Res<SinkParticleParameters>,
+    mut candidate_communicator: ExchangeCommunicator<SinkCandidate>,
+    mut environment_communicator: ExchangeCommunicator<SinkEnvironment>,
+) {
+    let accretion_radius = parameters.accretion_radius;
+    let candidates: Vec<_> = particles
+        .iter_mut()
+        .filter(|(_, _, position, _, density)| {
+            ***density > parameters.density_threshold
+                && sinks.iter().all(|(_, sink)| {
+                    box_.periodic_distance(position, &sink.data.position) >= accretion_radius
+                })
+        })
+        .map(|(entity, id, position, velocity, density)| {
+            (
+                entity,
+                SinkCandidate {
+                    id: *id,
+                    position: **position,
+                    velocity: **velocity,
+                    density: **density,
+                },
+            )
+        })
+        .collect();
+    let environment = |candidate: &SinkCandidate| {
+        local_environment(candidate, &gas, &tree, &box_, accretion_radius)
+    };
+    let mut outgoing = DataByRank::from_communicator(&*candidate_communicator);
+    for rank in candidate_communicator.other_ranks() {
+        outgoing.insert(
+            rank,
+            candidates
+                .iter()
+                .map(|(_, candidate)| candidate.clone())
+                .collect(),
+        );
+    }
+    let replies: DataByRank<Vec<SinkEnvironment>> = candidate_communicator
+        .exchange_all(outgoing)
+        .into_iter()
+        .map(|(rank, candidates)| (rank, candidates.iter().map(environment).collect()))
+        .collect();
+    let remote_environments = environment_communicator.exchange_all(replies);
+    for (index, (entity, candidate)) in candidates.iter().enumerate() {
+        let mut total = environment(candidate);
+        for (_, environments) in remote_environments.iter() {
+            total += environments[index].clone();
+        }
+        if total.allows_sink_formation(accretion_radius) {
+            commands
+                .entity(*entity)
+                .remove::<GasComponents>()
+                .insert((Species::Sink, Sink));
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "2d"))]
+mod tests {
+    use super::Accretion;
+    use super::SinkCandidate;
+    use super::SinkEnvironment;
+    use crate::components::ParticleId;
+    use crate::prelude::SimulationBox;
+    use crate::units::Density;
+    use crate::units::Energy;
+    use crate::units::Length;
+    use crate::units::Mass;
+    use crate::units::VecLength;
+    use crate::units::VecVelocity;
+
+    #[test]
+    fn accretion_conserves_mass_momentum_and_center_of_mass() {
+        let box_ = SimulationBox::cube_from_side_length(Length::meters(10.0));
+        let mut sink_position = VecLength::meters(1.0, 1.0, 1.0);
+        let mut sink_velocity = VecVelocity::meters_per_second(1.0, 0.0, 0.0);
+        let mut sink_mass = Mass::kilograms(2.0);
+        let mut momentum = sink_velocity * sink_mass;
+        let mut weighted_position = sink_position * sink_mass;
+        let mut accretion = Accretion::default();
+        for (position, velocity, mass) in [
+            (
+                VecLength::meters(2.0, 1.0, 1.0),
+                VecVelocity::meters_per_second(0.0, 1.0, 0.0),
+                Mass::kilograms(1.0),
+            ),
+            (
+                VecLength::meters(1.0, 0.0, 1.0),
+                VecVelocity::meters_per_second(0.0, 0.0, -3.0),
+                Mass::kilograms(0.5),
+            ),
+        ] {
+            momentum += velocity * mass;
+            weighted_position += position * mass;
+            accretion.add_particle(&box_, &sink_position, &position, velocity, mass);
+        }
+        accretion.apply(
+            &box_,
+            &mut sink_position,
+            &mut sink_velocity,
+            &mut sink_mass,
+        );
+        assert_eq!(sink_mass, Mass::kilograms(3.5));
+        assert!(
+            ((sink_velocity * sink_mass - momentum).length() / momentum.length()).value() < 1e-10
+        );
+        let center_of_mass = weighted_position / sink_mass;
+        assert!(
+            ((sink_position - center_of_mass).length() / center_of_mass.length()).value() < 1e-10
+        );
+    }
+
+    /// A candidate surrounded by six particles at half the radius
+    /// which all move radially with the given velocity.
+    fn environment_of_sphere(
+        radius: Length,
+        thermal_energy: Energy,
+        radial_velocity: f64,
+    ) -> SinkEnvironment {
+        let box_ = SimulationBox::cube_from_side_length_centered(radius * 10.0);
+        let candidate = SinkCandidate {
+            id: ParticleId(0),
+            position: VecLength::zero(),
+            velocity: VecVelocity::zero(),
+            density: Density::kilogram_per_cubic_meter(2.0),
+        };
+        let mass = Mass::kilograms(1e10);
+        let mut environment = SinkEnvironment::default();
+        environment.add_particle(
+            &box_,
+            &candidate,
+            candidate.id,
+            &candidate.position,
+            candidate.velocity,
+            mass,
+            thermal_energy,
+            candidate.density,
+        );
+        let directions = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        for (i, [x, y, z]) in directions.into_iter().enumerate() {
+            environment.add_particle(
+                &box_,
+                &candidate,
+                ParticleId(i as u64 + 1),
+                &(VecLength::meters(x, y, z) * 0.5 * radius.in_meters()),
+                VecVelocity::meters_per_second(x, y, z) * radial_velocity,
+                mass,
+                thermal_energy,
+                Density::kilogram_per_cubic_meter(1.0),
+            );
+        }
+        environment
+    }
+
+    #[test]
+    fn only_cold_collapsing_gas_forms_sinks() {
+        let radius = Length::meters(1.0);
+        let cold = Energy::joules(1.0);
+        assert!(environment_of_sphere(radius, cold, -0.1).allows_sink_formation(radius));
+        // Expanding gas
+        assert!(!environment_of_sphere(radius, cold, 0.1).allows_sink_formation(radius));
+        // Hot gas
+        assert!(!environment_of_sphere(radius, Energy::joules(1e11), -0.1)
+            .allows_sink_formation(radius));
+        // Fast, unbound gas
+        assert!(!environment_of_sphere(radius, cold, -1e3).allows_sink_formation(radius));
+    }
+}
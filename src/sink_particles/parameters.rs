@@ -0,0 +1,17 @@
+use derive_custom::raxiom_parameters;
+
+use crate::units::Density;
+use crate::units::Length;
+
+/// Parameters for sink particles. Only needed if the
+/// [SinkParticlePlugin](crate::prelude::SinkParticlePlugin)
+/// is added to the simulation.
+#[raxiom_parameters("sink_particles")]
+pub struct SinkParticleParameters {
+    /// Only gas above this density can form sink particles.
+    pub density_threshold: Density,
+    /// Gas within this radius around a sink particle is accreted
+    /// if it is gravitationally bound to the sink. No new sink
+    /// particles form within this radius around existing sinks.
+    pub accretion_radius: Length,
+}
@@ -0,0 +1,622 @@
+mod parameters;
+
+use std::collections::HashSet;
+use std::f64::consts::PI;
+
+use bevy::prelude::*;
+use mpi::traits::Equivalence;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+pub use self::parameters::StarFormationParameters;
+pub use self::parameters::SupernovaFeedback;
+use crate::communication::CommunicationPlugin;
+use crate::communication::Communicator;
+use crate::communication::DataByRank;
+use crate::communication::ExchangeCommunicator;
+use crate::communication::SizedCommunicator;
+use crate::components;
+use crate::components::Abundances;
+use crate::components::GasComponents;
+use crate::components::InternalEnergy;
+use crate::components::Mass;
+use crate::components::ParticleId;
+use crate::components::Position;
+use crate::components::SmoothingLength;
+use crate::components::Species;
+use crate::components::Star;
+use crate::components::StellarFormationTime;
+use crate::components::Timestep;
+use crate::components::Velocity;
+use crate::hydrodynamics::compute_energy_change_system;
+use crate::hydrodynamics::HydrodynamicsParameters;
+use crate::hydrodynamics::QuadTree;
+use crate::hydrodynamics::SphKernel;
+use crate::io::input::ComponentInput;
+use crate::named::Named;
+use crate::prelude::ActiveParticles;
+use crate::prelude::Float;
+use crate::prelude::Particles;
+use crate::prelude::SimulationBox;
+use crate::simulation::RaxiomPlugin;
+use crate::simulation::Simulation;
+use crate::simulation_plugin::SimulationStages;
+use crate::simulation_plugin::Time;
+use crate::timestep::TimestepParameters;
+use crate::timestep::TimestepState;
+use crate::units;
+use crate::units::Density;
+use crate::units::Energy;
+use crate::units::Length;
+use crate::units::VecLength;
+use crate::units::VecVelocity;
+use crate::units::GRAVITY_CONSTANT;
+
+/// Converts cold, dense gas particles into star particles. Every
+/// active gas particle above the
+/// [density threshold](crate::parameters::StarFormationParameters::density_threshold)
+/// and below the
+/// [temperature threshold](crate::parameters::StarFormationParameters::temperature_threshold)
+/// is converted with a probability such that the expected star
+/// formation rate follows the Schmidt law
+/// rho_* = efficiency * rho / t_ff
+/// where t_ff = sqrt(3 pi / (32 G rho)) is the free-fall time.
+/// The particle keeps its position, velocity, mass and
+/// [ParticleId](crate::components::ParticleId), so no new ids are
+/// needed. Optionally, every newly formed star immediately injects
+/// its supernova energy into the gas within its smoothing length,
+/// either thermally or kinetically, weighted by the SPH kernel.
+/// Gas particles which are converted into stars in the same
+/// timestep do not receive any of the energy.
+/// Requires the
+/// [HydrodynamicsPlugin](crate::prelude::HydrodynamicsPlugin).
+#[derive(Named)]
+pub struct StarFormationPlugin;
+
+impl RaxiomPlugin for StarFormationPlugin {
+    fn build_everywhere(&self, sim: &mut Simulation) {
+        sim.add_parameter_type::<StarFormationParameters>()
+            .add_component::<StellarFormationTime>(ComponentInput::Optional)
+            .add_plugin(CommunicationPlugin::<SupernovaEvent>::exchange())
+            .add_plugin(CommunicationPlugin::<FeedbackNormalization>::default())
+            .insert_resource(SupernovaEvents::default())
+            .insert_resource(FormingStars::default())
+            .add_system_to_stage(
+                SimulationStages::ForceCalculation,
+                form_stars_system.after(compute_energy_change_system),
+            )
+            .add_system_to_stage(
+                SimulationStages::ForceCalculation,
+                supernova_feedback_system.after(form_stars_system),
+            );
+    }
+}
+
+/// The supernova energy of a newly formed star, which is
+/// distributed among the gas particles within `radius` of
+/// `position`.
+#[derive(Clone, Equivalence)]
+struct SupernovaEvent {
+    position: VecLength,
+    radius: Length,
+    energy: Energy,
+}
+
+#[derive(Resource, Default, Deref, DerefMut)]
+struct SupernovaEvents(Vec<SupernovaEvent>);
+
+/// The local gas particles which are converted into stars in the
+/// current timestep. Since the conversion only happens at the end
+/// of the stage, they are still gas particles during the feedback.
+#[derive(Resource, Default, Deref, DerefMut)]
+struct FormingStars(HashSet<Entity>);
+
+/// The sum of m_j W(r_j, radius) over the particles of one rank
+/// which receive a share of the energy of a supernova event.
+#[derive(Clone, Equivalence)]
+struct FeedbackNormalization(Density);
+
+/// A uniformly distributed random number in [0, 1) which only
+/// depends on the seed, the particle id and the time of the current
+/// step, so that the same particles are converted into stars
+/// independently of the domain decomposition, while particles on
+/// smaller timesteps draw a new number in every one of their steps.
+fn random_number(seed: u64, id: ParticleId, time: units::Time) -> Float {
+    let time = time.value_unchecked().to_bits();
+    let mut rng =
+        StdRng::seed_from_u64(seed ^ id.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ time.rotate_left(32));
+    rng.gen()
+}
+
+impl StarFormationParameters {
+    /// The probability that a star forming gas particle of the given
+    /// density is converted into a star during the timestep.
+    fn conversion_probability(&self, density: Density, timestep: units::Time) -> Float {
+        let inverse_free_fall_time = (32.0 * GRAVITY_CONSTANT * density / (3.0 * PI)).sqrt();
+        1.0 - (-(self.efficiency * timestep * inverse_free_fall_time).value()).exp()
+    }
+}
+
+impl SupernovaEvent {
+    /// The weight m_j W(r_j, radius) of a gas particle in the
+    /// distribution of the supernova energy, before normalization.
+    fn weight(
+        &self,
+        kernel: &SphKernel,
+        box_: &SimulationBox,
+        position: &VecLength,
+        mass: units::Mass,
+    ) -> Density {
+        mass * kernel.value(
+            box_.periodic_distance(&self.position, position),
+            self.radius,
+        )
+    }
+}
+
+/// The velocity change along `direction` which increases the
+/// kinetic energy of a particle with the given mass and velocity
+/// by exactly `energy`, i.e. k n with the positive solution k of
+/// 0.5 m (|v + k n|^2 - |v|^2) = energy, where n is the unit
+/// vector along `direction`.
+fn kinetic_kick(
+    energy: Energy,
+    mass: units::Mass,
+    velocity: VecVelocity,
+    direction: VecLength,
+) -> VecVelocity {
+    let direction = direction / direction.length();
+    let radial_velocity = velocity.dot(direction);
+    let kick = (radial_velocity * radial_velocity + 2.0 * energy / mass).sqrt() - radial_velocity;
+    direction * kick
+}
+
+fn form_stars_system(
+    mut commands: Commands,
+    mut particles: ActiveParticles<(
+        Entity,
+        &ParticleId,
+        &Position,
+        &Mass,
+        &InternalEnergy,
+        &components::Density,
+        &SmoothingLength,
+        Option<&Abundances>,
+        &Timestep,
+    )>,
+    time: Res<Time>,
+    state: Res<TimestepState>,
+    timestep_parameters: Res<TimestepParameters>,
+    parameters: Res<StarFormationParameters>,
+    mut events: ResMut<SupernovaEvents>,
+    mut forming_stars: ResMut<FormingStars>,
+) {
+    forming_stars.clear();
+    let time = state.current_time(&timestep_parameters, **time);
+    for (entity, id, pos, mass, energy, density, smoothing_length, abundances, timestep) in
+        particles.iter_mut()
+    {
+        let temperature =
+            (**energy / **mass).to_temperature(Abundances::molecular_weight_of(abundances));
+        if **density < parameters.density_threshold
+            || temperature > parameters.temperature_threshold
+        {
+            continue;
+        }
+        let probability = parameters.conversion_probability(**density, **timestep);
+        if random_number(parameters.seed, *id, time) >= probability {
+            continue;
+        }
+        commands.entity(entity).remove::<GasComponents>().insert((
+            Species::Star,
+            Star,
+            StellarFormationTime(time),
+        ));
+        forming_stars.insert(entity);
+        if let Some(feedback) = &parameters.feedback {
+            events.push(SupernovaEvent {
+                position: **pos,
+                radius: **smoothing_length,
+                energy: feedback.energy_per_mass() * **mass,
+            });
+        }
+    }
+}
+
+/// Sends the supernova events of this rank to all other ranks and
+/// injects the energy of all events into the local gas particles
+/// in their vicinity. The normalization of the kernel weights of
+/// every event is summed over all ranks, so that the total injected
+/// energy is independent of the domain decomposition and gas
+/// particles which form stars in the same timestep (on any rank)
+/// can be left out.
+fn supernova_feedback_system(
+    mut particles: Particles<(&Mass, &mut InternalEnergy, &mut Velocity)>,
+    mut events: ResMut<SupernovaEvents>,
+    mut communicator: ExchangeCommunicator<SupernovaEvent>,
+    mut normalization_communicator: Communicator<FeedbackNormalization>,
+    forming_stars: Res<FormingStars>,
+    tree: Res<QuadTree>,
+    box_: Res<SimulationBox>,
+    parameters: Res<StarFormationParameters>,
+    hydro_parameters: Res<HydrodynamicsParameters>,
+) {
+    let feedback = match &parameters.feedback {
+        Some(feedback) => feedback,
+        None => return,
+    };
+    let mut outgoing = DataByRank::from_communicator(&*communicator);
+    for rank in communicator.other_ranks() {
+        outgoing.insert(rank, events.to_vec());
+    }
+    let mut incoming = communicator.exchange_all(outgoing);
+    incoming.insert(communicator.rank(), events.drain(..).collect());
+    // Order the events by rank, so that the normalizations of
+    // every rank refer to the same events.
+    let events: Vec<_> = communicator
+        .all_ranks()
+        .into_iter()
+        .flat_map(|rank| incoming.remove(&rank).unwrap())
+        .collect();
+    if events.is_empty() {
+        return;
+    }
+    let kernel = &hydro_parameters.kernel;
+    // Halo particles receive their energy on their own rank.
+    let recipients: Vec<Vec<_>> = events
+        .iter()
+        .map(|event| {
+            tree.get_particles_in_gather_radius(&box_, &event.position, &event.radius)
+                .into_iter()
+                .filter(|neighbour| {
+                    !forming_stars.contains(&neighbour.entity)
+                        && particles.contains(neighbour.entity)
+                })
+                .collect()
+        })
+        .collect();
+    let normalizations: Vec<_> = events
+        .iter()
+        .zip(recipients.iter())
+        .map(|(event, recipients)| {
+            FeedbackNormalization(
+                recipients
+                    .iter()
+                    .map(|neighbour| {
+                        let (mass, _, _) = particles.get(neighbour.entity).unwrap();
+                        event.weight(kernel, &box_, &neighbour.pos, **mass)
+                    })
+                    .sum(),
+            )
+        })
+        .collect();
+    let normalizations =
+        sum_normalizations(normalization_communicator.all_gather_vec(&normalizations));
+    for ((event, recipients), normalization) in events
+        .iter()
+        .zip(recipients.into_iter())
+        .zip(normalizations.into_iter())
+    {
+        if normalization == Density::zero() {
+            continue;
+        }
+        for neighbour in recipients {
+            let (mass, mut internal_energy, mut velocity) =
+                particles.get_mut(neighbour.entity).unwrap();
+            let energy = event.energy
+                * (event.weight(kernel, &box_, &neighbour.pos, **mass) / normalization);
+            match feedback {
+                SupernovaFeedback::Thermal { .. } => {
+                    **internal_energy += energy;
+                }
+                SupernovaFeedback::Kinetic { .. } => {
+                    let direction = box_.periodic_distance_vec(&neighbour.pos, &event.position);
+                    **velocity += kinetic_kick(energy, **mass, **velocity, direction);
+                }
+            }
+        }
+    }
+}
+
+/// Sums the normalizations of every event over all ranks, in the
+/// order of the ranks, so that the result is identical on every
+/// rank.
+fn sum_normalizations(mut data: DataByRank<Vec<FeedbackNormalization>>) -> Vec<Density> {
+    let mut results: Vec<_> = data.drain_all().collect();
+    results.sort_by_key(|(rank, _)| *rank);
+    let mut results = results.into_iter().map(|(_, result)| result);
+    let mut sum: Vec<_> = results
+        .next()
+        .unwrap()
+        .into_iter()
+        .map(|normalization| normalization.0)
+        .collect();
+    for result in results {
+        debug_assert_eq!(sum.len(), result.len());
+        for (sum, normalization) in sum.iter_mut().zip(result.into_iter()) {
+            *sum += normalization.0;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::random_number;
+    use super::StarFormationParameters;
+    use crate::components::ParticleId;
+    use crate::timestep::TimestepParameters;
+    use crate::timestep::TimestepState;
+    use crate::units::Density;
+    use crate::units::Temperature;
+    use crate::units::Time;
+
+    fn parameters() -> StarFormationParameters {
+        StarFormationParameters {
+            density_threshold: Density::kilogram_per_cubic_meter(1e-21),
+            temperature_threshold: Temperature::kelvins(1e4),
+            efficiency: 0.01,
+            seed: 0,
+            feedback: None,
+        }
+    }
+
+    #[test]
+    fn conversion_probability_follows_free_fall_time() {
+        let parameters = parameters();
+        let density = Density::kilogram_per_cubic_meter(1e-20);
+        let short = parameters.conversion_probability(density, Time::years(1.0));
+        let long = parameters.conversion_probability(density, Time::years(1e6));
+        let very_long = parameters.conversion_probability(density, Time::years(1e12));
+        assert!(0.0 < short && short < long && long < very_long);
+        assert!((very_long - 1.0).abs() < 1e-10);
+        let denser = parameters
+            .conversion_probability(Density::kilogram_per_cubic_meter(1e-18), Time::years(1e6));
+        assert!(denser > long);
+    }
+
+    #[test]
+    fn random_numbers_are_reproducible() {
+        let time = Time::years(1.0);
+        let x = random_number(0, ParticleId(5), time);
+        assert!((0.0..1.0).contains(&x));
+        assert_eq!(x, random_number(0, ParticleId(5), time));
+        assert_ne!(x, random_number(0, ParticleId(6), time));
+        assert_ne!(x, random_number(1, ParticleId(5), time));
+        assert_ne!(x, random_number(0, ParticleId(5), Time::years(2.0)));
+    }
+
+    #[test]
+    fn fine_timestep_levels_draw_independently_on_every_substep() {
+        let timestep_parameters = TimestepParameters {
+            num_levels: 3,
+            max_timestep: Time::years(1.0),
+            courant_factor: 0.15,
+            acceleration_factor: 0.025,
+        };
+        let probability = 0.1;
+        let num_particles = 10000;
+        let mut state = TimestepState::new(timestep_parameters.num_levels);
+        let mut time = Time::zero();
+        let mut converted = HashSet::new();
+        let mut times = vec![];
+        // One cycle of the highest level, on which the particles
+        // are active in all four steps.
+        for _ in 0..4 {
+            let current_time = state.current_time(&timestep_parameters, time);
+            times.push(current_time);
+            for id in 0..num_particles {
+                if random_number(0, ParticleId(id), current_time) < probability {
+                    converted.insert(id);
+                }
+            }
+            if state.on_synchronization_step() {
+                time += timestep_parameters.max_timestep;
+            }
+            state = state.next();
+        }
+        for (i, time) in times.iter().enumerate() {
+            assert!(
+                ((*time - Time::years(0.25 * i as f64)) / Time::years(1.0))
+                    .value()
+                    .abs()
+                    < 1e-10
+            );
+        }
+        let expected = 1.0 - (1.0 - probability).powi(4);
+        let fraction = converted.len() as f64 / num_particles as f64;
+        assert!((fraction - expected).abs() < 0.02);
+    }
+
+    #[test]
+    #[cfg(not(feature = "2d"))]
+    fn kinetic_kick_injects_exact_energy() {
+        use super::kinetic_kick;
+        use crate::units::Energy;
+        use crate::units::Mass;
+        use crate::units::VecLength;
+        use crate::units::VecVelocity;
+
+        let mass = Mass::kilograms(2.0);
+        let energy = Energy::joules(3.0);
+        let direction = VecLength::meters(1.0, 2.0, 0.0);
+        for velocity in [
+            VecVelocity::zero(),
+            VecVelocity::meters_per_second(1.0, 2.0, 0.0),
+            VecVelocity::meters_per_second(-5.0, -10.0, 0.0),
+            VecVelocity::meters_per_second(0.0, 0.0, 7.0),
+        ] {
+            let kick = kinetic_kick(energy, mass, velocity, direction);
+            let new_velocity = velocity + kick;
+            let injected = 0.5 * mass * (new_velocity.dot(new_velocity) - velocity.dot(velocity));
+            assert!(((injected - energy) / energy).value().abs() < 1e-10);
+            // The kick points away from the star.
+            assert!(kick.dot(direction).value_unchecked() > 0.0);
+        }
+    }
+
+    #[cfg(not(feature = "2d"))]
+    #[cfg(not(feature = "mpi"))]
+    mod feedback {
+        use std::sync::Mutex;
+
+        use bevy::prelude::Entity;
+
+        use super::parameters;
+        use crate::communication::local_sim_building::build_local_communication_sim_with_custom_logic;
+        use crate::communication::CommunicationPlugin;
+        use crate::communication::Rank;
+        use crate::communication::WorldRank;
+        use crate::components;
+        use crate::hydrodynamics::quadtree::LeafData;
+        use crate::hydrodynamics::ArtificialViscosity;
+        use crate::hydrodynamics::HydrodynamicsParameters;
+        use crate::hydrodynamics::InitialGasEnergy;
+        use crate::hydrodynamics::QuadTree;
+        use crate::hydrodynamics::SmoothingLengthIteration;
+        use crate::hydrodynamics::SphKernel;
+        use crate::prelude::LocalParticle;
+        use crate::prelude::SimulationBox;
+        use crate::quadtree::QuadTreeConfig;
+        use crate::simulation::Simulation;
+        use crate::star_formation::supernova_feedback_system;
+        use crate::star_formation::FeedbackNormalization;
+        use crate::star_formation::FormingStars;
+        use crate::star_formation::StarFormationParameters;
+        use crate::star_formation::SupernovaEvent;
+        use crate::star_formation::SupernovaEvents;
+        use crate::star_formation::SupernovaFeedback;
+        use crate::test_utils::run_system_on_sim;
+        use crate::units::Energy;
+        use crate::units::EnergyPerMass;
+        use crate::units::Length;
+        use crate::units::Mass;
+        use crate::units::VecLength;
+        use crate::units::VecVelocity;
+
+        static INJECTED_ENERGY: Mutex<Vec<Energy>> = Mutex::new(Vec::new());
+
+        fn supernova_energy() -> Energy {
+            Energy::joules(10.0)
+        }
+
+        /// The positions of the gas particles on each rank and
+        /// whether they form a star in the current timestep. The
+        /// supernova happens at the origin, where a gas particle on
+        /// rank 0 was just converted. A neighbour on rank 1 is
+        /// converted in the same timestep.
+        fn particles_on_rank(rank: Rank) -> Vec<(VecLength, bool)> {
+            match rank {
+                0 => vec![
+                    (VecLength::zero(), true),
+                    (VecLength::meters(0.5, 0.0, 0.0), false),
+                    (VecLength::meters(0.0, 0.5, 0.0), false),
+                    // Outside of the feedback radius
+                    (VecLength::meters(2.0, 0.0, 0.0), false),
+                ],
+                _ => vec![
+                    (VecLength::meters(-0.5, 0.0, 0.0), false),
+                    (VecLength::meters(0.0, -0.3, 0.0), true),
+                    (VecLength::meters(0.0, 0.0, 0.7), false),
+                ],
+            }
+        }
+
+        fn build_feedback_sim(sim: &mut Simulation) {
+            sim.add_plugin(CommunicationPlugin::<SupernovaEvent>::exchange())
+                .add_plugin(CommunicationPlugin::<FeedbackNormalization>::default());
+        }
+
+        fn inject_feedback(mut sim: Simulation) {
+            let rank = **sim.unwrap_resource::<WorldRank>();
+            let radius = Length::meters(1.0);
+            let box_ = SimulationBox::cube_from_side_length_centered(Length::meters(10.0));
+            let world = sim.world();
+            let mut forming_stars = FormingStars::default();
+            let mut leaves = vec![];
+            for (pos, forms_star) in particles_on_rank(rank) {
+                let entity = world
+                    .spawn((
+                        components::Position(pos),
+                        components::Mass(Mass::kilograms(1.0)),
+                        components::InternalEnergy(Energy::zero()),
+                        components::Velocity(VecVelocity::zero()),
+                        LocalParticle,
+                    ))
+                    .id();
+                if forms_star {
+                    forming_stars.insert(entity);
+                }
+                leaves.push(LeafData {
+                    entity,
+                    pos,
+                    smoothing_length: radius,
+                    velocity: VecVelocity::zero(),
+                });
+            }
+            let events = if rank == 0 {
+                vec![SupernovaEvent {
+                    position: VecLength::zero(),
+                    radius,
+                    energy: supernova_energy(),
+                }]
+            } else {
+                vec![]
+            };
+            world.insert_resource(QuadTree::new(&QuadTreeConfig::default(), leaves, &box_));
+            world.insert_resource(box_);
+            world.insert_resource(SupernovaEvents(events));
+            world.insert_resource(forming_stars);
+            world.insert_resource(StarFormationParameters {
+                feedback: Some(SupernovaFeedback::Thermal {
+                    energy_per_mass: EnergyPerMass::joules_per_kilogram(1.0),
+                }),
+                ..parameters()
+            });
+            world.insert_resource(HydrodynamicsParameters {
+                num_smoothing_neighbours: 32,
+                min_smoothing_length: Length::meters(1e-5),
+                max_smoothing_length: Length::meters(1e5),
+                initial_gas_energy: InitialGasEnergy::Explicit,
+                tree: QuadTreeConfig::default(),
+                viscosity: ArtificialViscosity::default(),
+                smoothing_length_iteration: SmoothingLengthIteration::default(),
+                kernel: SphKernel::default(),
+            });
+            run_system_on_sim(&mut sim, supernova_feedback_system);
+            let world = sim.world();
+            let forming_stars: Vec<Entity> =
+                world.resource::<FormingStars>().iter().copied().collect();
+            let mut injected = Energy::zero();
+            for (entity, energy) in world
+                .query::<(Entity, &components::InternalEnergy)>()
+                .iter(world)
+            {
+                if forming_stars.contains(&entity) {
+                    assert_eq!(**energy, Energy::zero());
+                }
+                injected += **energy;
+            }
+            INJECTED_ENERGY.lock().unwrap().push(injected);
+        }
+
+        #[test]
+        fn feedback_across_ranks_injects_total_energy() {
+            build_local_communication_sim_with_custom_logic(build_feedback_sim, inject_feedback, 2);
+            let injected = INJECTED_ENERGY.lock().unwrap();
+            assert_eq!(injected.len(), 2);
+            // Both ranks receive a share of the energy.
+            assert!(injected.iter().all(|energy| *energy > Energy::zero()));
+            let total: Energy = injected.iter().copied().sum();
+            assert!(
+                ((total - supernova_energy()) / supernova_energy())
+                    .value()
+                    .abs()
+                    < 1e-10
+            );
+        }
+    }
+}
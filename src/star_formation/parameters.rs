@@ -0,0 +1,76 @@
+use derive_custom::raxiom_parameters;
+
+use crate::prelude::Float;
+use crate::units::Density;
+use crate::units::EnergyPerMass;
+use crate::units::Temperature;
+
+/// Parameters for star formation. Only needed if the
+/// [StarFormationPlugin](crate::prelude::StarFormationPlugin)
+/// is added to the simulation.
+#[raxiom_parameters("star_formation")]
+pub struct StarFormationParameters {
+    /// Only gas above this density forms stars.
+    pub density_threshold: Density,
+    /// Only gas below this temperature forms stars.
+    #[serde(default = "default_temperature_threshold")]
+    pub temperature_threshold: Temperature,
+    /// The fraction of the star forming gas which is converted
+    /// into stars per free-fall time.
+    #[serde(default = "default_efficiency")]
+    pub efficiency: Float,
+    /// The seed of the random numbers which decide which gas
+    /// particles are converted into stars. Runs with the same
+    /// seed form the same stars, independent of the number
+    /// of ranks.
+    #[serde(default)]
+    pub seed: u64,
+    /// The supernova feedback of newly formed stars. See
+    /// [SupernovaFeedback](crate::parameters::SupernovaFeedback)
+    #[serde(default)]
+    pub feedback: Option<SupernovaFeedback>,
+}
+
+/// How the supernova energy of newly formed stars is
+/// injected into the surrounding gas.
+#[raxiom_parameters]
+#[serde(tag = "type")]
+pub enum SupernovaFeedback {
+    /// Increase the internal energy of the neighbouring gas.
+    Thermal {
+        /// The supernova energy released per unit stellar mass.
+        #[serde(default = "default_energy_per_mass")]
+        energy_per_mass: EnergyPerMass,
+    },
+    /// Kick the neighbouring gas radially away from the star,
+    /// such that the kinetic energy of every particle increases
+    /// by exactly its share of the supernova energy.
+    Kinetic {
+        /// The supernova energy released per unit stellar mass.
+        #[serde(default = "default_energy_per_mass")]
+        energy_per_mass: EnergyPerMass,
+    },
+}
+
+impl SupernovaFeedback {
+    pub fn energy_per_mass(&self) -> EnergyPerMass {
+        match self {
+            Self::Thermal { energy_per_mass } | Self::Kinetic { energy_per_mass } => {
+                *energy_per_mass
+            }
+        }
+    }
+}
+
+fn default_temperature_threshold() -> Temperature {
+    Temperature::kelvins(1e4)
+}
+
+fn default_efficiency() -> Float {
+    0.01
+}
+
+/// 10^51 erg per 100 solar masses of formed stars.
+fn default_energy_per_mass() -> EnergyPerMass {
+    EnergyPerMass::joules_per_kilogram(5.03e11)
+}
@@ -1,84 +1,85 @@
+use bevy::ecs::query::QueryEntityError;
 use bevy::ecs::query::QueryItem;
 use bevy::ecs::query::ROQueryItem;
 use bevy::ecs::query::ReadOnlyWorldQuery;
 use bevy::ecs::query::WorldQuery;
 use bevy::ecs::system::SystemParam;
+use bevy::prelude::Entity;
 use bevy::prelude::Res;
 use bevy::prelude::Resource;
-use bevy::utils::HashSet;
 
-use super::time_bins::TimeBins;
-use super::TimestepCriterion;
+use super::TimestepParameters;
 use super::TimestepState;
+use crate::components::Timestep;
 use crate::prelude::Particles;
 
+/// A query for all particles which are active in the current step,
+/// i.e. all particles that are at the end of their timestep. Since
+/// the time bin of a particle is determined by its [Timestep], this
+/// stays correct if particles are exchanged between ranks or spawned
+/// during the simulation. The query Q may not contain mutable access
+/// to the [Timestep].
 #[derive(SystemParam, Resource)]
-pub struct ActiveParticles<'w, 's, T, Q, F = ()>
+pub struct ActiveParticles<'w, 's, Q, F = ()>
 where
     Q: WorldQuery + 'static,
     F: ReadOnlyWorldQuery + 'static,
-    T: Sync + Send + 'static + TimestepCriterion,
 {
-    query: Particles<'w, 's, Q, (F, T::Filter)>,
-    timebins: Res<'w, TimeBins<T>>,
+    query: Particles<'w, 's, (Q, &'static Timestep), F>,
+    parameters: Res<'w, TimestepParameters>,
     active_timestep: Res<'w, TimestepState>,
 }
 
-impl<'w, 's, T, Q, F> ActiveParticles<'w, 's, T, Q, F>
+impl<'w, 's, Q, F> ActiveParticles<'w, 's, Q, F>
 where
     Q: ReadOnlyWorldQuery + 'static,
     F: ReadOnlyWorldQuery + 'static,
-    T: Sync + Send + 'static + TimestepCriterion,
 {
     pub fn iter(&'w self) -> impl Iterator<Item = ROQueryItem<Q>> + 'w {
-        self.timebins
-            .iter_active(&self.active_timestep)
-            .flat_map(|bin| bin.iter())
-            .map(move |x| self.query.get(*x).unwrap())
+        self.query
+            .iter()
+            .filter(|(_, timestep)| {
+                self.active_timestep
+                    .is_active(&self.parameters, ***timestep)
+            })
+            .map(|(item, _)| item)
     }
 }
 
-impl<'w, 's, T, Q: WorldQuery, F: ReadOnlyWorldQuery> ActiveParticles<'w, 's, T, Q, F>
-where
-    T: Sync + Send + 'static + TimestepCriterion,
-{
+impl<'w, 's, Q: WorldQuery, F: ReadOnlyWorldQuery> ActiveParticles<'w, 's, Q, F> {
     pub fn iter_mut(&mut self) -> impl Iterator<Item = QueryItem<'_, Q>> {
-        // Some notes on this pretty bad implementation:
-        // From the outside, this is actually really simple, I want to write something like
-        //
-        // for entity in timebins[**active_timestep].iter() {
-        //     let mut item = query.get_mut(*entity).unwrap();
-        // }
-        //
-        // As demonstrated by iter(), this doesn't have to be that
-        // hard. However, writing an iterator for the mutable version
-        // of this was impossible to me.  I am not proud of this
-        // solution at all. It allocates unnecessarily and is probably
-        // a lot slower than a proper version of this would be.
-        // approximately 2 hours trying to get the lifetimes right by
-        // writing a custom iterator struct to do this but didn't
-        // manage.  If this ever becomes a bottleneck, I will revisit
-        // this.
-        //
-        // SAFETY: We check for entity uniqueness here, so that
-        // get_unchecked is safe. This is something that could
-        // possibly be removed if we ensure that the entities in the
-        // timebins are always unique
-        //
-        let entities: Vec<_> = self
-            .timebins
-            .iter_active(&self.active_timestep)
-            .flat_map(|bin| bin.iter())
-            .collect();
-        let num = entities.len();
-        let entities = entities.into_iter().collect::<HashSet<_>>();
-        assert_eq!(
-            num,
-            entities.len(),
-            "Some entities are in multiple timebins!"
-        );
-        entities
-            .into_iter()
-            .filter_map(|x| unsafe { self.query.get_unchecked(*x).ok() })
+        let parameters = &self.parameters;
+        let active_timestep = &self.active_timestep;
+        self.query
+            .iter_mut()
+            .filter(move |(_, timestep)| active_timestep.is_active(parameters, ***timestep))
+            .map(|(item, _)| item)
+    }
+
+    /// Runs f on all active particles in parallel.
+    pub fn par_for_each_mut<'a>(
+        &'a mut self,
+        batch_size: usize,
+        f: impl Fn(QueryItem<'a, Q>) + Send + Sync + Clone,
+    ) {
+        let parameters = &*self.parameters;
+        let active_timestep = &*self.active_timestep;
+        self.query
+            .par_for_each_mut(batch_size, move |(item, timestep)| {
+                if active_timestep.is_active(parameters, **timestep) {
+                    f(item)
+                }
+            });
+    }
+
+    /// Returns the query item of the given entity, if it is
+    /// an active particle.
+    pub fn get_mut(&mut self, entity: Entity) -> Result<QueryItem<'_, Q>, QueryEntityError> {
+        let (item, timestep) = self.query.get_mut(entity)?;
+        if self.active_timestep.is_active(&self.parameters, **timestep) {
+            Ok(item)
+        } else {
+            Err(QueryEntityError::QueryDoesNotMatch(entity))
+        }
     }
 }
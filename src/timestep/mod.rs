@@ -1,7 +1,6 @@
 mod active_particles;
 mod constant_timestep;
 mod parameters;
-mod time_bins;
 
 use std::marker::PhantomData;
 
@@ -10,26 +9,37 @@ use bevy::ecs::query::QueryItem;
 use bevy::ecs::query::ReadOnlyWorldQuery;
 use bevy::ecs::query::WorldQuery;
 use bevy::prelude::Commands;
+use bevy::prelude::Component;
 use bevy::prelude::CoreStage;
+use bevy::prelude::Deref;
+use bevy::prelude::DerefMut;
 use bevy::prelude::Entity;
+use bevy::prelude::IntoSystemDescriptor;
 use bevy::prelude::Res;
 use bevy::prelude::ResMut;
 use bevy::prelude::Resource;
 use bevy::prelude::StartupStage;
 use bevy::prelude::Without;
+use hdf5::H5Type;
+use mpi::traits::Equivalence;
 
 pub use self::constant_timestep::ConstantTimestep;
 pub use self::parameters::TimestepParameters;
-use self::time_bins::TimeBins;
 use crate::components::Timestep;
+use crate::io::restart::RestartAttribute;
+use crate::io::restart::RestartPlugin;
+use crate::io::restart::ToRestartAttribute;
 use crate::named::Named;
 use crate::prelude::Float;
 use crate::prelude::Particles;
 use crate::prelude::Simulation;
 use crate::simulation::RaxiomPlugin;
+use crate::simulation_plugin::SimulationStages;
 use crate::units::Time;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Resource)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Resource, H5Type, Named)]
+#[name = "timestep_state"]
+#[repr(C)]
 pub struct TimestepState {
     /// The currently active timestep. Level 0
     /// is the highest possible timestep T_0 and level i
@@ -39,14 +49,14 @@ pub struct TimestepState {
 }
 
 impl TimestepState {
-    fn new(max_num_bins: usize) -> Self {
+    pub(crate) fn new(max_num_bins: usize) -> Self {
         Self {
             max_num_bins,
             count: 0,
         }
     }
 
-    fn next(self) -> Self {
+    pub(crate) fn next(self) -> Self {
         let max_count = 2usize.pow(self.max_num_bins as u32 - 1);
         Self {
             count: (self.count + 1).rem_euclid(max_count),
@@ -63,14 +73,58 @@ impl TimestepState {
     pub fn on_synchronization_step(&self) -> bool {
         self.count == 0
     }
+
+    /// The time at the beginning of the current step, given the
+    /// simulation time. The simulation time is only advanced by
+    /// T_0 on synchronization steps, so in all other steps of a
+    /// cycle it is already the time at the end of the cycle.
+    pub fn current_time(&self, parameters: &TimestepParameters, time: Time) -> Time {
+        if self.on_synchronization_step() {
+            time
+        } else {
+            time - parameters.max_timestep + parameters.smallest_timestep() * self.count as Float
+        }
+    }
+
+    /// Whether a particle with the given timestep is at the
+    /// beginning (and thus also the end) of one of its timesteps
+    /// in the current step. Particles which have not been assigned
+    /// a timestep yet are always active.
+    pub fn is_active(&self, parameters: &TimestepParameters, timestep: Time) -> bool {
+        timestep == Time::zero() || self.is_active_bin(parameters.level(timestep))
+    }
+}
+
+impl ToRestartAttribute for TimestepState {
+    type Output = Self;
+
+    fn to_restart_value(&self) -> Self {
+        *self
+    }
+
+    fn from_restart_value(value: Self) -> Self {
+        value
+    }
 }
 
+/// A criterion which determines the desired timestep of every
+/// particle matching its query and filter. If multiple criteria
+/// apply to a particle, its timestep is the minimum of their
+/// desired timesteps.
 pub trait TimestepCriterion: Sync + Send {
     type Query: ReadOnlyWorldQuery + WorldQuery;
     type Filter: ReadOnlyWorldQuery + WorldQuery;
     fn timestep(parameters: &TimestepParameters, query_item: QueryItem<Self::Query>) -> Time;
 }
 
+/// The smallest timestep desired by any of the criteria that apply
+/// to the particle. Reset to the maximum timestep once the new
+/// timestep of the particle has been determined.
+#[derive(H5Type, Component, Debug, Clone, Equivalence, Deref, DerefMut, Named)]
+#[name = "desired_timestep"]
+#[repr(transparent)]
+pub struct DesiredTimestep(pub Time);
+
 #[derive(Named)]
 pub struct TimestepPlugin<T> {
     _marker: PhantomData<T>,
@@ -92,20 +146,31 @@ impl<T: TimestepCriterion + 'static> RaxiomPlugin for TimestepPlugin<T> {
     fn build_once_everywhere(&self, sim: &mut Simulation) {
         let parameters = sim
             .add_derived_component::<Timestep>()
+            .add_component_no_io::<DesiredTimestep>()
             .add_parameter_type_and_get_result::<TimestepParameters>()
             .clone();
         sim.insert_resource(TimestepState::new(parameters.num_levels))
-            .add_system_to_stage(CoreStage::PostUpdate, timestep_transition_system);
+            .add_plugin(RestartPlugin::<RestartAttribute<TimestepState>>::default())
+            .add_system_to_stage(CoreStage::PostUpdate, timestep_transition_system)
+            .add_system_to_stage(
+                SimulationStages::Integration,
+                determine_timesteps_system
+                    .after("closing_kick")
+                    .before("opening_kick"),
+            );
     }
 
     fn build_everywhere(&self, sim: &mut Simulation) {
-        let num_levels = sim.unwrap_resource::<TimestepParameters>().num_levels;
         sim.add_startup_system_to_stage(
             StartupStage::PostStartup,
             add_timestep_component_system::<T>,
         )
-        .add_system_to_stage(CoreStage::PreUpdate, determine_timesteps_system::<T>)
-        .insert_resource(TimeBins::<T>::new(num_levels));
+        .add_system_to_stage(
+            SimulationStages::Integration,
+            desired_timestep_system::<T>
+                .after("closing_kick")
+                .before(determine_timesteps_system),
+        );
     }
 }
 
@@ -117,27 +182,52 @@ fn timestep_transition_system(mut state: ResMut<TimestepState>) {
 fn add_timestep_component_system<T: TimestepCriterion + 'static>(
     mut commands: Commands,
     particles: Particles<(Entity, T::Query), (T::Filter, Without<Timestep>)>,
+    parameters: Res<TimestepParameters>,
 ) {
     for (entity, _) in particles.iter() {
-        commands.entity(entity).insert(Timestep(Time::zero()));
+        commands.entity(entity).insert((
+            Timestep(Time::zero()),
+            DesiredTimestep(parameters.max_timestep),
+        ));
     }
 }
 
-fn determine_timesteps_system<T: TimestepCriterion + 'static>(
+/// Lowers the desired timestep of all active particles to the
+/// timestep required by the criterion T.
+pub(crate) fn desired_timestep_system<T: TimestepCriterion + 'static>(
     parameters: Res<TimestepParameters>,
-    mut bins: ResMut<TimeBins<T>>,
-    mut particles: Particles<(Entity, &mut Timestep, T::Query), T::Filter>,
+    state: Res<TimestepState>,
+    mut particles: Particles<(&mut DesiredTimestep, &Timestep, T::Query), T::Filter>,
 ) {
-    bins.reset();
-    for (entity, mut timestep, data) in particles.iter_mut() {
-        let desired_timestep = T::timestep(&parameters, data);
-        let timestep_ratio = (parameters.max_timestep / desired_timestep).value();
-        // bin = log2(T_0 / T) clamped to [0, num_levels)
-        let bin_level = timestep_ratio
-            .log2()
-            .clamp(0.0, (parameters.num_levels - 1) as Float) as usize;
-        **timestep = parameters.max_timestep / (2u32.pow(bin_level as u32) as Float);
-        bins.insert(bin_level, entity);
+    for (mut desired_timestep, timestep, data) in particles.iter_mut() {
+        if !state.is_active(&parameters, **timestep) {
+            continue;
+        }
+        let timestep = T::timestep(&parameters, data);
+        **desired_timestep = (**desired_timestep).min(timestep);
+    }
+}
+
+/// Assigns new timesteps to all active particles. In order to keep
+/// the hierarchy of time bins synchronized, a particle can only move
+/// to a larger timestep if the current step is also the beginning of
+/// one of the larger timesteps. Moving to smaller timesteps is always
+/// possible.
+pub(crate) fn determine_timesteps_system(
+    parameters: Res<TimestepParameters>,
+    state: Res<TimestepState>,
+    mut particles: Particles<(&mut Timestep, &mut DesiredTimestep)>,
+) {
+    for (mut timestep, mut desired_timestep) in particles.iter_mut() {
+        if !state.is_active(&parameters, **timestep) {
+            continue;
+        }
+        let desired_level = parameters.level_for_desired_timestep(**desired_timestep);
+        let level = (desired_level..parameters.num_levels)
+            .find(|level| state.is_active_bin(*level))
+            .unwrap();
+        **timestep = parameters.timestep_at_level(level);
+        **desired_timestep = parameters.max_timestep;
     }
 }
 
@@ -147,6 +237,7 @@ mod tests {
     use bevy::prelude::Component;
     use bevy::prelude::IntoSystemDescriptor;
 
+    use super::determine_timesteps_system;
     use super::parameters::TimestepParameters;
     use super::TimestepCriterion;
     use super::TimestepPlugin;
@@ -155,6 +246,8 @@ mod tests {
     use crate::prelude::LocalParticle;
     use crate::prelude::Particles;
     use crate::prelude::Simulation;
+    use crate::prelude::SimulationStages;
+    use crate::stages::SimulationStagesPlugin;
     use crate::test_utils::assert_is_close;
     use crate::test_utils::run_system_on_sim;
     use crate::timestep::active_particles::ActiveParticles;
@@ -162,7 +255,7 @@ mod tests {
     use crate::units::Time;
 
     #[derive(Component)]
-    struct DesiredTimestep(Time);
+    struct TargetTimestep(Time);
 
     #[derive(Component, Default)]
     struct Counter {
@@ -174,9 +267,9 @@ mod tests {
     impl TimestepCriterion for DumbCriterion {
         type Filter = ();
 
-        type Query = &'static DesiredTimestep;
+        type Query = &'static TargetTimestep;
 
-        fn timestep(_parameters: &TimestepParameters, query_item: &DesiredTimestep) -> Time {
+        fn timestep(_parameters: &TimestepParameters, query_item: &TargetTimestep) -> Time {
             query_item.0
         }
     }
@@ -189,25 +282,23 @@ mod tests {
             let spawn = |commands: &mut Commands, factor: usize| {
                 // Add an epsilon to make sure we slip into the correct bin
                 let epsilon = Time::seconds(1e-5);
-                let timestep = BASE_TIMESTEP / (factor as Float) - epsilon;
-                commands.spawn((DesiredTimestep(timestep), Counter::default(), LocalParticle));
+                let timestep = BASE_TIMESTEP / (factor as Float) + epsilon;
+                commands.spawn((TargetTimestep(timestep), Counter::default(), LocalParticle));
             };
             spawn(&mut commands, 1);
             spawn(&mut commands, 2);
             spawn(&mut commands, 4);
             spawn(&mut commands, 8);
         }
-        fn count_timesteps_system(
-            mut particles: ActiveParticles<DumbCriterion, (&mut Counter, &Timestep)>,
-        ) {
+        fn count_timesteps_system(mut particles: ActiveParticles<(&mut Counter, &Timestep)>) {
             for (mut counter, timestep) in particles.iter_mut() {
                 counter.counter += 1;
                 counter.total_time += **timestep;
             }
         }
-        fn check_counters_system(particles: Particles<(&Counter, &DesiredTimestep)>) {
+        fn check_counters_system(particles: Particles<(&Counter, &TargetTimestep)>) {
             for (counter, timestep) in particles.iter() {
-                let desired_num_updates = (BASE_TIMESTEP / timestep.0).value() as usize;
+                let desired_num_updates = (BASE_TIMESTEP / timestep.0).value().round() as usize;
                 assert_eq!(desired_num_updates, counter.counter);
                 assert_is_close(counter.total_time, BASE_TIMESTEP);
             }
@@ -216,14 +307,58 @@ mod tests {
         sim.add_parameters_explicitly(TimestepParameters {
             num_levels: 4,
             max_timestep: Time::seconds(1.0),
+            courant_factor: 0.15,
+            acceleration_factor: 0.025,
         });
+        sim.add_plugin(SimulationStagesPlugin);
         sim.add_plugin(TimestepPlugin::<DumbCriterion>::default());
         sim.add_startup_system(
             spawn_particles_system.before(add_timestep_component_system::<DumbCriterion>),
         );
-        sim.add_system(count_timesteps_system);
+        sim.add_system_to_stage(
+            SimulationStages::Integration,
+            count_timesteps_system.after(determine_timesteps_system),
+        );
         // Run one full timestep
         sim.timestep();
         run_system_on_sim(&mut sim, check_counters_system);
     }
+
+    struct QuarterCriterion;
+    impl TimestepCriterion for QuarterCriterion {
+        type Filter = ();
+
+        type Query = ();
+
+        fn timestep(parameters: &TimestepParameters, _query_item: ()) -> Time {
+            parameters.max_timestep / 4.0
+        }
+    }
+
+    #[test]
+    fn timestep_is_minimum_of_criteria() {
+        let mut sim = Simulation::test();
+        fn spawn_particles_system(mut commands: Commands) {
+            commands.spawn((TargetTimestep(Time::seconds(1.0)), LocalParticle));
+            commands.spawn((TargetTimestep(Time::seconds(0.125)), LocalParticle));
+        }
+        fn check_timesteps_system(particles: Particles<(&Timestep, &TargetTimestep)>) {
+            for (timestep, target) in particles.iter() {
+                assert_is_close(**timestep, target.0.min(Time::seconds(0.25)));
+            }
+        }
+        sim.add_parameter_file_contents("".into());
+        sim.add_parameters_explicitly(TimestepParameters {
+            num_levels: 4,
+            max_timestep: Time::seconds(1.0),
+            courant_factor: 0.15,
+            acceleration_factor: 0.025,
+        });
+        sim.add_plugin(SimulationStagesPlugin);
+        sim.add_plugin(TimestepPlugin::<DumbCriterion>::default());
+        sim.add_plugin(TimestepPlugin::<QuarterCriterion>::default());
+        sim.add_startup_system(spawn_particles_system);
+        sim.timestep();
+        run_system_on_sim(&mut sim, check_timesteps_system);
+    }
 }
@@ -1,5 +1,6 @@
 use derive_custom::raxiom_parameters;
 
+use crate::prelude::Float;
 use crate::units::Time;
 
 #[raxiom_parameters("timestep")]
@@ -7,8 +8,56 @@ pub struct TimestepParameters {
     #[serde(default = "default_num_levels")]
     pub num_levels: usize,
     pub max_timestep: Time,
+    /// The Courant factor C of the hydrodynamical timestep
+    /// criterion dt = C h / v_sig, where v_sig is the largest
+    /// signal velocity between the particle and its neighbours.
+    #[serde(default = "default_courant_factor")]
+    pub courant_factor: Float,
+    /// The accuracy parameter eta of the acceleration based timestep
+    /// criterion dt = sqrt(2 eta epsilon / |a|), where epsilon is the
+    /// gravitational softening length.
+    #[serde(default = "default_acceleration_factor")]
+    pub acceleration_factor: Float,
+}
+
+impl TimestepParameters {
+    /// The timestep T_i = T_0 2^{-i} of level i.
+    pub fn timestep_at_level(&self, level: usize) -> Time {
+        self.max_timestep / (2u32.pow(level as u32) as Float)
+    }
+
+    /// The timestep of the highest level, by which all
+    /// particles are drifted in every step.
+    pub fn smallest_timestep(&self) -> Time {
+        self.timestep_at_level(self.num_levels - 1)
+    }
+
+    /// The level of a particle with the given timestep, which has to
+    /// be one of the timesteps T_i = T_0 2^{-i}.
+    pub fn level(&self, timestep: Time) -> usize {
+        (self.max_timestep / timestep).value().log2().round() as usize
+    }
+
+    /// The level of the largest timestep T_i which is smaller than
+    /// the desired timestep, clamped to the available levels.
+    pub fn level_for_desired_timestep(&self, desired_timestep: Time) -> usize {
+        // bin = log2(T_0 / T) clamped to [0, num_levels)
+        let timestep_ratio = (self.max_timestep / desired_timestep).value();
+        timestep_ratio
+            .log2()
+            .ceil()
+            .clamp(0.0, (self.num_levels - 1) as Float) as usize
+    }
 }
 
 fn default_num_levels() -> usize {
     1
 }
+
+fn default_courant_factor() -> Float {
+    0.15
+}
+
+fn default_acceleration_factor() -> Float {
+    0.025
+}
@@ -24,8 +24,10 @@ unit_system!(
     LENGTH, Length, length: 1,
     {
         meters, 1.0, "m",
+        centimeters, 0.01, "cm",
         kilometers, 1000.0, "km",
-        astronomical_units, 1.4959787e11, "au"
+        astronomical_units, 1.4959787e11, "au",
+        kiloparsecs, 3.0856776e19, "kpc"
     },
     TIME, Time, time: 1,
     {
@@ -35,11 +37,13 @@ unit_system!(
     VELOCITY, Velocity, length: 1, time: -1,
     {
         meters_per_second, 1.0, "m/s",
+        centimeters_per_second, 0.01, "cm/s",
         kilometers_per_second, 1000.0, "km/s",
         astronomical_units_per_day, 1731460.0, "au/d"
     },
     MASS, Mass, mass: 1,
     {
+        grams, 0.001, "g",
         kilograms, 1.0, "kg",
         earth, 5.9722e24, "Mearth",
         solar, 1.988477e30, "Msol"
@@ -100,6 +104,9 @@ unit_system!(
     LENGTHMASS, LengthMass, mass: 1, length: 1,
     {
     },
+    MASSPERLENGTH, MassPerLength, mass: 1, length: -1,
+    {
+    },
     INVERSE_TIME, InverseTime, time: -1,
     {
     },
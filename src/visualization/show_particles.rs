@@ -9,24 +9,20 @@ use super::DrawCircle;
 use super::RColor;
 use super::VisualizationParameters;
 use super::VisualizationStage;
+use crate::components::Abundances;
 use crate::components::InternalEnergy;
 use crate::components::Mass;
 use crate::components::Position;
 use crate::components::Pressure;
 use crate::named::Named;
-use crate::prelude::Float;
 use crate::prelude::Particles;
 use crate::prelude::Simulation;
 use crate::prelude::WorldRank;
 use crate::simulation::RaxiomPlugin;
 use crate::units;
-use crate::units::Dimensionless;
 use crate::units::EnergyPerMass;
 use crate::units::Temperature;
 
-// The molecular weight that this plugin just blindly assumes.
-const MOLECULAR_WEIGHT: Float = 4.0;
-
 /// Which quantity is shown via the particle color.
 #[derive(Default)]
 #[raxiom_parameters]
@@ -83,17 +79,22 @@ impl RaxiomPlugin for ShowParticlesPlugin {
     }
 }
 
-fn temperature_color_map(e: EnergyPerMass, scale: Temperature) -> RColor {
-    RColor::reds((e.to_temperature(Dimensionless::dimensionless(MOLECULAR_WEIGHT)) / scale).value())
+fn temperature_color_map(
+    e: EnergyPerMass,
+    abundances: Option<&Abundances>,
+    scale: Temperature,
+) -> RColor {
+    let molecular_weight = Abundances::molecular_weight_of(abundances);
+    RColor::reds((e.to_temperature(molecular_weight) / scale).value())
 }
 
 fn color_particles_by_temperature_system(
     visualization_parameters: Res<VisualizationParameters>,
-    mut particles: Particles<(&mut DrawCircle, &InternalEnergy, &Mass)>,
+    mut particles: Particles<(&mut DrawCircle, &InternalEnergy, &Mass, Option<&Abundances>)>,
 ) {
     if let ColorMap::Temperature { scale } = visualization_parameters.color_map {
-        for (mut circle, internal_energy, mass) in particles.iter_mut() {
-            circle.color = temperature_color_map(**internal_energy / **mass, scale);
+        for (mut circle, internal_energy, mass, abundances) in particles.iter_mut() {
+            circle.color = temperature_color_map(**internal_energy / **mass, abundances, scale);
         }
     }
 }